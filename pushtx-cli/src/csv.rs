@@ -0,0 +1,138 @@
+//! Bulk input and per-row results in CSV form.
+//!
+//! Input rows are `label,hex`. A header row whose second column is `hex` is skipped. The output
+//! has one row per input row: `label,txid,status,acks,reason`.
+//!
+//! The report of `--report-format csv` has one row per transaction instead:
+//! `txid,wtxid,status,acks,first_ack_secs,reject_reason`.
+
+use std::io::Write;
+use std::path::Path;
+
+use pushtx::{Report, Transaction, Txid};
+
+/// A labeled transaction from the input file.
+pub struct Row {
+    pub label: String,
+    pub txid: Txid,
+}
+
+/// Reads the input file into labeled rows and the transactions to broadcast.
+pub fn read(path: &Path) -> Result<(Vec<Row>, Vec<Transaction>), super::Error> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut rows = Vec::new();
+    let mut txs = Vec::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        // hex never contains commas, so the label is everything up to the last one
+        let (label, hex) = line
+            .rsplit_once(',')
+            .ok_or(super::Error::Csv { line: i + 1 })?;
+        if i == 0 && hex.trim().eq_ignore_ascii_case("hex") {
+            continue;
+        }
+        let tx = Transaction::from_hex(hex.trim())?;
+        rows.push(Row {
+            label: unquote(label.trim()),
+            txid: tx.txid(),
+        });
+        txs.push(tx);
+    }
+
+    Ok((rows, txs))
+}
+
+/// Writes the outcome of every row.
+pub fn write(path: &Path, rows: &[Row], report: &Report) -> std::io::Result<()> {
+    let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(out, "label,txid,status,acks,reason")?;
+
+    for row in rows {
+        let acks = report
+            .latencies
+            .get(&row.txid)
+            .map_or(0, |latency| latency.acks.len());
+        let status = status(report, &row.txid);
+        let reason = match status {
+            "rejected" => report.rejects.get(&row.txid).map(ToString::to_string),
+            "unknown" => report.diagnoses.get(&row.txid).map(ToString::to_string),
+            _ => None,
+        };
+        let reason = reason.unwrap_or_default();
+        writeln!(
+            out,
+            "{},{},{status},{acks},{}",
+            quote(&row.label),
+            row.txid,
+            quote(&reason)
+        )?;
+    }
+
+    out.flush()
+}
+
+/// Writes one row per transaction, in the order given.
+pub fn write_report(out: &mut impl Write, txids: &[Txid], report: &Report) -> std::io::Result<()> {
+    writeln!(out, "txid,wtxid,status,acks,first_ack_secs,reject_reason")?;
+
+    for txid in txids {
+        let latency = report.latencies.get(txid).cloned().unwrap_or_default();
+        let wtxid = report
+            .wtxids
+            .get(txid)
+            .map(ToString::to_string)
+            .unwrap_or_default();
+        let first_ack = latency
+            .first()
+            .map(|first| format!("{:.3}", first.as_secs_f64()))
+            .unwrap_or_default();
+        let reject = report
+            .rejects
+            .get(txid)
+            .map(ToString::to_string)
+            .unwrap_or_default();
+        writeln!(
+            out,
+            "{txid},{wtxid},{},{},{first_ack},{}",
+            status(report, txid),
+            latency.acks.len(),
+            quote(&reject)
+        )?;
+    }
+
+    out.flush()
+}
+
+/// How a transaction fared, as a single word.
+fn status(report: &Report, txid: &Txid) -> &'static str {
+    if report.success.contains(txid) {
+        "propagated"
+    } else if report.already_known.contains(txid) {
+        "already_known"
+    } else if report.rejects.contains_key(txid) {
+        "rejected"
+    } else {
+        "unknown"
+    }
+}
+
+/// Strips the quotes around a quoted field.
+fn unquote(field: &str) -> String {
+    match field.strip_prefix('"').and_then(|f| f.strip_suffix('"')) {
+        Some(inner) => inner.replace("\"\"", "\""),
+        None => field.to_string(),
+    }
+}
+
+/// Quotes a field if it needs it.
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
@@ -0,0 +1,83 @@
+//! Transaction input in the formats that wallets and scripts commonly produce.
+//!
+//! Text input holds one transaction per line, as hex, base64 or a base64 PSBT. Binary input holds
+//! a single raw transaction or PSBT.
+
+use base64::Engine;
+use pushtx::Transaction;
+
+/// The magic bytes that every binary PSBT starts with.
+const PSBT_MAGIC: &[u8] = b"psbt\xff";
+
+/// The base64 encoding of `PSBT_MAGIC`, which every base64 PSBT starts with.
+const PSBT_BASE64_MAGIC: &str = "cHNidP8";
+
+/// How to interpret the transaction input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Tell the format from the contents, line by line for text.
+    Auto,
+    /// Hex encoded transactions, one per line.
+    Hex,
+    /// Base64 encoded transactions, one per line.
+    Base64,
+    /// Finalized PSBTs, one base64 PSBT per line, or a single binary PSBT.
+    Psbt,
+    /// A single transaction in its binary serialization.
+    Raw,
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Auto => write!(f, "auto"),
+            Format::Hex => write!(f, "hex"),
+            Format::Base64 => write!(f, "base64"),
+            Format::Psbt => write!(f, "psbt"),
+            Format::Raw => write!(f, "raw"),
+        }
+    }
+}
+
+/// Parses the whole input in the given format.
+pub fn parse(input: &[u8], format: Format) -> Result<Vec<Transaction>, super::Error> {
+    match format {
+        Format::Raw => Ok(vec![Transaction::from_bytes(input)?]),
+        Format::Psbt if input.starts_with(PSBT_MAGIC) => Ok(vec![Transaction::from_psbt(input)?]),
+        Format::Auto if input.starts_with(PSBT_MAGIC) => Ok(vec![Transaction::from_psbt(input)?]),
+        Format::Auto if std::str::from_utf8(input).is_err() => {
+            Ok(vec![Transaction::from_bytes(input)?])
+        }
+        _ => {
+            let text = std::str::from_utf8(input).map_err(|_| super::Error::NotText)?;
+            text.lines()
+                .enumerate()
+                .filter(|(_, line)| !line.trim().is_empty())
+                .map(|(i, line)| parse_line(line.trim(), format, i + 1))
+                .collect()
+        }
+    }
+}
+
+/// Parses a single line of text input.
+pub fn parse_line(line: &str, format: Format, number: usize) -> Result<Transaction, super::Error> {
+    let format = match format {
+        Format::Auto if line.starts_with(PSBT_BASE64_MAGIC) => Format::Psbt,
+        Format::Auto if Transaction::from_hex(line).is_ok() => Format::Hex,
+        Format::Auto => Format::Base64,
+        format => format,
+    };
+
+    match format {
+        Format::Hex => Ok(Transaction::from_hex(line)?),
+        Format::Base64 => Ok(Transaction::from_bytes(base64(line, number)?)?),
+        Format::Psbt => Ok(Transaction::from_psbt(base64(line, number)?)?),
+        Format::Auto | Format::Raw => unreachable!("raw input is never split into lines"),
+    }
+}
+
+fn base64(line: &str, number: usize) -> Result<Vec<u8>, super::Error> {
+    base64::engine::general_purpose::STANDARD
+        .decode(line)
+        .map_err(|_| super::Error::Base64 { line: number })
+}
@@ -1,3 +1,7 @@
+mod csv;
+mod input;
+mod stream;
+
 use pushtx::*;
 
 use core::panic;
@@ -27,6 +31,18 @@ struct Cli {
     #[arg(short = 'm', long, default_value_t = TorMode::Try)]
     tor_mode: TorMode,
 
+    /// A SOCKS proxy to use instead of a local Tor instance. Can be given several times
+    ///
+    /// The first reachable proxy is used and the next ones take over, in order, if it becomes
+    /// unreachable. Ignored with --tor-mode no.
+    #[arg(long = "proxy", value_name = "ADDR")]
+    proxies: Vec<std::net::SocketAddr>,
+
+    /// Broadcast once over Tor and once over clearnet, succeeding if either path works.
+    /// Overrides the Tor mode.
+    #[arg(long)]
+    diverse: bool,
+
     /// Dry-run mode. Performs the whole process except the sending part.
     #[arg(short, long)]
     dry_run: bool,
@@ -35,15 +51,182 @@ struct Cli {
     #[arg(short, long, default_value_t = Network::Mainnet)]
     network: Network,
 
-    /// Zero or one paths to a file containing line-delimited hex encoded transactions
+    /// Zero or one paths to a file containing transactions, see --format
     ///
-    /// If not present, stdin is used instead (hex only, one tx per line).
+    /// If not present, stdin is used instead.
     #[arg(short = 'f', long = "file", value_name = "FILE")]
     txs: Option<PathBuf>,
 
+    /// Transactions passed directly as arguments, as an alternative to --file and stdin
+    ///
+    /// Each argument is read like one line of input, see --format.
+    #[arg(value_name = "TX", conflicts_with_all = ["txs", "csv"])]
+    args: Vec<String>,
+
+    /// Keep reading stdin and broadcast each transaction as soon as its line arrives
+    ///
+    /// Prints one result per line. All broadcasts share one connection to the network, so they
+    /// can be linked to each other by whoever sees it, such as the Tor exit.
+    #[arg(long, conflicts_with_all = ["txs", "csv", "args", "diverse", "attest"])]
+    stream: bool,
+
+    /// The format of the transactions in the file or on stdin
+    ///
+    /// Text holds one transaction per line. Auto-detection tells hex, base64 and base64 PSBTs
+    /// apart line by line, and treats anything that is not text as a binary transaction or PSBT.
+    #[arg(long, default_value_t = input::Format::Auto)]
+    format: input::Format,
+
+    /// Path to a CSV file with "label,hex" rows, as an alternative to --file
+    ///
+    /// Per-row results are written to the file given by --csv-out.
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with = "txs",
+        requires = "csv_out"
+    )]
+    csv: Option<PathBuf>,
+
+    /// Where to write per-row results of --csv
+    #[arg(long, value_name = "FILE", requires = "csv")]
+    csv_out: Option<PathBuf>,
+
+    /// How to print the outcome
+    ///
+    /// With csv, nothing but one row per transaction is printed, as
+    /// "txid,wtxid,status,acks,first_ack_secs,reject_reason".
+    #[arg(long, default_value_t = ReportFormat::Text, conflicts_with = "stream")]
+    report_format: ReportFormat,
+
+    /// Write a JSON record of the broadcast (peers, messages sent, acks, timestamps) to FILE
+    #[arg(long, value_name = "FILE")]
+    attest: Option<PathBuf>,
+
+    /// Sign the --attest record with the hex encoded secret key stored in FILE
+    #[arg(long, value_name = "FILE", requires = "attest")]
+    attest_key: Option<PathBuf>,
+
+    /// Limit outbound traffic to this many bytes per second
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u32).range(1..))]
+    max_bandwidth: Option<u32>,
+
+    /// Path to a CIDR to country map (one "CIDR COUNTRY" entry per line)
+    #[arg(long, value_name = "FILE", requires = "exclude_country")]
+    country_map: Option<PathBuf>,
+
+    /// Do not connect to peers in this country (two letter code, repeatable)
+    #[arg(long, value_name = "CC", requires = "country_map")]
+    exclude_country: Vec<String>,
+
+    /// Txid of a transaction that replaces a conflicting one in the set (repeatable)
+    #[arg(long, value_name = "TXID")]
+    replacement: Vec<Txid>,
+
+    /// Append every transaction announcement seen from peers to this file (JSON lines)
+    #[arg(long, value_name = "FILE")]
+    inv_log: Option<PathBuf>,
+
+    /// Append every address dialed, the outcome and timestamps to this file (JSON lines)
+    #[arg(long, value_name = "FILE")]
+    audit_log: Option<PathBuf>,
+
+    /// P2P protocol version to advertise to peers
+    #[arg(long, value_name = "VERSION", default_value_t = PROTOCOL_VERSION)]
+    protocol_version: u32,
+
+    /// Ask a few peers for the transactions first and skip the ones they already have
+    #[arg(long)]
+    presence_probe: bool,
+
+    /// Fetch transactions announced by peers and warn about any that double spend the broadcast ones
+    #[arg(long)]
+    conflict_alerts: bool,
+
+    /// Stop as soon as a peer rejects one of the transactions, leaving the rest unsent
+    #[arg(long)]
+    halt_on_reject: bool,
+
+    /// Give up on a transaction that no peer acks within SECS of it going out
+    #[arg(long, value_name = "SECS")]
+    ack_timeout: Option<u64>,
+
+    /// Succeed once at least K of the transactions are acked instead of all of them
+    #[arg(long, value_name = "K", conflicts_with = "acks_per_tx")]
+    min_acked: Option<usize>,
+
+    /// Succeed only once every transaction is acked by at least P peers
+    #[arg(long, value_name = "P")]
+    acks_per_tx: Option<usize>,
+
+    /// Look for a node of your own on localhost and always broadcast through it if found
+    #[arg(long)]
+    local_node: bool,
+
+    /// Never query DNS seeds, only use the built-in peer list or the one in FILE
+    #[arg(long, value_name = "FILE")]
+    fixed_only: Option<Option<PathBuf>>,
+
+    /// Print every address that may be contacted before connecting
+    #[arg(long)]
+    print_pool: bool,
+
+    /// Cache DNS seed results in this file and reuse them for six hours
+    #[arg(long, value_name = "FILE")]
+    dns_cache: Option<PathBuf>,
+
+    /// How many peers to send the transactions to at the same time
+    #[arg(long, default_value_t = 1)]
+    broadcast_peers: u8,
+
+    /// How many more peers to connect to only to watch for the transactions
+    #[arg(long, default_value_t = 9)]
+    monitor_peers: u16,
+
+    /// Send to the broadcast peers first and to progressively more peers after every SECS without acks
+    #[arg(long, value_name = "SECS")]
+    escalate: Option<u64>,
+
+    /// Stay connected this many seconds after success and print a propagation summary
+    #[arg(long, value_name = "SECS")]
+    wait: Option<u64>,
+
+    /// Stay connected this many seconds after success and report when new blocks include the transactions
+    #[arg(long, value_name = "SECS")]
+    watch_blocks: Option<u64>,
+
+    /// Only listen for this many seconds before sending, skipping transactions already relayed
+    #[arg(long, value_name = "SECS")]
+    listen_first: Option<u64>,
+
+    /// Print a status line every SECS while the broadcast runs
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    stats_interval: Option<u64>,
+
     /// Print debug info (use multiple times for more verbosity; max 3)
     #[arg(short, long, action = clap::ArgAction::Count)]
     verbose: u8,
+
+    /// Replace txids and peer addresses in the debug output with short salted hashes
+    #[arg(long)]
+    redact_logs: bool,
+
+    /// Disconnect peers that announce messages with payloads larger than this many bytes
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u32).range(4096..))]
+    max_message_size: Option<u32>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Handshake a number of peers and tabulate what they advertise, without broadcasting
+    Census {
+        /// How many peers to survey
+        #[arg(long, default_value_t = 100)]
+        peers: usize,
+    },
 }
 
 fn main() -> anyhow::Result<()> {
@@ -61,46 +244,81 @@ fn main() -> anyhow::Result<()> {
             .filter_level(level.to_level_filter())
             .init();
     }
+    pushtx::set_log_redaction(cli.redact_logs);
+
+    let exclude_countries = match cli.country_map {
+        Some(path) => Some(geo::CountryExclusion {
+            map: std::fs::read_to_string(path)?
+                .parse()
+                .map_err(Error::CountryMap)?,
+            countries: cli
+                .exclude_country
+                .iter()
+                .map(|cc| cc.to_ascii_uppercase())
+                .collect(),
+        }),
+        None => None,
+    };
 
-    let txs: Result<Vec<_>, Error> = match cli.txs {
-        Some(path) => {
-            let mut contents = String::new();
-            let mut file = std::fs::File::open(path)?;
-            file.read_to_string(&mut contents)?;
-            contents
-                .lines()
-                .filter(|line| !line.trim().is_empty())
-                .map(|line| pushtx::Transaction::from_hex(line).map_err(Into::into))
-                .collect()
+    if let Some(Command::Census { peers }) = cli.command {
+        let opts = Opts {
+            use_tor: cli.tor_mode.into(),
+            socks_proxies: cli.proxies,
+            network: cli.network.into(),
+            max_bandwidth: cli.max_bandwidth,
+            max_message_size: cli.max_message_size,
+            protocol_version: cli.protocol_version,
+            exclude_countries,
+            max_time: std::time::Duration::from_secs(120),
+            ..Default::default()
+        };
+        println!("* Surveying up to {peers} peers...");
+        let census = census::census(&opts, peers).map_err(Error::Census)?;
+        print_census(&census);
+        return Ok(());
+    }
+
+    let mut csv_rows = None;
+    let txs: Result<Vec<_>, Error> = match (&cli.csv, cli.txs) {
+        (Some(path), _) => csv::read(path).map(|(rows, txs)| {
+            csv_rows = Some(rows);
+            txs
+        }),
+        (None, _) if cli.stream => Ok(Vec::new()),
+        (None, _) if !cli.args.is_empty() => {
+            input::parse(cli.args.join("\n").as_bytes(), cli.format)
         }
-        None => {
-            let stdin = std::io::stdin();
+        (None, Some(path)) => std::fs::read(path)
+            .map_err(Into::into)
+            .and_then(|contents| input::parse(&contents, cli.format)),
+        (None, None) => {
+            let mut stdin = std::io::stdin();
             if stdin.is_terminal() {
                 eprintln!("Enter some hex-encoded transactions (one per line, Ctrl + {EOF_CHR} when done) ... ");
             }
+            let mut contents = Vec::new();
             stdin
-                .lines()
-                .filter_map(|line| match line {
-                    Ok(line) if !line.trim().is_empty() => {
-                        Some(pushtx::Transaction::from_hex(line).map_err(Into::into))
-                    }
-                    Ok(_) => None,
-                    Err(err) => Some(Err(Error::Io(err))),
-                })
-                .collect()
+                .read_to_end(&mut contents)
+                .map_err(Into::into)
+                .and_then(|_| input::parse(&contents, cli.format))
         }
     };
 
-    if cli.dry_run {
+    // anything but the table would get in the way of parsing it
+    let text = cli.report_format == ReportFormat::Text;
+
+    if cli.dry_run && text {
         println!("! ** DRY RUN MODE **");
     }
 
     let txs = match txs {
+        Ok(txs) if cli.stream => Ok(txs),
+        Ok(txs) if !text && !txs.is_empty() => Ok(txs),
         Ok(txs) => {
             if !txs.is_empty() {
                 println!("* The following transactions will be broadcast:");
                 for tx in &txs {
-                    println!("  - {}", tx.txid())
+                    println!("  - {} (wtxid {})", tx.txid(), tx.wtxid())
                 }
                 Ok(txs)
             } else {
@@ -111,21 +329,139 @@ fn main() -> anyhow::Result<()> {
     }?;
 
     let txids: HashSet<_> = txs.iter().map(|tx| tx.txid()).collect();
+    // the status lines and the csv report follow the order the transactions were given in
+    let mut listed = HashSet::new();
+    let ordered_txids: Vec<_> = txs
+        .iter()
+        .map(|tx| tx.txid())
+        .filter(|txid| listed.insert(*txid))
+        .collect();
 
-    let receiver = broadcast(
-        txs,
-        Opts {
-            use_tor: cli.tor_mode.into(),
-            network: cli.network.into(),
-            dry_run: cli.dry_run,
-            ..Default::default()
+    let inv_log = match cli.inv_log {
+        Some(path) => Some(inv_log(path)?),
+        None => None,
+    };
+
+    let connection_log = match cli.audit_log {
+        Some(path) => Some(audit_log(path)?),
+        None => None,
+    };
+
+    let attestation = match (&cli.attest, &cli.attest_key) {
+        (Some(_), Some(key)) => {
+            Attestation::signed_from_hex(&std::fs::read_to_string(key)?).ok_or(Error::AttestKey)?
+        }
+        (Some(_), None) => Attestation::Unsigned,
+        (None, _) => Attestation::Off,
+    };
+
+    let monitor = cli.stats_interval.map(|_| Monitor::new());
+    let opts = Opts {
+        use_tor: cli.tor_mode.into(),
+        socks_proxies: cli.proxies,
+        network: cli.network.into(),
+        dry_run: cli.dry_run,
+        max_bandwidth: cli.max_bandwidth,
+        max_message_size: cli.max_message_size,
+        find_peer_strategy: match cli.fixed_only {
+            Some(file) => FindPeerStrategy::FixedOnly(file),
+            None => FindPeerStrategy::default(),
+        },
+        dns_cache: cli.dns_cache.map(|path| seeds::DnsCache {
+            path,
+            ttl: std::time::Duration::from_secs(6 * 60 * 60),
+        }),
+        local_node: if cli.local_node {
+            LocalNodeProbe::Localhost
+        } else {
+            LocalNodeProbe::Off
         },
-    );
+        broadcast_peers: cli.broadcast_peers,
+        monitor_peers: cli.monitor_peers,
+        protocol_version: cli.protocol_version,
+        inv_log,
+        connection_log,
+        presence_probe: cli.presence_probe,
+        conflict_alerts: cli.conflict_alerts,
+        halt_on_reject: cli.halt_on_reject,
+        ack_timeout: std::time::Duration::from_secs(cli.ack_timeout.unwrap_or(0)),
+        success_policy: match (cli.min_acked, cli.acks_per_tx) {
+            (Some(k), _) => SuccessPolicy::AtLeast(k),
+            (None, Some(p)) => SuccessPolicy::PeersPerTx(p),
+            (None, None) => SuccessPolicy::AllAcked,
+        },
+        attestation,
+        listen_first: cli.listen_first.map(std::time::Duration::from_secs),
+        linger: std::time::Duration::from_secs(cli.wait.unwrap_or(0)),
+        watch_blocks: std::time::Duration::from_secs(cli.watch_blocks.unwrap_or(0)),
+        escalation: cli.escalate.map(|secs| Escalation {
+            window: std::time::Duration::from_secs(secs),
+            ..Default::default()
+        }),
+        exclude_countries,
+        replacements: cli.replacement.into_iter().collect(),
+        monitor: monitor.clone(),
+        ..Default::default()
+    };
+    if cli.stream {
+        return stream::run(opts, cli.format);
+    }
+
+    let receiver = if cli.diverse {
+        broadcast_diverse(txs, opts)
+    } else {
+        broadcast(txs, opts)
+    };
+
+    let stats = monitor.zip(cli.stats_interval.map(std::time::Duration::from_secs));
+    let mut next_stats = stats
+        .as_ref()
+        .map(|(_, interval)| std::time::Instant::now() + *interval);
 
     loop {
-        match receiver.recv() {
+        let info = match (&stats, next_stats) {
+            (Some((monitor, interval)), Some(at)) => match receiver.recv_deadline(at) {
+                Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
+                    let line = stats_line(&monitor.status(), &monitor.peers(), &ordered_txids);
+                    if text {
+                        println!("{line}");
+                    } else {
+                        eprintln!("{line}");
+                    }
+                    next_stats = Some(at + *interval);
+                    continue;
+                }
+                Err(crossbeam_channel::RecvTimeoutError::Disconnected) => {
+                    Err(crossbeam_channel::RecvError)
+                }
+                Ok(info) => Ok(info),
+            },
+            _ => receiver.recv(),
+        };
+        match info {
+            Ok(Info::Done(Ok(report))) if !text => {
+                if let (Some(rows), Some(path)) = (&csv_rows, &cli.csv_out) {
+                    csv::write(path, rows, &report)?;
+                }
+                if let (Some(attestation), Some(path)) = (&report.attestation, &cli.attest) {
+                    std::fs::write(path, attestation)?;
+                }
+                csv::write_report(&mut std::io::stdout().lock(), &ordered_txids, &report)?;
+                if report.policy_met {
+                    break Ok(());
+                }
+                break Err(Error::Partial.into());
+            }
+            Ok(info) if !text && !matches!(info, Info::Done(_)) => {}
             Ok(Info::ResolvingPeers) => println!("* Resolving peers from DNS..."),
             Ok(Info::ResolvedPeers(n)) => println!("* Resolved {n} peers"),
+            Ok(Info::PeerPool(pool)) => {
+                if cli.print_pool {
+                    for peer in pool {
+                        println!("  - {peer}");
+                    }
+                }
+            }
             Ok(Info::ConnectingToNetwork { tor_status }) => {
                 println!("* Connecting to the P2P network ({})...", cli.network);
                 match tor_status {
@@ -133,20 +469,108 @@ fn main() -> anyhow::Result<()> {
                     None => println!("  - not using Tor"),
                 }
             }
+            Ok(Info::ConnectFailed { peer, cause }) => {
+                println!("  - could not connect to {peer}: {cause}")
+            }
             Ok(Info::Broadcast { peer }) => println!("* Broadcast to peer {}", peer),
-            Ok(Info::Done(Ok(Report { success, rejects }))) => {
-                let difference: Vec<_> = txids.difference(&success).collect();
-                if difference.is_empty() {
+            Ok(Info::Confirmed { txid, block_hash }) => {
+                println!("* Confirmed: {txid} in block {block_hash}")
+            }
+            Ok(Info::Warning(warning)) => println!("! {warning}"),
+            Ok(Info::Progress { .. }) => {}
+            Ok(Info::Done(Ok(report))) => {
+                if let (Some(rows), Some(path)) = (&csv_rows, &cli.csv_out) {
+                    csv::write(path, rows, &report)?;
+                    println!("* Per-row results written to {}", path.display());
+                }
+                if let (Some(attestation), Some(path)) = (&report.attestation, &cli.attest) {
+                    std::fs::write(path, attestation)?;
+                    println!("* Attestation written to {}", path.display());
+                }
+                let Report {
+                    success,
+                    wtxids,
+                    rejects,
+                    already_known,
+                    policy_met,
+                    unsent,
+                    timed_out,
+                    diagnoses,
+                    latencies,
+                    interest,
+                    escalation_stage,
+                    feefilters,
+                    ..
+                } = report;
+                let difference: Vec<_> = txids
+                    .iter()
+                    .filter(|txid| !success.contains(*txid) && !already_known.contains(*txid))
+                    .collect();
+                for txid in &already_known {
+                    println!("  - already known to the network: {txid}");
+                }
+                if policy_met {
                     println!("* Done! Broadcast successful");
+                    for txid in &success {
+                        match wtxids.get(txid) {
+                            Some(wtxid) => println!("  - seen: {txid} (wtxid {wtxid})"),
+                            None => println!("  - seen: {txid}"),
+                        }
+                    }
+                    for missing in difference {
+                        println!("  - not seen: {missing}");
+                    }
+                    if let Some(stage) = escalation_stage {
+                        println!("  - acked at escalation stage {stage}");
+                    }
+                    if cli.wait.is_some() {
+                        print_propagation(&txids, &latencies, &rejects);
+                    } else {
+                        for (txid, latency) in latencies {
+                            if let Some(first) = latency.first() {
+                                let secs = first.as_secs_f64();
+                                println!("  - {txid}: first seen after {secs:.1}s");
+                            }
+                        }
+                    }
                     break Ok(());
                 } else {
                     println!("* Failed to broadcast one or more transactions");
                     for missing in difference {
-                        println!("  - failed: {missing}");
+                        if unsent.contains(*missing) {
+                            println!("  - not sent: {missing}");
+                            continue;
+                        }
+                        if timed_out.contains(*missing) {
+                            println!("  - timed out: {missing}");
+                        } else {
+                            println!("  - failed: {missing}");
+                        }
+                        let requests = interest
+                            .get(*missing)
+                            .map(Vec::as_slice)
+                            .unwrap_or_default();
+                        if let Some(first) = requests.first() {
+                            println!(
+                                "    requested by {} peers, first by {} after {:.1}s",
+                                requests.len(),
+                                first.peer,
+                                first.after.as_secs_f64()
+                            );
+                        }
                     }
                     for (r_txid, r_reason) in rejects {
                         println!("  - reject: {r_txid}: {r_reason}");
                     }
+                    for (d_txid, diagnosis) in diagnoses {
+                        println!("  - diagnosis: {d_txid}: {diagnosis}");
+                    }
+                    if let (Some(median), Some(min)) = (feefilters.median(), feefilters.min()) {
+                        let peers = feefilters.peers.len();
+                        println!(
+                            "  - feefilters of {peers} peers: median {median} sat/kvB, lowest {min}"
+                        );
+                    }
                     break Err(Error::Partial.into());
                 }
             }
@@ -158,16 +582,177 @@ fn main() -> anyhow::Result<()> {
     }
 }
 
+/// A compact status line, so that long runs do not look stuck between events.
+fn stats_line(status: &Status, peers: &[PeerStatus], txids: &[Txid]) -> String {
+    let handshaked = peers
+        .iter()
+        .filter(|peer| matches!(peer.state, PeerState::Ready | PeerState::Selected))
+        .count();
+    let acks: Vec<_> = txids
+        .iter()
+        .map(|txid| {
+            status
+                .acks_per_tx
+                .get(txid)
+                .copied()
+                .unwrap_or(0)
+                .to_string()
+        })
+        .collect();
+    format!(
+        "~ [{}s] {}: {} connected, {handshaked} handshaked, acks {}",
+        status.elapsed.as_secs(),
+        status.phase,
+        status.connected,
+        acks.join("/")
+    )
+}
+
+/// Prints how far each transaction propagated while lingering.
+fn print_propagation(
+    txids: &HashSet<Txid>,
+    latencies: &std::collections::HashMap<Txid, Latency>,
+    rejects: &std::collections::HashMap<Txid, Reject>,
+) {
+    println!("* Propagation summary:");
+    for txid in txids {
+        let latency = latencies.get(txid).cloned().unwrap_or_default();
+        let secs = |d: Option<std::time::Duration>| match d {
+            Some(d) => format!("{:.1}s", d.as_secs_f64()),
+            None => "-".to_string(),
+        };
+        println!(
+            "  - {txid}: {} acks, first after {}, last after {}",
+            latency.acks.len(),
+            secs(latency.first()),
+            secs(latency.acks.last().copied())
+        );
+        if let Some(reason) = rejects.get(txid) {
+            println!("    late reject: {reason}");
+        }
+    }
+}
+
+/// Prints the tables of a census.
+fn print_census(census: &census::Census) {
+    println!("* Surveyed {} peers", census.peers.len());
+    println!("* User agents:");
+    for (user_agent, count) in census.user_agents() {
+        println!("  {count:>6}  {user_agent}");
+    }
+    println!("* Protocol versions:");
+    for (version, count) in census.versions() {
+        println!("  {count:>6}  {version}");
+    }
+    println!("* Service flags:");
+    for (services, count) in census.services() {
+        println!("  {count:>6}  {services:#x}");
+    }
+}
+
+/// Starts a thread that appends observed announcements to a file, one JSON object per line.
+fn inv_log(path: PathBuf) -> std::io::Result<crossbeam_channel::Sender<InvObservation>> {
+    use std::io::Write;
+
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    let (inv_tx, inv_rx) = crossbeam_channel::unbounded::<InvObservation>();
+
+    std::thread::spawn(move || {
+        let mut file = std::io::BufWriter::new(file);
+        for observation in inv_rx {
+            let time = observation
+                .at
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64();
+            let (kind, id) = match observation.tx {
+                ObservedTx::Txid(txid) => ("txid", txid.to_string()),
+                ObservedTx::Wtxid(wtxid) => ("wtxid", wtxid.to_string()),
+            };
+            let line = format!(
+                r#"{{"time":{time:.3},"peer":"{}","{kind}":"{id}"}}"#,
+                observation.peer
+            );
+            if writeln!(file, "{line}").and_then(|_| file.flush()).is_err() {
+                eprintln!("! Failed to write to the inv log, no longer logging");
+                break;
+            }
+        }
+    });
+
+    Ok(inv_tx)
+}
+
+/// Starts a thread that appends connection records to a file, one JSON object per line.
+fn audit_log(path: PathBuf) -> std::io::Result<crossbeam_channel::Sender<ConnectionRecord>> {
+    use std::io::Write;
+
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    let (record_tx, record_rx) = crossbeam_channel::unbounded::<ConnectionRecord>();
+
+    std::thread::spawn(move || {
+        let secs = |time: std::time::SystemTime| {
+            time.duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64()
+        };
+        let mut file = std::io::BufWriter::new(file);
+        for record in record_rx {
+            let connected_at = match record.connected_at {
+                Some(time) => format!("{:.3}", secs(time)),
+                None => "null".to_string(),
+            };
+            let proxy = match record.proxy {
+                Some(proxy) => format!("\"{proxy}\""),
+                None => "null".to_string(),
+            };
+            let line = format!(
+                r#"{{"peer":"{}","dialed_at":{:.3},"connected_at":{connected_at},"ended_at":{:.3},"proxy":{proxy},"outcome":"{}"}}"#,
+                record.peer,
+                secs(record.dialed_at),
+                secs(record.ended_at),
+                record.outcome
+            );
+            if writeln!(file, "{line}").and_then(|_| file.flush()).is_err() {
+                eprintln!("! Failed to write to the audit log, no longer logging");
+                break;
+            }
+        }
+    });
+
+    Ok(record_tx)
+}
+
 #[derive(Debug, thiserror::Error)]
 enum Error {
     #[error("IO error while reading transaction(s): {0}")]
     Io(#[from] std::io::Error),
     #[error("Error while parsing transaction(s): {0}")]
     Parse(#[from] pushtx::ParseTxError),
+    #[error("Invalid country map: {0}")]
+    CountryMap(pushtx::geo::ParseCountryMapError),
+    #[error("Line {line} is not valid base64")]
+    Base64 { line: usize },
+    #[error("Input is not text, pass --format raw or --format psbt for binary input")]
+    NotText,
+    #[error("Streaming reads one transaction per line, --format raw is not supported")]
+    StreamRaw,
+    #[error("Malformed CSV row on line {line}, expected \"label,hex\"")]
+    Csv { line: usize },
+    #[error("Attestation key is not a valid secret key (64 hex characters)")]
+    AttestKey,
     #[error("Empty transaction set, did you pass at least one transaction?")]
     EmptyTxSet,
     #[error("Failed to broadcast: {0}")]
     Broadcast(pushtx::Error),
+    #[error("Census failed: {0}")]
+    Census(pushtx::Error),
     #[error("Failed to broadcast one or more transactions")]
     Partial,
 }
@@ -204,6 +789,25 @@ impl std::fmt::Display for TorMode {
     }
 }
 
+/// How to print the outcome of a broadcast.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    /// Progress and a summary for reading.
+    Text,
+    /// One CSV row per transaction and nothing else.
+    Csv,
+}
+
+impl std::fmt::Display for ReportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ReportFormat::Text => "text",
+            ReportFormat::Csv => "csv",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 /// The Bitcoin network to connect to.
 #[derive(Debug, Clone, Copy, clap::ValueEnum)]
 pub enum Network {
@@ -0,0 +1,79 @@
+//! Streaming mode: stdin stays open and every transaction is broadcast as soon as its line
+//! arrives, rather than after the input ends.
+
+use std::io::BufRead;
+use std::sync::{Arc, Mutex};
+
+use pushtx::{FindPeerStrategy, Info, Opts, Reactor, Report, Service};
+
+use super::{input, Error};
+
+/// Broadcasts each line of stdin on its own as soon as it is read and prints one result line per
+/// input line, prefixed with its line number. Every broadcast goes through the same p2p reactor,
+/// and once the first peer pool is resolved, later broadcasts draw their peers from it.
+pub fn run(opts: Opts, format: input::Format) -> anyhow::Result<()> {
+    if format == input::Format::Raw {
+        return Err(Error::StreamRaw.into());
+    }
+
+    let reactor = Reactor::with_proxies(opts.use_tor.clone(), &opts.socks_proxies)
+        .map_err(Error::Broadcast)?;
+    let pool: Arc<Mutex<Option<Vec<Service>>>> = Default::default();
+    let mut followers = Vec::new();
+
+    for (i, line) in std::io::stdin().lock().lines().enumerate() {
+        let (number, line) = (i + 1, line?);
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let tx = match input::parse_line(line.trim(), format, number) {
+            Ok(tx) => tx,
+            Err(err) => {
+                println!("{number}: invalid: {err}");
+                continue;
+            }
+        };
+
+        let mut opts = opts.clone();
+        if let Some(pool) = pool.lock().unwrap().clone() {
+            opts.find_peer_strategy = FindPeerStrategy::Custom(pool);
+        }
+
+        let txid = tx.txid();
+        let receiver = reactor.broadcast(vec![tx], opts);
+        let pool = pool.clone();
+        followers.push(std::thread::spawn(move || {
+            let result = loop {
+                match receiver.recv() {
+                    Ok(Info::PeerPool(peers)) => {
+                        pool.lock().unwrap().get_or_insert(peers);
+                    }
+                    Ok(Info::Done(result)) => break result,
+                    Ok(_) => {}
+                    Err(_) => panic!("worker thread disconnected"),
+                }
+            };
+            println!("{number}: {txid}: {}", outcome(txid, result));
+        }));
+    }
+
+    for follower in followers {
+        let _ = follower.join();
+    }
+
+    Ok(())
+}
+
+/// Describes how the broadcast of a single transaction ended.
+fn outcome(txid: pushtx::Txid, result: Result<Report, pushtx::Error>) -> String {
+    match result {
+        Ok(report) if report.success.contains(&txid) => "broadcast".to_string(),
+        Ok(report) if report.already_known.contains(&txid) => "already known".to_string(),
+        Ok(report) => match report.rejects.get(&txid) {
+            Some(reason) => format!("failed: rejected: {reason}"),
+            None => "failed".to_string(),
+        },
+        Err(err) => format!("failed: {err}"),
+    }
+}
@@ -0,0 +1,280 @@
+//! # Kotlin and Swift bindings for pushtx
+//!
+//! UniFFI scaffolding around the `pushtx` library so that mobile wallets can broadcast
+//! transactions without writing a Rust bridge of their own. On Android, a running Orbot instance
+//! is picked up as the local Tor proxy.
+//!
+//! Bindings are generated from the compiled library:
+//!
+//! ```text
+//! cargo build --release -p pushtx-ffi
+//! cargo run -p pushtx-ffi --bin uniffi-bindgen -- generate \
+//!     --library target/release/libpushtx_ffi.so --language kotlin --out-dir out
+//! ```
+//!
+//! A broadcast is started with `broadcast()`, and its progress is followed by calling
+//! `Broadcast::next_event()` from a background thread until it returns nothing.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+uniffi::setup_scaffolding!();
+
+/// Which Bitcoin network to connect to.
+#[derive(uniffi::Enum)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    Regtest,
+    Signet,
+}
+
+impl From<Network> for pushtx::Network {
+    fn from(value: Network) -> Self {
+        match value {
+            Network::Mainnet => Self::Mainnet,
+            Network::Testnet => Self::Testnet,
+            Network::Regtest => Self::Regtest,
+            Network::Signet => Self::Signet,
+        }
+    }
+}
+
+/// Whether to connect through a local Tor proxy.
+#[derive(uniffi::Enum)]
+pub enum TorMode {
+    /// Never use Tor.
+    No,
+    /// Use Tor if a proxy is found, otherwise connect directly.
+    BestEffort,
+    /// Use Tor and fail if no proxy is found.
+    Must,
+}
+
+impl From<TorMode> for pushtx::TorMode {
+    fn from(value: TorMode) -> Self {
+        match value {
+            TorMode::No => Self::No,
+            TorMode::BestEffort => Self::BestEffort,
+            TorMode::Must => Self::Must,
+        }
+    }
+}
+
+/// A subset of the library options suitable for mobile use.
+#[derive(uniffi::Record)]
+pub struct Opts {
+    pub network: Network,
+    pub use_tor: TorMode,
+    /// The maximum duration of the broadcast in seconds.
+    pub max_time_secs: u64,
+    /// Whether to simulate the broadcast without sending anything.
+    pub dry_run: bool,
+    /// How many peers to send the transactions to at the same time.
+    pub broadcast_peers: u8,
+    /// How many more peers to connect to only to watch for the transactions.
+    pub monitor_peers: u16,
+}
+
+impl From<Opts> for pushtx::Opts {
+    fn from(value: Opts) -> Self {
+        Self {
+            network: value.network.into(),
+            use_tor: value.use_tor.into(),
+            max_time: Duration::from_secs(value.max_time_secs),
+            dry_run: value.dry_run,
+            broadcast_peers: value.broadcast_peers,
+            monitor_peers: value.monitor_peers,
+            ..Default::default()
+        }
+    }
+}
+
+/// The outcome of a finished broadcast.
+#[derive(uniffi::Record)]
+pub struct Report {
+    /// Txids of the transactions that were seen on the network.
+    pub success: Vec<String>,
+    /// The wtxid of every transaction, by txid.
+    pub wtxids: HashMap<String, String>,
+    /// Rejected txids along with the reason.
+    pub rejects: HashMap<String, String>,
+    /// Best-effort explanations for txids that were neither seen nor rejected.
+    pub diagnoses: HashMap<String, String>,
+}
+
+/// Where a broadcast stands, see `Broadcast::status`.
+#[derive(uniffi::Record)]
+pub struct Status {
+    pub phase: Phase,
+    /// How many peers are connected.
+    pub connected: u64,
+    /// How many peers acked each txid so far.
+    pub acks_per_tx: HashMap<String, u64>,
+    /// The time since the broadcast started in seconds.
+    pub elapsed_secs: f64,
+}
+
+impl From<pushtx::Status> for Status {
+    fn from(value: pushtx::Status) -> Self {
+        Self {
+            phase: value.phase.into(),
+            connected: value.connected as u64,
+            acks_per_tx: value
+                .acks_per_tx
+                .into_iter()
+                .map(|(txid, acks)| (txid.to_string(), acks as u64))
+                .collect(),
+            elapsed_secs: value.elapsed.as_secs_f64(),
+        }
+    }
+}
+
+/// The phases of a broadcast.
+#[derive(uniffi::Enum)]
+pub enum Phase {
+    Pending,
+    ResolvingPeers,
+    Connecting,
+    Broadcasting,
+    Lingering,
+    Done,
+}
+
+impl From<pushtx::Phase> for Phase {
+    fn from(value: pushtx::Phase) -> Self {
+        match value {
+            pushtx::Phase::Pending => Self::Pending,
+            pushtx::Phase::ResolvingPeers => Self::ResolvingPeers,
+            pushtx::Phase::Connecting => Self::Connecting,
+            pushtx::Phase::Broadcasting => Self::Broadcasting,
+            pushtx::Phase::Lingering => Self::Lingering,
+            pushtx::Phase::Done => Self::Done,
+        }
+    }
+}
+
+/// Progress of a broadcast.
+#[derive(uniffi::Enum)]
+pub enum Event {
+    ResolvingPeers,
+    ResolvedPeers { count: u64 },
+    PeerPool { peers: Vec<String> },
+    ConnectingToNetwork { tor_proxy: Option<String> },
+    ConnectFailed { peer: String, cause: String },
+    Broadcast { peer: String },
+    Confirmed { txid: String, block_hash: String },
+    Warning { message: String },
+    Progress { phase: Phase, fraction: f32 },
+    Done { report: Report },
+    Failed { reason: String },
+}
+
+impl From<pushtx::Info> for Event {
+    fn from(value: pushtx::Info) -> Self {
+        match value {
+            pushtx::Info::ResolvingPeers => Self::ResolvingPeers,
+            pushtx::Info::ResolvedPeers(count) => Self::ResolvedPeers {
+                count: count as u64,
+            },
+            pushtx::Info::PeerPool(peers) => Self::PeerPool {
+                peers: peers.iter().map(ToString::to_string).collect(),
+            },
+            pushtx::Info::ConnectingToNetwork { tor_status } => Self::ConnectingToNetwork {
+                tor_proxy: tor_status.map(|proxy| proxy.to_string()),
+            },
+            pushtx::Info::ConnectFailed { peer, cause } => Self::ConnectFailed {
+                peer: peer.to_string(),
+                cause: cause.to_string(),
+            },
+            pushtx::Info::Broadcast { peer } => Self::Broadcast {
+                peer: peer.to_string(),
+            },
+            pushtx::Info::Confirmed { txid, block_hash } => Self::Confirmed {
+                txid: txid.to_string(),
+                block_hash: block_hash.to_string(),
+            },
+            pushtx::Info::Warning(warning) => Self::Warning {
+                message: warning.to_string(),
+            },
+            pushtx::Info::Progress { phase, fraction } => Self::Progress {
+                phase: phase.into(),
+                fraction,
+            },
+            pushtx::Info::Done(Ok(report)) => Self::Done {
+                report: Report {
+                    success: report.success.iter().map(ToString::to_string).collect(),
+                    wtxids: report
+                        .wtxids
+                        .into_iter()
+                        .map(|(txid, wtxid)| (txid.to_string(), wtxid.to_string()))
+                        .collect(),
+                    rejects: report
+                        .rejects
+                        .into_iter()
+                        .map(|(txid, reject)| (txid.to_string(), reject.to_string()))
+                        .collect(),
+                    diagnoses: report
+                        .diagnoses
+                        .into_iter()
+                        .map(|(txid, diagnosis)| (txid.to_string(), diagnosis.to_string()))
+                        .collect(),
+                },
+            },
+            pushtx::Info::Done(Err(err)) => Self::Failed {
+                reason: err.to_string(),
+            },
+        }
+    }
+}
+
+/// Errors raised before a broadcast starts.
+#[derive(Debug, thiserror::Error, uniffi::Error)]
+#[uniffi(flat_error)]
+pub enum Error {
+    #[error("invalid transaction: {0}")]
+    InvalidTransaction(pushtx::ParseTxError),
+}
+
+/// A broadcast running in the background.
+#[derive(uniffi::Object)]
+pub struct Broadcast {
+    receiver: crossbeam_channel::Receiver<pushtx::Info>,
+    monitor: pushtx::Monitor,
+}
+
+#[uniffi::export]
+impl Broadcast {
+    /// Blocks until the next event. Returns nothing once the broadcast is over and its final
+    /// event has been read.
+    pub fn next_event(&self) -> Option<Event> {
+        self.receiver.recv().ok().map(Into::into)
+    }
+
+    /// Where the broadcast stands right now. Does not block and does not consume events.
+    pub fn status(&self) -> Status {
+        self.monitor.status().into()
+    }
+}
+
+/// Starts broadcasting hex encoded transactions.
+#[uniffi::export]
+pub fn broadcast(txs: Vec<String>, opts: Opts) -> Result<Arc<Broadcast>, Error> {
+    let txs = txs
+        .iter()
+        .map(pushtx::Transaction::from_hex)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::InvalidTransaction)?;
+
+    let monitor = pushtx::Monitor::new();
+    let opts = pushtx::Opts {
+        monitor: Some(monitor.clone()),
+        ..opts.into()
+    };
+
+    Ok(Arc::new(Broadcast {
+        receiver: pushtx::broadcast(txs, opts),
+        monitor,
+    }))
+}
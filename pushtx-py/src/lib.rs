@@ -0,0 +1,246 @@
+//! # Python bindings for pushtx
+//!
+//! Built with `maturin develop` (or `maturin build`) from this directory.
+//!
+//! ```python
+//! import pushtx
+//!
+//! for event in pushtx.broadcast(tx_hex, network="signet", use_tor="must"):
+//!     print(event)
+//!     if event["type"] == "done":
+//!         print(event["report"]["success"])
+//! ```
+//!
+//! Events are dicts with a `type` key. The last one is either `done`, carrying the report, or
+//! `failed`, carrying the reason.
+
+use std::time::Duration;
+
+use pyo3::exceptions::{PyStopIteration, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+/// Broadcasts one or more hex encoded transactions and returns an iterator over the progress
+/// events. Accepted options: `network` ("mainnet", "testnet", "signet", "regtest"), `use_tor`
+/// ("no", "best_effort", "must"), `max_time` (seconds), `dry_run`, `broadcast_peers` and
+/// `monitor_peers`.
+#[pyfunction]
+#[pyo3(signature = (tx_hex, **opts))]
+fn broadcast(tx_hex: &Bound<'_, PyAny>, opts: Option<&Bound<'_, PyDict>>) -> PyResult<Broadcast> {
+    let txs: Vec<String> = match tx_hex.extract::<String>() {
+        Ok(tx) => vec![tx],
+        Err(_) => tx_hex.extract()?,
+    };
+
+    let txs = txs
+        .iter()
+        .map(pushtx::Transaction::from_hex)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| PyValueError::new_err(err.to_string()))?;
+
+    let opts = match opts {
+        Some(opts) => parse_opts(opts)?,
+        None => pushtx::Opts::default(),
+    };
+
+    Ok(Broadcast {
+        receiver: Some(pushtx::broadcast(txs, opts)),
+    })
+}
+
+/// Turns keyword arguments into broadcast options.
+fn parse_opts(kwargs: &Bound<'_, PyDict>) -> PyResult<pushtx::Opts> {
+    let mut opts = pushtx::Opts::default();
+
+    for (key, value) in kwargs.iter() {
+        match key.extract::<String>()?.as_str() {
+            "network" => {
+                opts.network = match value.extract::<String>()?.as_str() {
+                    "mainnet" => pushtx::Network::Mainnet,
+                    "testnet" => pushtx::Network::Testnet,
+                    "signet" => pushtx::Network::Signet,
+                    "regtest" => pushtx::Network::Regtest,
+                    other => return Err(PyValueError::new_err(format!("unknown network {other}"))),
+                }
+            }
+            "use_tor" => {
+                opts.use_tor = match value.extract::<String>()?.as_str() {
+                    "no" => pushtx::TorMode::No,
+                    "best_effort" => pushtx::TorMode::BestEffort,
+                    "must" => pushtx::TorMode::Must,
+                    other => {
+                        return Err(PyValueError::new_err(format!("unknown Tor mode {other}")))
+                    }
+                }
+            }
+            "max_time" => opts.max_time = Duration::from_secs_f64(value.extract()?),
+            "dry_run" => opts.dry_run = value.extract()?,
+            "broadcast_peers" => opts.broadcast_peers = value.extract()?,
+            "monitor_peers" => opts.monitor_peers = value.extract()?,
+            other => return Err(PyValueError::new_err(format!("unknown option {other}"))),
+        }
+    }
+
+    Ok(opts)
+}
+
+/// A broadcast running in the background, iterable for its progress events.
+#[pyclass]
+struct Broadcast {
+    receiver: Option<crossbeam_channel::Receiver<pushtx::Info>>,
+}
+
+#[pymethods]
+impl Broadcast {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<PyObject> {
+        let info = match &self.receiver {
+            Some(receiver) => py.allow_threads(|| receiver.recv().ok()),
+            None => None,
+        };
+
+        match info {
+            Some(info) => {
+                if matches!(info, pushtx::Info::Done(_)) {
+                    self.receiver = None;
+                }
+                event(py, info)
+            }
+            None => {
+                self.receiver = None;
+                Err(PyStopIteration::new_err(()))
+            }
+        }
+    }
+}
+
+/// Converts a progress event into a dict.
+fn event(py: Python<'_>, info: pushtx::Info) -> PyResult<PyObject> {
+    let dict = PyDict::new_bound(py);
+
+    match info {
+        pushtx::Info::ResolvingPeers => dict.set_item("type", "resolving_peers")?,
+        pushtx::Info::ResolvedPeers(count) => {
+            dict.set_item("type", "resolved_peers")?;
+            dict.set_item("count", count)?;
+        }
+        pushtx::Info::ConnectingToNetwork { tor_status } => {
+            dict.set_item("type", "connecting")?;
+            dict.set_item("tor_proxy", tor_status.map(|proxy| proxy.to_string()))?;
+        }
+        pushtx::Info::ConnectFailed { peer, cause } => {
+            dict.set_item("type", "connect_failed")?;
+            dict.set_item("peer", peer.to_string())?;
+            dict.set_item("cause", cause.to_string())?;
+        }
+        pushtx::Info::PeerPool(peers) => {
+            let peers: Vec<_> = peers.iter().map(ToString::to_string).collect();
+            dict.set_item("type", "peer_pool")?;
+            dict.set_item("peers", peers)?;
+        }
+        pushtx::Info::Broadcast { peer } => {
+            dict.set_item("type", "broadcast")?;
+            dict.set_item("peer", peer.to_string())?;
+        }
+        pushtx::Info::Confirmed { txid, block_hash } => {
+            dict.set_item("type", "confirmed")?;
+            dict.set_item("txid", txid.to_string())?;
+            dict.set_item("block_hash", block_hash.to_string())?;
+        }
+        pushtx::Info::Warning(warning) => {
+            dict.set_item("type", "warning")?;
+            dict.set_item("message", warning.to_string())?;
+        }
+        pushtx::Info::Progress { phase, fraction } => {
+            dict.set_item("type", "progress")?;
+            dict.set_item("phase", phase.to_string())?;
+            dict.set_item("fraction", fraction)?;
+        }
+        pushtx::Info::Done(Ok(report)) => {
+            let success: Vec<_> = report.success.iter().map(ToString::to_string).collect();
+            let already_known: Vec<_> = report
+                .already_known
+                .iter()
+                .map(ToString::to_string)
+                .collect();
+            let unsent: Vec<_> = report.unsent.iter().map(ToString::to_string).collect();
+            let wtxids = PyDict::new_bound(py);
+            for (txid, wtxid) in report.wtxids {
+                wtxids.set_item(txid.to_string(), wtxid.to_string())?;
+            }
+            let rejects = PyDict::new_bound(py);
+            for (txid, reason) in report.rejects {
+                rejects.set_item(txid.to_string(), reason.to_string())?;
+            }
+            let diagnoses = PyDict::new_bound(py);
+            for (txid, diagnosis) in report.diagnoses {
+                diagnoses.set_item(txid.to_string(), diagnosis.to_string())?;
+            }
+
+            let policy_met = report.policy_met;
+            let escalation_stage = report.escalation_stage;
+            let attestation = report.attestation;
+            let connect_failures = PyDict::new_bound(py);
+            for (cause, count) in report.connect_failures {
+                connect_failures.set_item(cause.to_string(), count)?;
+            }
+            let feefilters = PyDict::new_bound(py);
+            for (peer, rate) in &report.feefilters.peers {
+                feefilters.set_item(peer.to_string(), rate)?;
+            }
+            let feefilter_median = report.feefilters.median();
+            let feefilter_min = report.feefilters.min();
+            let latencies = PyDict::new_bound(py);
+            for (txid, latency) in report.latencies {
+                let acks: Vec<_> = latency.acks.iter().map(|d| d.as_secs_f64()).collect();
+                latencies.set_item(txid.to_string(), acks)?;
+            }
+
+            let interest = PyDict::new_bound(py);
+            for (txid, requests) in report.interest {
+                let requests: Vec<_> = requests
+                    .iter()
+                    .map(|interest| (interest.peer.to_string(), interest.after.as_secs_f64()))
+                    .collect();
+                interest.set_item(txid.to_string(), requests)?;
+            }
+
+            let report = PyDict::new_bound(py);
+            report.set_item("success", success)?;
+            report.set_item("wtxids", wtxids)?;
+            report.set_item("rejects", rejects)?;
+            report.set_item("already_known", already_known)?;
+            report.set_item("unsent", unsent)?;
+            report.set_item("policy_met", policy_met)?;
+            report.set_item("diagnoses", diagnoses)?;
+            report.set_item("latencies", latencies)?;
+            report.set_item("interest", interest)?;
+            report.set_item("escalation_stage", escalation_stage)?;
+            report.set_item("connect_failures", connect_failures)?;
+            report.set_item("feefilters", feefilters)?;
+            report.set_item("feefilter_median", feefilter_median)?;
+            report.set_item("feefilter_min", feefilter_min)?;
+            report.set_item("attestation", attestation)?;
+
+            dict.set_item("type", "done")?;
+            dict.set_item("report", report)?;
+        }
+        pushtx::Info::Done(Err(err)) => {
+            dict.set_item("type", "failed")?;
+            dict.set_item("reason", err.to_string())?;
+        }
+    }
+
+    Ok(dict.into())
+}
+
+#[pymodule]
+#[pyo3(name = "pushtx")]
+fn pushtx_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(broadcast, m)?)?;
+    m.add_class::<Broadcast>()?;
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use bitcoin::consensus::encode;
+use bitcoin::hashes::Hash;
+use bitcoin::p2p::message::{NetworkMessage, RawNetworkMessage};
+use bitcoin::p2p::message_blockdata::Inventory;
+use bitcoin::p2p::{Address, ServiceFlags};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+/// Serializes a mainnet message the way a peer would send it.
+fn wire(message: NetworkMessage) -> Vec<u8> {
+    encode::serialize(&RawNetworkMessage::new(
+        bitcoin::Network::Bitcoin.magic(),
+        message,
+    ))
+}
+
+/// A transaction with a few inputs and outputs, about the size of a typical payment.
+fn transaction() -> bitcoin::Transaction {
+    let input = bitcoin::TxIn {
+        previous_output: bitcoin::OutPoint::new(bitcoin::Txid::all_zeros(), 0),
+        witness: bitcoin::Witness::from_slice(&[vec![0_u8; 72], vec![0_u8; 33]]),
+        ..Default::default()
+    };
+    let output = bitcoin::TxOut {
+        value: bitcoin::Amount::from_sat(50_000),
+        script_pubkey: bitcoin::ScriptBuf::from_bytes(vec![0_u8; 22]),
+    };
+    bitcoin::Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: vec![input; 3],
+        output: vec![output; 2],
+    }
+}
+
+fn messages() -> Vec<(&'static str, Vec<u8>)> {
+    let txids = (0..1000_u32).map(|i| {
+        let mut bytes = [0_u8; 32];
+        bytes[..4].copy_from_slice(&i.to_le_bytes());
+        Inventory::WTx(bitcoin::Wtxid::from_byte_array(bytes))
+    });
+    let address = Address::new(&([10, 0, 0, 1], 8333).into(), ServiceFlags::NETWORK);
+
+    vec![
+        ("inv", wire(NetworkMessage::Inv(txids.collect()))),
+        ("tx", wire(NetworkMessage::Tx(transaction()))),
+        ("addr", wire(NetworkMessage::Addr(vec![(0, address); 1000]))),
+        ("ping", wire(NetworkMessage::Ping(42))),
+        // decoded by the general decoder
+        ("feefilter", wire(NetworkMessage::FeeFilter(1000))),
+        // skipped without decoding
+        ("sendheaders", wire(NetworkMessage::SendHeaders)),
+    ]
+}
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    for (command, bytes) in messages() {
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(command), &bytes, |b, bytes| {
+            b.iter(|| pushtx::bench::decode(bytes).expect("valid message"))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
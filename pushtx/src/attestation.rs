@@ -0,0 +1,149 @@
+//! Evidence of a broadcast for auditing purposes: who was contacted, what was sent to whom and
+//! when, and which peers announced the transactions back. Serialized as JSON and optionally
+//! signed.
+
+use std::collections::HashSet;
+use std::time::SystemTime;
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::p2p::message_network::VersionMessage;
+use bitcoin::secp256k1::{Keypair, Message, Secp256k1};
+
+use crate::{net, Attestation, Network};
+
+/// Collects broadcast events while an attestation is requested and does nothing otherwise.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    enabled: bool,
+    /// When the broadcast started, in milliseconds since the Unix epoch.
+    started_at: u128,
+    /// The resolved peer pool, as JSON strings.
+    pool: Vec<String>,
+    /// The completed handshakes, as JSON objects.
+    handshakes: Vec<String>,
+    /// The transaction messages sent, as JSON objects.
+    sent: Vec<String>,
+    /// The acks observed, as JSON objects.
+    acks: Vec<String>,
+}
+
+impl Recorder {
+    pub fn new(attestation: &Attestation) -> Self {
+        Self {
+            enabled: !matches!(attestation, Attestation::Off),
+            started_at: unix_millis(),
+            ..Default::default()
+        }
+    }
+
+    /// Records the peers that may be contacted.
+    pub fn pool(&mut self, pool: &[net::Service]) {
+        if self.enabled {
+            self.pool = pool.iter().map(|peer| format!("\"{peer}\"")).collect();
+        }
+    }
+
+    /// Records a completed handshake.
+    pub fn handshake(&mut self, peer: net::Service, version: &VersionMessage) {
+        if self.enabled {
+            self.handshakes.push(format!(
+                "{{\"peer\":\"{peer}\",\"at\":{},\"version\":{},\"services\":{},\
+                 \"user_agent\":\"{}\",\"start_height\":{}}}",
+                unix_millis(),
+                version.version,
+                version.services.to_u64(),
+                escape(&version.user_agent),
+                version.start_height
+            ));
+        }
+    }
+
+    /// Records a transaction sent or announced to a peer through the given command.
+    pub fn sent(&mut self, peer: net::Service, command: &str, txid: bitcoin::Txid) {
+        if self.enabled {
+            self.sent.push(format!(
+                "{{\"peer\":\"{peer}\",\"at\":{},\"command\":\"{command}\",\"txid\":\"{txid}\"}}",
+                unix_millis()
+            ));
+        }
+    }
+
+    /// Records a peer announcing or serving one of the transactions.
+    pub fn ack(&mut self, peer: net::Service, txid: bitcoin::Txid) {
+        if self.enabled {
+            self.acks.push(format!(
+                "{{\"peer\":\"{peer}\",\"at\":{},\"txid\":\"{txid}\"}}",
+                unix_millis()
+            ));
+        }
+    }
+
+    /// Serializes the evidence and signs it if a key was given. Returns `None` if no attestation
+    /// was requested.
+    pub fn finish(
+        self,
+        attestation: &Attestation,
+        network: Network,
+        txids: impl Iterator<Item = bitcoin::Txid>,
+        success: &HashSet<crate::Txid>,
+    ) -> Option<String> {
+        if !self.enabled {
+            return None;
+        }
+
+        let network = bitcoin::Network::from(network);
+        let txids: Vec<_> = txids.map(|txid| format!("\"{txid}\"")).collect();
+        let success: Vec<_> = success.iter().map(|txid| format!("\"{txid}\"")).collect();
+        let attestation_json = format!(
+            "{{\"version\":1,\"network\":\"{network}\",\"started_at\":{},\"finished_at\":{},\
+             \"txids\":[{}],\"success\":[{}],\"peer_pool\":[{}],\"handshakes\":[{}],\
+             \"sent\":[{}],\"acks\":[{}]}}",
+            self.started_at,
+            unix_millis(),
+            txids.join(","),
+            success.join(","),
+            self.pool.join(","),
+            self.handshakes.join(","),
+            self.sent.join(","),
+            self.acks.join(",")
+        );
+
+        match attestation {
+            Attestation::Signed(key) => {
+                let secp = Secp256k1::signing_only();
+                let keypair = Keypair::from_secret_key(&secp, key);
+                let pubkey = hex::encode(keypair.x_only_public_key().0.serialize());
+                let digest = sha256::Hash::hash(attestation_json.as_bytes()).to_byte_array();
+                let sig = secp.sign_schnorr_no_aux_rand(&Message::from_digest(digest), &keypair);
+                Some(format!(
+                    "{{\"attestation\":{attestation_json},\
+                     \"pubkey\":\"{pubkey}\",\"sig\":\"{sig}\"}}"
+                ))
+            }
+            Attestation::Unsigned | Attestation::Off => {
+                Some(format!("{{\"attestation\":{attestation_json}}}"))
+            }
+        }
+    }
+}
+
+fn unix_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
+}
+
+/// Escapes a string for use inside a JSON string literal.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,10 @@
+//! Entry points for the benchmarks into code that is otherwise private.
+
+use bitcoin::p2p::message::NetworkMessage;
+
+/// Decodes a single P2P message from the start of `buffer` the way the p2p reactor does, and
+/// returns it along with the number of bytes it took up. Returns `None` if the buffer does not
+/// start with a complete and valid message.
+pub fn decode(buffer: &[u8]) -> Option<(NetworkMessage, usize)> {
+    crate::p2p::decode(buffer).map(|(message, consumed)| (message.into_payload(), consumed))
+}
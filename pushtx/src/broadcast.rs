@@ -1,292 +1,850 @@
 use std::collections::{HashMap, HashSet};
-use std::net::{Ipv4Addr, SocketAddr};
+use std::net::SocketAddr;
+use std::ops::ControlFlow;
 use std::time;
 use std::time::Duration;
 
-use crate::handshake::{self, Handshake};
+use crate::attestation;
+use crate::diagnosis::Evidence;
+use crate::latency;
+use crate::local_node;
 use crate::p2p::{self, Outbox, Receiver, Sender};
-use crate::{net, seeds, Error, FindPeerStrategy, Info, Opts, Report, Transaction};
-use bitcoin::p2p::message::NetworkMessage;
+use crate::redact;
+use crate::{
+    net, policy, seeds, ConnectFailure, Error, FindPeerStrategy, Info, LocalNodeProbe, Opts,
+    Reactor, Report, TorMode, Transaction, Warning,
+};
 use bitcoin::p2p::message_blockdata::Inventory;
 use crossbeam_channel::RecvTimeoutError;
 
+mod machine;
+
+use machine::{Batch, Machine};
+
+/// The `NODE_NETWORK` service bit, advertised by nodes that serve the full block chain.
+const NODE_NETWORK: u64 = 1;
+
 /// Transaction broadcast runner. Needs to be constructed and started to run.
 pub(crate) struct Runner {
     info_tx: crossbeam_channel::Sender<Info>,
     tx: Vec<Transaction>,
     opts: Opts,
+    reactor: Option<Reactor>,
 }
 
 impl Runner {
-    /// Constructs a new broadcast runner without actually running it.
+    /// Constructs a new broadcast runner without actually running it. The runner uses its own p2p
+    /// reactor unless a shared one is provided.
     /// The receiver allows the caller to follow the broadcast progress.
-    pub fn new(tx: Vec<Transaction>, opts: Opts) -> (Self, crossbeam_channel::Receiver<Info>) {
+    pub fn new(
+        tx: Vec<Transaction>,
+        opts: Opts,
+        reactor: Option<Reactor>,
+    ) -> (Self, crossbeam_channel::Receiver<Info>) {
         let (info_tx, info_rx) = crossbeam_channel::unbounded();
-        let runner = Self { info_tx, tx, opts };
+        let runner = Self {
+            info_tx,
+            tx,
+            opts,
+            reactor,
+        };
 
         (runner, info_rx)
     }
 
     /// Runs the broadcast in a background thread.
     pub fn run(self) {
-        std::thread::spawn(move || {
-            let (must_use_tor, proxy) = match self.opts.use_tor {
-                crate::TorMode::No => (false, None),
-                crate::TorMode::BestEffort => (false, detect_tor_proxy()),
-                crate::TorMode::Must => (true, detect_tor_proxy()),
-            };
+        let reactor = self.reactor.clone();
+        self.run_with(move |proxy, opts| match reactor {
+            Some(reactor) => Ok(p2p::shared_client(
+                &reactor.shared,
+                opts.network,
+                opts.protocol_version,
+                &opts.handshake,
+                opts.max_bandwidth,
+                opts.rng(),
+            )),
+            None => p2p::client(
+                proxy,
+                opts.network,
+                opts.protocol_version,
+                &opts.handshake,
+                opts.max_bandwidth,
+                opts.max_message_size,
+                opts.rng(),
+            ),
+        });
+    }
 
-            if self.opts.dry_run {
-                log::warn!("dry run is enabled, broadcast is simulated");
+    /// Runs the broadcast in a background thread on top of a p2p client created by `connect`,
+    /// which is given the Tor proxies in use.
+    pub fn run_with<C, P, E>(
+        self,
+        connect: impl FnOnce(Option<p2p::ProxyPool>, &Opts) -> std::io::Result<C> + Send + 'static,
+    ) where
+        C: Outbox<P> + Receiver<P, E> + Sender,
+        P: p2p::Peerlike,
+        E: Into<p2p::Event<P>>,
+    {
+        std::thread::spawn(move || {
+            let info_tx = self.info_tx.clone();
+            let monitor = self.opts.monitor.clone();
+            let outcome = self.broadcast(connect);
+            if let Some(monitor) = monitor {
+                monitor.finish();
             }
-
-            log::info!("Tor proxy status: {:?}", proxy);
-            if proxy.is_none() && must_use_tor {
-                log::error!("Tor usage required but local proxy not found");
-                let _ = self.info_tx.send(Info::Done(Err(Error::TorNotFound)));
-                return;
+            match outcome {
+                Some(result) => {
+                    if let Err(err) = &result {
+                        log::error!("broadcast failed: {err}");
+                    }
+                    let _ = info_tx.send(Info::Done(result));
+                }
+                None => log::info!("info receiver dropped, broadcast abandoned"),
             }
+        });
+    }
 
-            let client = p2p::client(proxy, self.opts.network, self.opts.ua);
-            let mut state = HashMap::new();
+    /// Performs the broadcast on the current thread and returns the outcome. Returns `None` if
+    /// the caller stopped listening, in which case the broadcast is abandoned early.
+    fn broadcast<C, P, E>(
+        self,
+        connect: impl FnOnce(Option<p2p::ProxyPool>, &Opts) -> std::io::Result<C>,
+    ) -> Option<Result<Report, Error>>
+    where
+        C: Outbox<P> + Receiver<P, E> + Sender,
+        P: p2p::Peerlike,
+        E: Into<p2p::Event<P>>,
+    {
+        let Self {
+            info_tx,
+            tx,
+            opts,
+            reactor,
+        } = self;
+        // sending fails only if the caller dropped the receiver
+        let notify = |info: Info| info_tx.send(info).ok();
+
+        if let Err(err) = validate_opts(&tx, &opts) {
+            log::error!("{err}");
+            return Some(Err(err));
+        }
 
-            let _ = self.info_tx.send(Info::ResolvingPeers);
-            let networks: &[net::Network] = match proxy {
-                Some(_) => &[net::Network::Ipv4, net::Network::Ipv6, net::Network::TorV3],
-                None => &[net::Network::Ipv4],
-            };
-            let addressbook =
-                create_node_pool(self.opts.find_peer_strategy, self.opts.network, networks);
-            let _ = self.info_tx.send(Info::ResolvedPeers(addressbook.len()));
+        if opts.dry_run {
+            log::warn!("dry run is enabled, broadcast is simulated");
+        }
 
-            let _ = self
-                .info_tx
-                .send(Info::ConnectingToNetwork { tor_status: proxy });
+        if let Some(monitor) = &opts.monitor {
+            monitor.start(opts.clock.clone());
+        }
 
-            let outbox = &client;
-            for addr in addressbook.iter().take(self.opts.target_peers.into()) {
-                outbox.connect(*addr);
+        #[cfg(feature = "consensus-validation")]
+        if !opts.prevouts.is_empty() {
+            if let Err(err) = crate::validation::validate(&tx, &opts.prevouts) {
+                log::error!("{err}");
+                return Some(Err(err));
             }
-            outbox.send().unwrap();
-
-            let tx_map: HashMap<_, _> = self.tx.into_iter().map(|tx| (tx.0.txid(), tx.0)).collect();
-            let mut acks = HashSet::new();
-            let mut selected: Option<BroadcastPeer<_>> = None;
-
-            let start = time::Instant::now();
-            let mut rejects = HashMap::new();
-
-            loop {
-                let mut need_replacements = 0;
-                let p2p = client.receiver();
-
-                match p2p.recv_timeout(Duration::from_secs(1)).map(Into::into) {
-                    Ok(p2p::Event::ConnectedTo { target, result }) => match result {
-                        Ok(id) => {
-                            log::info!("connected: peer @ {target}");
-                            state.insert(id, Peer::Handshaking(target, Handshake::default()));
-                            outbox.version(id);
-                        }
-                        Err(_) => {
-                            log::info!("failed to connect to peer @ {target}");
-                            need_replacements += 1;
-                        }
-                    },
-
-                    Ok(p2p::Event::Message { peer, message }) => match state.get_mut(&peer) {
-                        Some(Peer::Handshaking(s, h)) => match h.update(message.payload().into()) {
-                            handshake::Event::Wait => {}
-                            handshake::Event::SendVerack => outbox.verack(peer),
-                            handshake::Event::Violation => {
-                                log::warn!("handshake violated: peer @ {}", s);
-                                state.remove(&peer);
-                                need_replacements += 1;
-                            }
-                            handshake::Event::Done { .. } => {
-                                let service = *s;
-                                log::info!("handshake complete: peer @ {}", s);
-                                state.insert(peer, Peer::Ready { service });
-                            }
-                        },
-                        Some(Peer::Ready { service }) => match message.payload() {
-                            NetworkMessage::Inv(inv) => {
-                                for inv in inv {
-                                    if let Inventory::Transaction(wanted_txid) = inv {
-                                        if tx_map.contains_key(wanted_txid)
-                                            && selected.as_ref().map(|s| s.id) != Some(peer)
-                                        {
-                                            log::info!(
-                                                "txid seen: peer @ {}: {}",
-                                                service,
-                                                wanted_txid
-                                            );
-                                            acks.insert(*wanted_txid);
-                                        }
-                                    }
-                                }
-                            }
-                            NetworkMessage::Reject(reject) => {
-                                log::warn!(
-                                    "reject: peer @ {}: type={}, code={:?}, reason={}",
-                                    service,
-                                    reject.message,
-                                    reject.ccode,
-                                    reject.reason
-                                );
-                                if reject.message == "tx" {
-                                    let txid = crate::Txid(reject.hash.into());
-                                    rejects.insert(txid, reject.reason.to_string());
-                                }
-                            }
-                            _ => {}
-                        },
-                        None => panic!("phantom peer {}", peer),
-                    },
-
-                    Ok(p2p::Event::Disconnected { peer, reason }) => match state.get_mut(&peer) {
-                        Some(Peer::Ready { service } | Peer::Handshaking(service, _)) => {
-                            log::info!("disconnected: peer @ {}, reason: {:?}", service, reason);
-                            if selected.as_ref().map(|s| s.id) == Some(peer) {
-                                selected = None;
-                            }
-                            need_replacements += 1;
-                            state.remove(&peer);
-                        }
-                        None => panic!("phantom peer {}", peer),
-                    },
-
-                    Err(RecvTimeoutError::Disconnected) => panic!("p2p reactor disconnected"),
-
-                    _ => {}
-                }
+        }
 
-                match &selected {
-                    Some(selected) if selected.is_stale() => {
-                        log::warn!("rotating broadcast peer");
-                        outbox.disconnect(selected.id);
-                    }
-                    _ => {}
-                }
+        #[cfg(feature = "rpc")]
+        if let Some(node) = opts.rpc.as_ref().filter(|node| node.preflight) {
+            if let Err(err) = crate::rpc::preflight(node, &tx) {
+                log::error!("{err}");
+                return Some(Err(err));
+            }
+        }
 
-                if selected.is_none() {
-                    let new_selected = state
-                        .iter()
-                        .filter_map(|(id, p)| match p {
-                            Peer::Handshaking(_, _) => None,
-                            Peer::Ready { service } => Some((*service, *id)),
-                        })
-                        .next();
-
-                    if let Some((service, id)) = new_selected {
-                        log::info!("selected broadcast peer @ {service}");
-                        selected = Some(BroadcastPeer::new(id));
-                        for tx in tx_map.values() {
-                            log::info!("broadcasting to {}", service);
-                            if !self.opts.dry_run {
-                                outbox.tx(id, tx.to_owned());
-                            }
-                        }
-                        let _ = self.info_tx.send(Info::Broadcast {
-                            peer: service.to_string(),
-                        });
-                    }
+        let proxies = match &reactor {
+            Some(reactor) => reactor.proxies.clone(),
+            None => match tor_proxy(&opts.use_tor, &opts.socks_proxies) {
+                Ok(proxies) => proxies,
+                Err(err) => return Some(Err(err)),
+            },
+        };
+        let proxy = proxies.as_ref().map(p2p::ProxyPool::current);
+        // the proxy in use changes if it fails over mid-run
+        let current_proxy = || proxies.as_ref().map(p2p::ProxyPool::current);
+
+        let dropped = match find_conflicts(&tx, &opts.replacements) {
+            Ok(dropped) => dropped,
+            Err(err) => {
+                log::error!("{err}");
+                return Some(Err(err));
+            }
+        };
+
+        let mut tx_map = HashMap::with_capacity(tx.len());
+        // peers that negotiated wtxid relay announce and request transactions by wtxid
+        let mut wtxids = HashMap::with_capacity(tx.len());
+        let mut evidence = Evidence::default();
+        let mut attest = attestation::Recorder::new(&opts.attestation);
+        // the outputs spent by the batch, to spot foreign transactions that spend them too
+        let mut spent = HashMap::new();
+        for tx in tx {
+            let txid = tx.0.txid();
+            if let Some(replacement) = dropped.get(&txid) {
+                log::warn!(
+                    "dropping {} in favor of its replacement {}",
+                    redact::r(txid),
+                    redact::r(replacement)
+                );
+                notify(Info::Warning(Warning::ConflictDropped {
+                    dropped: crate::Txid(txid),
+                    replacement: crate::Txid(*replacement),
+                }))?;
+                #[cfg(feature = "zeroize")]
+                crate::wipe::transaction(tx.0);
+                continue;
+            }
+            wtxids.insert(tx.0.wtxid(), txid);
+            if opts.conflict_alerts {
+                spent.extend(tx.0.input.iter().map(|input| (input.previous_output, txid)));
+            }
+            evidence.transaction(&tx.0);
+            let violations = policy::check_outputs(&tx.0);
+            let shared = p2p::SharedTx::new(&tx.0);
+            #[cfg(feature = "zeroize")]
+            crate::wipe::transaction(tx.0);
+            if let Some(duplicate) = tx_map.insert(txid, shared) {
+                #[cfg(feature = "zeroize")]
+                crate::wipe::shared_tx(duplicate);
+                #[cfg(not(feature = "zeroize"))]
+                drop(duplicate);
+                log::warn!("duplicate transaction in batch: {}", redact::r(txid));
+                notify(Info::Warning(Warning::DuplicateTx(crate::Txid(txid))))?;
+            } else {
+                for (vout, violation) in violations {
+                    log::warn!(
+                        "non-standard output {}:{vout}: {violation}",
+                        redact::r(txid)
+                    );
+                    notify(Info::Warning(Warning::NonStandardOutput {
+                        txid: crate::Txid(txid),
+                        vout,
+                        violation,
+                    }))?;
+                    evidence.non_standard(txid);
                 }
+            }
+        }
 
-                let elapsed = time::Instant::now() - start;
+        // seeded apart from the client so that the two do not draw the same numbers
+        let mut rng = fastrand::Rng::with_seed(opts.rng().u64(..) ^ u64::MAX);
+
+        notify(Info::ResolvingPeers)?;
+        let pool = create_node_pool(&opts, proxy.is_some(), &mut rng);
+        let (mut addressbook, fallback) = match pool {
+            Ok(pool) => pool,
+            Err(err) => return Some(Err(err)),
+        };
+        if let Some(warning) = fallback {
+            log::warn!("{warning}");
+            notify(Info::Warning(warning))?;
+        }
+        // dialed first and preferred as a broadcast peer
+        let local_node = match proxy {
+            Some(_) if opts.local_node != LocalNodeProbe::Off => {
+                log::info!("not looking for a local node, connecting through Tor");
+                None
+            }
+            Some(_) => None,
+            None => local_node::detect(opts.local_node, opts.network),
+        };
+        if let Some(local_node) = local_node {
+            addressbook.retain(|node| *node != local_node);
+            addressbook.insert(0, local_node);
+        }
+        notify(Info::ResolvedPeers(addressbook.len()))?;
+        notify(Info::PeerPool(addressbook.clone()))?;
+        attest.pool(&addressbook);
+        notify(Info::ConnectingToNetwork { tor_status: proxy })?;
+        if let Some(monitor) = &opts.monitor {
+            monitor.phase(crate::Phase::Connecting);
+        }
 
-                if self.opts.dry_run && elapsed.as_secs() > 3 {
-                    acks.extend(tx_map.keys());
-                }
+        let client = match connect(proxies.clone(), &opts) {
+            Ok(client) => client,
+            Err(err) => return Some(Err(err.into())),
+        };
+        let outbox = &client;
+        #[cfg(any(feature = "electrum", feature = "rpc"))]
+        let wtxid_of: HashMap<_, _> = wtxids
+            .iter()
+            .map(|(wtxid, txid)| (crate::Txid(*txid), *wtxid))
+            .collect();
+        let batch = Batch {
+            tx_map,
+            wtxids,
+            spent,
+            evidence,
+            attest,
+        };
+        let mut machine = Machine::new(
+            opts.clone(),
+            batch,
+            addressbook,
+            local_node,
+            proxies.clone(),
+            rng,
+        );
+        machine.dial(outbox);
+
+        // how long to wait for the next event, which is until the next deadline at most
+        let mut wait = Duration::ZERO;
+
+        let result = loop {
+            #[cfg(feature = "chaos")]
+            machine.release(outbox);
+            if let Err(err) = client.send() {
+                break Some(Err(err.into()));
+            }
 
-                if acks.len() == tx_map.len() || elapsed >= self.opts.max_time {
-                    log::info!("broadcast stop");
-                    break;
+            let event: Option<p2p::Event<P>> = match client.receiver().recv_timeout(wait) {
+                Ok(event) => Some(event.into()),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => {
+                    break Some(Err(Error::Internal("p2p reactor disconnected".to_string())))
                 }
+            };
+            if let Some(event) = event {
+                if let ControlFlow::Break(result) = machine.handle(event, outbox, &notify) {
+                    break result;
+                }
+            }
+            if let ControlFlow::Break(result) = machine.tick(outbox, &notify) {
+                break result;
+            }
 
-                for _ in 0..need_replacements {
-                    let replacement = fastrand::choice(addressbook.iter()).unwrap();
-                    outbox.connect(*replacement);
-                    log::info!("picked replacement peer @ {replacement}");
+            wait = machine.wait(client.has_pending());
+        };
+        #[allow(unused_variables, unused_mut)]
+        let (tx_map, mut rng) = machine.close();
+
+        #[cfg(any(feature = "electrum", feature = "rpc"))]
+        let result = match result {
+            Some(Err(err @ (Error::AllConnectionsFailed { .. } | Error::ConnectTimeout)))
+                if !opts.dry_run && has_fallback(&opts) =>
+            {
+                log::warn!("could not reach the p2p network: {err}");
+                Some(Ok(unreached(wtxid_of)))
+            }
+            result => result,
+        };
+        #[cfg(feature = "rpc")]
+        let result = match (result, opts.rpc.as_ref().filter(|node| node.fallback)) {
+            (Some(Ok(mut report)), Some(node)) if !opts.dry_run => {
+                let failed: Vec<_> = tx_map
+                    .iter()
+                    .map(|(txid, tx)| (crate::Txid(*txid), tx.payload()))
+                    .filter(|(txid, _)| {
+                        !report.success.contains(txid)
+                            && !report.already_known.contains(txid)
+                            && !report.rejects.contains_key(txid)
+                    })
+                    .collect();
+                if !failed.is_empty() {
+                    log::warn!("submitting {} txs to the node", failed.len());
+                    let (accepted, refused) = crate::rpc::submit(node, &failed);
+                    report.rejects.extend(refused);
+                    report.rpc_submitted = Some(accepted);
+                }
+                Some(Ok(report))
+            }
+            (result, _) => result,
+        };
+        #[cfg(feature = "electrum")]
+        let result = match (result, &opts.electrum) {
+            (Some(Ok(mut report)), Some(fallback))
+                if !opts.dry_run
+                    && report.success.is_empty()
+                    && report.already_known.is_empty() =>
+            {
+                let failed: Vec<_> = tx_map
+                    .iter()
+                    .map(|(txid, tx)| (crate::Txid(*txid), tx.payload()))
+                    .filter(|(txid, _)| {
+                        !report.rejects.contains_key(txid) && !rpc_accepted(&report, txid)
+                    })
+                    .collect();
+                if !failed.is_empty() {
+                    log::warn!("publishing {} txs through electrum servers", failed.len());
+                    report.electrum_published = Some(crate::electrum::publish(
+                        fallback,
+                        &failed,
+                        current_proxy(),
+                        &mut rng,
+                    ));
+                }
+                Some(Ok(report))
+            }
+            (result, _) => result,
+        };
+
+        #[cfg(feature = "nostr")]
+        let result = match (result, &opts.nostr) {
+            (Some(Ok(mut report)), Some(fallback)) if !opts.dry_run => {
+                let failed: Vec<_> = tx_map
+                    .iter()
+                    .map(|(txid, tx)| (crate::Txid(*txid), tx.payload()))
+                    .filter(|(txid, _)| {
+                        !report.success.contains(txid)
+                            && !report.rejects.contains_key(txid)
+                            && !rpc_accepted(&report, txid)
+                    })
+                    .collect();
+                if !failed.is_empty() {
+                    log::warn!("publishing {} txs to nostr relays", failed.len());
+                    report.nostr_published = Some(crate::nostr::publish(fallback, &failed));
                 }
-                client.send().unwrap();
+                Some(Ok(report))
             }
+            (result, _) => result,
+        };
 
-            client.shutdown().join().unwrap().unwrap();
-            let report = Ok(Report {
-                success: acks.into_iter().map(crate::Txid).collect(),
-                rejects,
-            });
-            let _ = self.info_tx.send(Info::Done(report));
-        });
+        // messages still queued in the reactor hold the transactions until it is gone
+        if let Err(err) = client.shutdown() {
+            log::warn!("p2p reactor shut down with an error: {err}");
+        }
+
+        #[cfg(feature = "zeroize")]
+        tx_map.into_values().for_each(crate::wipe::shared_tx);
+
+        #[cfg(feature = "explorer")]
+        let result = match (result, &opts.explorer) {
+            (Some(Ok(mut report)), Some(check)) if !opts.dry_run => {
+                log::info!("cross-checking with explorer @ {}", check.url);
+                let seen =
+                    crate::explorer::cross_check(check, &report.success, current_proxy(), &mut rng);
+                report.explorer_seen = Some(seen);
+                Some(Ok(report))
+            }
+            (result, _) => result,
+        };
+
+        result
+    }
+}
+
+/// Whether a fallback is configured that can still get the transactions out when no peer was
+/// ever reached.
+#[cfg(any(feature = "electrum", feature = "rpc"))]
+fn has_fallback(opts: &Opts) -> bool {
+    #[cfg(feature = "electrum")]
+    if opts.electrum.is_some() {
+        return true;
     }
+    #[cfg(feature = "rpc")]
+    if opts.rpc.as_ref().is_some_and(|node| node.fallback) {
+        return true;
+    }
+    false
 }
 
-/// Peer status.
-enum Peer {
-    /// Currently handshaking.
-    Handshaking(net::Service, Handshake),
-    /// Handshake established, ready for interaction.
-    Ready { service: net::Service },
+/// Whether the node already accepted the transaction through the RPC fallback, in which case the
+/// fallbacks after it leave the transaction alone.
+#[cfg(any(feature = "electrum", feature = "nostr"))]
+fn rpc_accepted(report: &Report, txid: &crate::Txid) -> bool {
+    report
+        .rpc_submitted
+        .as_ref()
+        .is_some_and(|accepted| accepted.contains(txid))
 }
 
-/// A single peer that we have selected for our transaction broadcast.
-struct BroadcastPeer<P: p2p::Peerlike> {
-    /// The id of the peer.
-    id: P,
-    /// The time the broadcast took place.
-    when: std::time::Instant,
+/// The report of a broadcast that never reached a peer, so that a fallback has one to fill in.
+#[cfg(any(feature = "electrum", feature = "rpc"))]
+fn unreached(wtxids: HashMap<crate::Txid, bitcoin::Wtxid>) -> Report {
+    Report {
+        success: HashSet::new(),
+        unsent: wtxids.keys().copied().collect(),
+        timed_out: HashSet::new(),
+        wtxids,
+        rejects: HashMap::new(),
+        already_known: HashSet::new(),
+        policy_met: false,
+        diagnoses: HashMap::new(),
+        explorer_seen: None,
+        nostr_published: None,
+        electrum_published: None,
+        rpc_submitted: None,
+        latencies: HashMap::new(),
+        interest: HashMap::new(),
+        connect_failures: HashMap::new(),
+        feefilters: crate::FeeFilters::default(),
+        escalation_stage: None,
+        attestation: None,
+    }
 }
 
-impl<P: p2p::Peerlike> BroadcastPeer<P> {
-    fn new(id: P) -> Self {
-        Self {
-            id,
-            when: std::time::Instant::now(),
+/// Finds transactions in the batch that spend the same output as another one. Returns the ones to
+/// drop, each mapped to the replacement it conflicts with. A conflict that the replacements do not
+/// settle is an error.
+fn find_conflicts(
+    txs: &[Transaction],
+    replacements: &HashSet<crate::Txid>,
+) -> Result<HashMap<bitcoin::Txid, bitcoin::Txid>, Error> {
+    let mut spenders = HashMap::new();
+    let mut dropped = HashMap::new();
+
+    for tx in txs {
+        let txid = tx.0.txid();
+        for input in &tx.0.input {
+            let spender = spenders.entry(input.previous_output).or_insert(txid);
+            if *spender == txid {
+                continue;
+            }
+
+            let (other, this) = (crate::Txid(*spender), crate::Txid(txid));
+            match (replacements.contains(&other), replacements.contains(&this)) {
+                (true, false) => {
+                    dropped.insert(txid, *spender);
+                }
+                (false, true) => {
+                    dropped.insert(*spender, txid);
+                    *spender = txid;
+                }
+                _ => return Err(Error::ConflictingTxs(other, this)),
+            }
         }
     }
-    /// Whether the peer is stale and should be rotated.
-    fn is_stale(&self) -> bool {
-        std::time::Instant::now() - self.when > Duration::from_secs(10)
+
+    Ok(dropped)
+}
+
+/// Finds the Tor proxies to use according to the Tor mode, if any. Configured proxies take
+/// precedence over the ones detected locally.
+pub(crate) fn tor_proxy(
+    mode: &TorMode,
+    configured: &[SocketAddr],
+) -> Result<Option<p2p::ProxyPool>, Error> {
+    let must_use_tor = matches!(mode, TorMode::Must);
+    let candidates = match mode {
+        TorMode::No => Vec::new(),
+        _ if !configured.is_empty() => configured.to_vec(),
+        _ => local_tor_proxies(),
+    };
+
+    let proxies = p2p::ProxyPool::new(candidates);
+    log::info!(
+        "Tor proxy status: {:?}",
+        proxies.as_ref().map(p2p::ProxyPool::current)
+    );
+    if proxies.is_none() && must_use_tor {
+        log::error!("Tor usage required but no proxy is reachable");
+        Err(Error::TorNotFound)
+    } else {
+        Ok(proxies)
     }
 }
 
-/// Tries to detect a local Tor proxy on the usual ports.
-fn detect_tor_proxy() -> Option<SocketAddr> {
-    fn is_port_reachable(addr: SocketAddr) -> bool {
-        std::net::TcpStream::connect(addr).is_ok()
+/// Where local Tor proxies usually listen, in order of preference.
+#[cfg(feature = "tor")]
+fn local_tor_proxies() -> Vec<SocketAddr> {
+    use std::net::Ipv4Addr;
+
+    // Tor daemon has a SOCKS proxy on port 9050, Tor browser on port 9150
+    vec![
+        (Ipv4Addr::LOCALHOST, 9050).into(),
+        (Ipv4Addr::LOCALHOST, 9150).into(),
+    ]
+}
+
+/// Tor support is compiled out, so no proxy is ever detected.
+#[cfg(not(feature = "tor"))]
+fn local_tor_proxies() -> Vec<SocketAddr> {
+    Vec::new()
+}
+
+/// Reports the transaction announcements in an `inv` message. Nobody listening is not an error.
+fn log_invs(
+    inv_log: &crossbeam_channel::Sender<crate::InvObservation>,
+    peer: net::Service,
+    inv: &[Inventory],
+    at: time::SystemTime,
+) {
+    for inv in inv {
+        let tx = match inv {
+            Inventory::Transaction(txid) => crate::ObservedTx::Txid(crate::Txid(*txid)),
+            Inventory::WTx(wtxid) => crate::ObservedTx::Wtxid(*wtxid),
+            _ => continue,
+        };
+        let _ = inv_log.send(crate::InvObservation { peer, tx, at });
+    }
+}
+
+/// Reports a connection that is over. Nobody listening is not an error.
+fn log_connection(
+    connection_log: &Option<crossbeam_channel::Sender<crate::ConnectionRecord>>,
+    clock: &dyn crate::Clock,
+    peer: net::Service,
+    dialed_at: time::SystemTime,
+    connected_at: Option<time::SystemTime>,
+    proxy: Option<SocketAddr>,
+    outcome: crate::ConnectionOutcome,
+) {
+    if let Some(connection_log) = connection_log {
+        let _ = connection_log.send(crate::ConnectionRecord {
+            peer,
+            dialed_at,
+            connected_at,
+            ended_at: clock.system_now(),
+            proxy,
+            outcome,
+        });
+    }
+}
+
+/// How many peers acked a transaction as far as `Opts::success_policy` is concerned.
+fn ack_count(
+    txid: &bitcoin::Txid,
+    acks: &HashSet<bitcoin::Txid>,
+    already_known: &HashSet<bitcoin::Txid>,
+    latency: &latency::Tracker,
+) -> usize {
+    if already_known.contains(txid) {
+        usize::MAX
+    } else if acks.contains(txid) {
+        // acks of transactions that had not gone out yet are not tracked, but they are acks
+        latency.ack_count(txid).max(1)
+    } else {
+        0
     }
+}
 
-    // Tor daemon has a SOCKS proxy on port 9050
-    if is_port_reachable((Ipv4Addr::LOCALHOST, 9050).into()) {
-        return Some((Ipv4Addr::LOCALHOST, 9050).into());
+/// Catches options that would otherwise only fail once the broadcast times out.
+fn validate_opts(tx: &[Transaction], opts: &Opts) -> Result<(), Error> {
+    let no_custom_peers = match &opts.find_peer_strategy {
+        FindPeerStrategy::Custom(peers) => peers.is_empty(),
+        _ => false,
+    };
+
+    let problem = if tx.is_empty() {
+        "no transactions to broadcast"
+    } else if opts.broadcast_peers == 0 {
+        "broadcast_peers is zero"
+    } else if opts.monitor_peers == 0 {
+        "monitor_peers is zero"
+    } else if opts.max_connection_attempts == 0 {
+        "max_connection_attempts is zero"
+    } else if opts.max_time.is_zero() {
+        "max_time is zero"
+    } else if opts.max_bandwidth == Some(0) {
+        "max_bandwidth is zero"
+    } else if no_custom_peers {
+        "the custom peer list is empty"
+    } else if matches!(opts.success_policy, crate::SuccessPolicy::AtLeast(k) if k > tx.len()) {
+        "success_policy asks for more transactions than the batch has"
+    } else {
+        return Ok(());
+    };
+
+    Err(Error::InvalidOptions(problem.to_string()))
+}
+
+/// Tells why connecting to `target` failed. Errors that come through the Tor proxy do not carry
+/// much detail, so anything but a refusal or timeout is put down to the proxy.
+fn connect_failure(target: &net::Service, err: &std::io::Error, via_tor: bool) -> ConnectFailure {
+    match err.kind() {
+        std::io::ErrorKind::ConnectionRefused => ConnectFailure::Refused,
+        std::io::ErrorKind::TimedOut => ConnectFailure::TimedOut,
+        _ if target.socket_addr().is_none() => ConnectFailure::OnionUnreachable,
+        _ if via_tor => ConnectFailure::Proxy,
+        kind => ConnectFailure::Other(kind),
     }
+}
 
-    // Tor browser has a SOCKS proxy on port 9150
-    if is_port_reachable((Ipv4Addr::LOCALHOST, 9150).into()) {
-        return Some((Ipv4Addr::LOCALHOST, 9150).into());
+/// Picks up to `n` random clearnet addresses from the peer pool other than the asking peer's own,
+/// timestamped as if they had been seen within the last three hours.
+fn addr_sample(
+    addressbook: &[net::Service],
+    asking: net::Service,
+    n: usize,
+    now: time::SystemTime,
+    rng: &mut fastrand::Rng,
+) -> Vec<(u32, bitcoin::p2p::Address)> {
+    let now = now
+        .duration_since(time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as u32)
+        .unwrap_or_default();
+
+    let mut candidates: Vec<_> = addressbook
+        .iter()
+        .filter(|addr| **addr != asking)
+        .filter_map(net::Service::socket_addr)
+        .collect();
+    rng.shuffle(&mut candidates);
+
+    candidates
+        .into_iter()
+        .take(n)
+        .map(|socket| {
+            let seen = now.saturating_sub(rng.u32(..3 * 60 * 60));
+            let services =
+                bitcoin::p2p::ServiceFlags::NETWORK | bitcoin::p2p::ServiceFlags::WITNESS;
+            (seen, bitcoin::p2p::Address::new(&socket, services))
+        })
+        .collect()
+}
+
+/// Tells which of `Opts::peer_requirements` a peer falls short of, if any. Peers from the seeds
+/// must also be full nodes.
+fn unmet_requirement(
+    opts: &Opts,
+    version: &bitcoin::p2p::message_network::VersionMessage,
+    from_seeds: bool,
+) -> Option<String> {
+    let requirements = &opts.peer_requirements;
+    let services = version.services.to_u64();
+    let required = if from_seeds {
+        requirements.services | NODE_NETWORK
+    } else {
+        requirements.services
+    };
+
+    if services & required != required {
+        Some(format!("missing services {:#x}", required & !services))
+    } else if version.version < requirements.min_version {
+        Some(format!("protocol version {}", version.version))
+    } else if requirements.relay && !version.relay {
+        Some("turned off relay".to_string())
+    } else if user_agent_filtered(opts, &version.user_agent) {
+        Some(format!("user agent {:?}", version.user_agent))
+    } else {
+        None
     }
+}
 
-    None
+/// Whether a peer is to be dropped because of the user agent it advertised.
+#[cfg(feature = "ua-filter")]
+fn user_agent_filtered(opts: &Opts, user_agent: &str) -> bool {
+    opts.ua_filter
+        .as_ref()
+        .is_some_and(|filter| !filter.allows(user_agent))
 }
 
-/// Creates a pool of nodes from where peers can be found.
-fn create_node_pool(
-    strategy: FindPeerStrategy,
-    p2p_network: crate::Network,
-    allowed_networks: &[net::Network],
-) -> Vec<net::Service> {
+/// Whether a peer is to be dropped because of the user agent it advertised. Filtering is compiled
+/// out, so this is always false.
+#[cfg(not(feature = "ua-filter"))]
+fn user_agent_filtered(_opts: &Opts, _user_agent: &str) -> bool {
+    false
+}
+
+/// Creates a pool of distinct nodes from where peers can be found, following the peer finding
+/// strategy of `opts` and leaving out the countries it excludes. Also returns a warning if DNS
+/// seeding underperformed and the fixed seeds had to be mixed in. Fails only if a peer list file
+/// cannot be read.
+pub(crate) fn create_node_pool(
+    opts: &Opts,
+    via_tor: bool,
+    rng: &mut fastrand::Rng,
+) -> Result<(Vec<net::Service>, Option<Warning>), Error> {
+    let strategy = &opts.find_peer_strategy;
+    let exclude_countries = opts.exclude_countries.as_ref();
     match strategy {
-        FindPeerStrategy::DnsSeedWithFixedFallback | FindPeerStrategy::DnsSeedOnly => {
-            let mut nodes = seeds::dns(p2p_network);
-            if matches!(strategy, FindPeerStrategy::DnsSeedWithFixedFallback) && nodes.len() < 20 {
-                nodes.extend(seeds::fixed(p2p_network));
-            }
-            fastrand::shuffle(&mut nodes);
-            nodes
-                .into_iter()
-                .filter(|node| allowed_networks.iter().any(|net| node.on_network(*net)))
-                .collect()
+        FindPeerStrategy::DnsSeedWithFixedFallback
+        | FindPeerStrategy::DnsSeedAndFixed
+        | FindPeerStrategy::DnsSeedOnly => {
+            let resolve_opts = seeds::ResolveOpts {
+                fixed_fallback: !matches!(strategy, FindPeerStrategy::DnsSeedOnly),
+                fallback_threshold: match strategy {
+                    FindPeerStrategy::DnsSeedAndFixed => usize::MAX,
+                    _ => opts.fixed_fallback_threshold,
+                },
+                timeout: opts.phase_timeouts.resolve,
+                via_tor,
+                cache: opts.dns_cache.clone(),
+                exclude_countries: exclude_countries.cloned(),
+            };
+            let resolution = seeds::resolve_with_rng(opts.network, &resolve_opts, rng);
+            // mixing in the fixed seeds is only worth a warning if it was not asked for
+            let warning = match (strategy, resolution.from_fixed) {
+                (FindPeerStrategy::DnsSeedWithFixedFallback, Some(from_fixed)) => {
+                    Some(Warning::FixedSeedFallback {
+                        from_dns: resolution.from_dns,
+                        from_fixed,
+                    })
+                }
+                _ => None,
+            };
+            Ok((resolution.nodes, warning))
+        }
+        FindPeerStrategy::FixedOnly(file) => {
+            let mut nodes =
+                seeds::resolve_fixed_with_rng(opts.network, via_tor, file.as_deref(), rng)?;
+            seeds::prune(&mut nodes, exclude_countries);
+            Ok((nodes, None))
         }
-        FindPeerStrategy::Custom(custom) => custom.into_iter().map(Into::into).collect(),
+        FindPeerStrategy::Custom(custom) => {
+            let mut nodes = custom.clone();
+            seeds::prune(&mut nodes, exclude_countries);
+            Ok((nodes, None))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+
+    /// A transaction spending the given outputs of a made-up transaction, told apart from others
+    /// spending the same by its lock time.
+    fn spending(vouts: &[u32], lock_time: u32) -> Transaction {
+        Transaction(bitcoin::Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::from_consensus(lock_time),
+            input: vouts
+                .iter()
+                .map(|vout| bitcoin::TxIn {
+                    previous_output: bitcoin::OutPoint::new(bitcoin::Txid::all_zeros(), *vout),
+                    ..Default::default()
+                })
+                .collect(),
+            output: vec![],
+        })
+    }
+
+    fn txid(tx: &Transaction) -> bitcoin::Txid {
+        tx.0.txid()
+    }
+
+    #[test]
+    fn distinct_inputs_do_not_conflict() {
+        let txs = [spending(&[0, 1], 0), spending(&[2], 0)];
+        assert!(find_conflicts(&txs, &HashSet::new()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn shared_input_is_settled_by_the_replacements() {
+        let (a, b) = (spending(&[0, 1], 0), spending(&[1, 2], 1));
+        let txs = [a.clone(), b.clone()];
+
+        let unsettled = find_conflicts(&txs, &HashSet::new());
+        assert!(matches!(
+            unsettled,
+            Err(Error::ConflictingTxs(x, y)) if x.0 == txid(&a) && y.0 == txid(&b)
+        ));
+
+        let both = HashSet::from([crate::Txid(txid(&a)), crate::Txid(txid(&b))]);
+        assert!(matches!(
+            find_conflicts(&txs, &both),
+            Err(Error::ConflictingTxs(..))
+        ));
+
+        // whichever comes first, the replacement stays and the other one is dropped
+        let replaced_by_b = HashSet::from([crate::Txid(txid(&b))]);
+        let dropped = find_conflicts(&txs, &replaced_by_b).unwrap();
+        assert_eq!(dropped, HashMap::from([(txid(&a), txid(&b))]));
+
+        let replaced_by_a = HashSet::from([crate::Txid(txid(&a))]);
+        let dropped = find_conflicts(&txs, &replaced_by_a).unwrap();
+        assert_eq!(dropped, HashMap::from([(txid(&b), txid(&a))]));
+    }
+
+    #[test]
+    fn duplicate_tx_does_not_conflict_with_itself() {
+        let tx = spending(&[0], 0);
+        let txs = [tx.clone(), tx];
+        assert!(find_conflicts(&txs, &HashSet::new()).unwrap().is_empty());
     }
 }
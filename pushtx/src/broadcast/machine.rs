@@ -0,0 +1,2215 @@
+//! The broadcast decision logic: which peers to dial and broadcast to, which acks count, when
+//! to rotate and replace peers and when the broadcast is over. It consumes p2p events and queues
+//! commands, while the runner thread owns the p2p client and feeds it events as they arrive.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::time;
+use std::time::Duration;
+
+use bitcoin::bip152::ShortId;
+use bitcoin::p2p::address::{AddrV2, AddrV2Message};
+use bitcoin::p2p::message::NetworkMessage;
+use bitcoin::p2p::message_blockdata::Inventory;
+use bitcoin::p2p::Magic;
+
+use super::{ack_count, addr_sample, connect_failure, log_connection, log_invs, unmet_requirement};
+use crate::attestation;
+#[cfg(feature = "chaos")]
+use crate::chaos;
+use crate::diagnosis::Evidence;
+use crate::handshake::{self, Handshake};
+use crate::latency;
+use crate::misbehavior::{Misbehavior, RateLimit, Scoreboard};
+use crate::p2p::{self, Outbox};
+use crate::redact;
+use crate::{
+    net, telemetry, AddrResponse, Clock, Error, FindPeerStrategy, Info, Opts, PeerState,
+    PeerStatus, Phase, Reject, Report, Warning,
+};
+
+/// Batches of up to this many transactions are pushed to the broadcast peer directly. Larger
+/// batches are announced through `inv` messages and served as the peer requests them.
+const MAX_PUSHED_TXS: usize = 100;
+
+/// How many transactions are announced per `inv` message. Well below the protocol limit of
+/// 50,000 entries so that a large batch is spread over several loop iterations.
+const INV_CHUNK_SIZE: usize = 1_000;
+
+/// When this little time is left and some transactions are still neither acked nor rejected, a
+/// few peers are asked for them directly to find out whether they made it into their mempools.
+const PROBE_WINDOW: Duration = Duration::from_secs(5);
+
+/// How many peers are asked for unacked transactions, or for all of them before broadcasting if
+/// `Opts::presence_probe` is set.
+const PROBE_PEERS: usize = 3;
+
+/// How long to wait for peers to answer the presence probe before broadcasting.
+const PRESENCE_WINDOW: Duration = Duration::from_secs(3);
+
+/// How many foreign transactions are fetched per second at most with `Opts::conflict_alerts`.
+const MAX_CONFLICT_FETCHES_PER_SEC: usize = 100;
+
+/// How many foreign transactions are fetched at most over the whole run with
+/// `Opts::conflict_alerts`, so that a busy mempool cannot keep the memory use growing.
+const MAX_CONFLICT_FETCHES: usize = 20_000;
+
+/// How many times the same address is dialed before it is given up on, counting connections that
+/// succeeded but were lost later.
+const MAX_ATTEMPTS_PER_ADDRESS: u32 = 2;
+
+/// How long a broadcast peer is kept before it is rotated out.
+const BROADCAST_PEER_LIFETIME: Duration = Duration::from_secs(10);
+
+/// How soon to retry sending messages that the bandwidth limit held back.
+const THROTTLE_RETRY: Duration = Duration::from_millis(50);
+
+/// How far the progress estimate has to move before it is sent again.
+const PROGRESS_STEP: f32 = 0.01;
+
+/// How long the progress estimate goes without being sent at most. Sending it is how a dropped
+/// receiver gets noticed while nothing else happens.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often to check for raw messages, which arrive on a channel of their own.
+#[cfg(feature = "raw-messages")]
+const RAW_MESSAGE_POLL: Duration = Duration::from_millis(100);
+
+/// How a step of the broadcast ended: `Continue` if the broadcast goes on, otherwise its outcome,
+/// which is `None` if the caller stopped listening.
+pub(super) type Flow = ControlFlow<Option<Result<Report, Error>>>;
+
+/// The transactions of a batch and what is derived from them up front.
+pub(super) struct Batch {
+    pub tx_map: HashMap<bitcoin::Txid, p2p::SharedTx>,
+    /// Peers that negotiated wtxid relay announce and request transactions by wtxid.
+    pub wtxids: HashMap<bitcoin::Wtxid, bitcoin::Txid>,
+    /// The outputs spent by the batch, to spot foreign transactions that spend them too.
+    pub spent: HashMap<bitcoin::OutPoint, bitcoin::Txid>,
+    pub evidence: Evidence,
+    pub attest: attestation::Recorder,
+}
+
+/// The state of a single broadcast.
+pub(super) struct Machine<P: p2p::Peerlike> {
+    opts: Opts,
+    clock: Arc<dyn Clock>,
+    magic: Magic,
+    via_tor: bool,
+    proxies: Option<p2p::ProxyPool>,
+    tx_map: HashMap<bitcoin::Txid, p2p::SharedTx>,
+    wtxids: HashMap<bitcoin::Wtxid, bitcoin::Txid>,
+    /// The other way around from `wtxids`, for announcing to peers that negotiated wtxid relay.
+    wtxid_of: HashMap<bitcoin::Txid, bitcoin::Wtxid>,
+    spent: HashMap<bitcoin::OutPoint, bitcoin::Txid>,
+    evidence: Evidence,
+    attest: attestation::Recorder,
+    addressbook: Vec<net::Service>,
+    /// Dialed first and preferred as a broadcast peer.
+    local_node: Option<net::Service>,
+    rng: fastrand::Rng,
+    #[cfg(feature = "chaos")]
+    chaos: chaos::Injector,
+
+    state: HashMap<P, Peer>,
+    scoreboard: Scoreboard,
+    rate_limits: HashMap<P, RateLimit>,
+    /// Peers that asked for `addrv2` (BIP-155) and that negotiated wtxid relay (BIP-339).
+    addr_v2: HashSet<P>,
+    wtxid_relay: HashSet<P>,
+    dialing: HashMap<net::Service, time::Instant>,
+    attempts: HashMap<net::Service, u32>,
+    total_attempts: usize,
+
+    acks: HashSet<bitcoin::Txid>,
+    selected: Vec<BroadcastPeer<P>>,
+    /// An ack from the same netgroup as a peer we broadcast to proves nothing, since both peers
+    /// could be run by the same operator.
+    broadcast_netgroups: HashSet<net::NetGroup>,
+    probes: HashSet<P>,
+    probed: bool,
+    answered_getaddr: HashSet<P>,
+    latency: latency::Tracker,
+    /// The presence probe asks a few peers for the transactions before anything is sent.
+    presence_settled: bool,
+    presence_until: Option<time::Instant>,
+    presence_peers: HashSet<P>,
+    already_known: HashSet<bitcoin::Txid>,
+    /// Transactions pushed in full rather than announced, which peers never request.
+    pushed: HashSet<bitcoin::Txid>,
+    listen_until: Option<time::Instant>,
+    linger_until: Option<time::Instant>,
+    /// Blocks asked for while watching for inclusion, and the transactions found in them.
+    requested_blocks: HashSet<bitcoin::BlockHash>,
+    confirmed: HashSet<bitcoin::Txid>,
+    /// Foreign transactions asked for to look for conflicts, and the conflicts found so far.
+    conflict_fetches: HashSet<Inventory>,
+    conflict_window: (time::Instant, usize),
+    conflicts: HashSet<bitcoin::Txid>,
+    /// The peers sent to so far when escalating, and when the current stage began.
+    escalated: HashSet<P>,
+    escalation_stage: u32,
+    escalated_at: Option<time::Instant>,
+
+    start: time::Instant,
+    /// Moved up once the broadcast phase starts, if it has a budget of its own.
+    deadline: time::Instant,
+    broadcast_started: bool,
+    rejects: HashMap<crate::Txid, Reject>,
+    /// Set once a reject stops the broadcast, see `Opts::halt_on_reject`.
+    halted: bool,
+    /// Given up on after `Opts::ack_timeout`.
+    timed_out: HashSet<bitcoin::Txid>,
+    /// The phase and progress estimate last sent, see `Info::Progress`, and when.
+    progress: (Phase, f32),
+    progress_sent: time::Instant,
+    connect_failures: HashMap<crate::ConnectFailure, usize>,
+
+    /// Lost peers are replaced in rounds no closer together than `opts.replacement_interval`.
+    need_replacements: usize,
+    last_replacement_round: Option<time::Instant>,
+    /// When each peer started handshaking, and the stalled ones that were already replaced.
+    handshake_started: HashMap<P, time::Instant>,
+    stall_replaced: HashSet<P>,
+    /// The address of each connection, when it was dialed and established and through which
+    /// proxy.
+    connections: HashMap<
+        P,
+        (
+            net::Service,
+            time::SystemTime,
+            time::SystemTime,
+            Option<SocketAddr>,
+        ),
+    >,
+}
+
+impl<P: p2p::Peerlike> Machine<P> {
+    /// Sets up a broadcast of `batch` to peers drawn from `addressbook`. Nothing is dialed until
+    /// `dial` is called.
+    pub fn new(
+        opts: Opts,
+        batch: Batch,
+        addressbook: Vec<net::Service>,
+        local_node: Option<net::Service>,
+        proxies: Option<p2p::ProxyPool>,
+        mut rng: fastrand::Rng,
+    ) -> Self {
+        let clock = opts.clock.clone();
+        let start = clock.now();
+        Self {
+            magic: bitcoin::Network::from(opts.network).magic(),
+            via_tor: proxies.is_some(),
+            proxies,
+            tx_map: batch.tx_map,
+            wtxid_of: batch.wtxids.iter().map(|(w, t)| (*t, *w)).collect(),
+            wtxids: batch.wtxids,
+            spent: batch.spent,
+            evidence: batch.evidence,
+            attest: batch.attest,
+            addressbook,
+            local_node,
+            #[cfg(feature = "chaos")]
+            chaos: chaos::Injector::new(opts.chaos.clone(), rng.fork()),
+            rng,
+
+            state: HashMap::new(),
+            scoreboard: Scoreboard::default(),
+            rate_limits: HashMap::new(),
+            addr_v2: HashSet::new(),
+            wtxid_relay: HashSet::new(),
+            dialing: HashMap::new(),
+            attempts: HashMap::new(),
+            total_attempts: 0,
+
+            acks: HashSet::new(),
+            selected: Vec::new(),
+            broadcast_netgroups: HashSet::new(),
+            probes: HashSet::new(),
+            probed: false,
+            answered_getaddr: HashSet::new(),
+            latency: latency::Tracker::default(),
+            presence_settled: !opts.presence_probe,
+            presence_until: None,
+            presence_peers: HashSet::new(),
+            already_known: HashSet::new(),
+            pushed: HashSet::new(),
+            listen_until: None,
+            linger_until: None,
+            requested_blocks: HashSet::new(),
+            confirmed: HashSet::new(),
+            conflict_fetches: HashSet::new(),
+            conflict_window: (start, 0),
+            conflicts: HashSet::new(),
+            escalated: HashSet::new(),
+            escalation_stage: 0,
+            escalated_at: None,
+
+            start,
+            deadline: start + opts.max_time,
+            broadcast_started: false,
+            rejects: HashMap::new(),
+            halted: false,
+            timed_out: HashSet::new(),
+            progress: (Phase::Pending, 0.0),
+            progress_sent: start,
+            connect_failures: HashMap::new(),
+
+            need_replacements: 0,
+            last_replacement_round: None,
+            handshake_started: HashMap::new(),
+            stall_replaced: HashSet::new(),
+            connections: HashMap::new(),
+
+            clock,
+            opts,
+        }
+    }
+
+    /// Dials the first peers of the address book.
+    pub fn dial(&mut self, outbox: &impl Outbox<P>) {
+        let first = self
+            .addressbook
+            .iter()
+            .take(self.opts.peer_count())
+            .take(self.opts.max_connection_attempts);
+        for addr in first {
+            #[cfg(feature = "chaos")]
+            self.chaos.connect(outbox, *addr);
+            #[cfg(not(feature = "chaos"))]
+            outbox.connect(*addr);
+            self.dialing.insert(*addr, self.clock.now());
+            *self.attempts.entry(*addr).or_default() += 1;
+            self.total_attempts += 1;
+        }
+    }
+
+    /// Dials the connections that chaos held back and whose delay is over.
+    #[cfg(feature = "chaos")]
+    pub fn release(&mut self, outbox: &impl Outbox<P>) {
+        self.chaos.release(outbox);
+    }
+
+    /// Processes a single p2p event.
+    pub fn handle(
+        &mut self,
+        event: p2p::Event<P>,
+        outbox: &impl Outbox<P>,
+        notify: &impl Fn(Info) -> Option<()>,
+    ) -> Flow {
+        match event {
+            p2p::Event::ConnectedTo { target, result } => {
+                let dialed = self.dialing.remove(&target);
+                let now = self.clock.system_now();
+                let dialed_at = dialed.map_or(now, |when| now - self.clock.elapsed(when));
+                match result {
+                    Ok(id) => {
+                        log::info!("connected: peer @ {}", redact::r(target));
+                        if let Some(when) = dialed {
+                            telemetry::peer_connected(self.via_tor, self.clock.elapsed(when));
+                        }
+                        self.connections.insert(
+                            id,
+                            (
+                                target,
+                                dialed_at,
+                                now,
+                                self.proxies.as_ref().map(p2p::ProxyPool::current),
+                            ),
+                        );
+                        self.state
+                            .insert(id, Peer::Handshaking(target, Handshake::default()));
+                        self.handshake_started.insert(id, self.clock.now());
+                        outbox.version(id, target);
+                    }
+                    Err(err) => {
+                        log::info!("failed to connect to peer @ {}: {err}", redact::r(target));
+                        telemetry::peer_connect_failed(self.via_tor);
+                        self.need_replacements += 1;
+                        let cause = connect_failure(&target, &err, self.via_tor);
+                        *self.connect_failures.entry(cause).or_default() += 1;
+                        log_connection(
+                            &self.opts.connection_log,
+                            &*self.clock,
+                            target,
+                            dialed_at,
+                            None,
+                            self.proxies.as_ref().map(p2p::ProxyPool::current),
+                            crate::ConnectionOutcome::Failed(cause),
+                        );
+                        if notify(Info::ConnectFailed {
+                            peer: target,
+                            cause,
+                        })
+                        .is_none()
+                        {
+                            return ControlFlow::Break(None);
+                        }
+                    }
+                }
+            }
+
+            p2p::Event::Message { peer, message } if *message.magic() != self.magic => {
+                if let Some(service) = self.state.get(&peer).map(Peer::service) {
+                    if !self.scoreboard.is_banned(&service) {
+                        log::warn!("wrong network: peer @ {}", redact::r(service));
+                        self.scoreboard.record(service, Misbehavior::WrongNetwork);
+                        outbox.disconnect(peer);
+                        let warning = Warning::WrongNetwork { peer: service };
+                        if notify(Info::Warning(warning)).is_none() {
+                            return ControlFlow::Break(None);
+                        }
+                    }
+                }
+            }
+
+            p2p::Event::Message { peer, message } => {
+                let now = self.clock.now();
+                let rate_limit = self
+                    .rate_limits
+                    .entry(peer)
+                    .or_insert_with(|| RateLimit::new(now));
+                if rate_limit.hit(now) {
+                    if let Some(service) = self.state.get(&peer).map(Peer::service) {
+                        log::warn!("flooding: peer @ {}", redact::r(service));
+                        if self.scoreboard.record(service, Misbehavior::Flood) {
+                            outbox.disconnect(peer);
+                        }
+                    }
+                }
+                // a banned peer is on its way out and nothing it sends counts anymore
+                let service = self.state.get(&peer).map(Peer::service);
+                if service.is_some_and(|service| self.scoreboard.is_banned(&service)) {
+                    return ControlFlow::Continue(());
+                }
+
+                match self.state.get_mut(&peer) {
+                    Some(Peer::Handshaking(s, h)) => match h.update(message.payload().into()) {
+                        handshake::Event::Wait => {}
+                        handshake::Event::SendVerack { version } => {
+                            #[cfg(feature = "chaos")]
+                            let dropped = self.chaos.drop_handshake();
+                            #[cfg(not(feature = "chaos"))]
+                            let dropped = false;
+                            if dropped {
+                                log::info!("chaos: dropping peer @ {} mid-handshake", redact::r(s));
+                                outbox.disconnect(peer);
+                            } else {
+                                handshake::acknowledge(
+                                    outbox,
+                                    peer,
+                                    self.opts.protocol_version,
+                                    version,
+                                );
+                            }
+                        }
+                        handshake::Event::Violation => {
+                            log::warn!("handshake violated: peer @ {}", redact::r(s));
+                            self.scoreboard.record(*s, Misbehavior::HandshakeViolation);
+                            outbox.disconnect(peer);
+                        }
+                        handshake::Event::Done {
+                            version,
+                            wants_addr_v2,
+                            wtxid_relay,
+                        } => {
+                            let service = *s;
+                            self.handshake_started.remove(&peer);
+                            self.attest.handshake(service, version);
+                            let from_seeds = Some(service) != self.local_node
+                                && !matches!(
+                                    self.opts.find_peer_strategy,
+                                    FindPeerStrategy::Custom(_)
+                                        | FindPeerStrategy::FixedOnly(Some(_))
+                                );
+                            if let Some(unmet) = unmet_requirement(&self.opts, version, from_seeds)
+                            {
+                                log::info!("filtered by {unmet}: peer @ {}", redact::r(service));
+                                // a filtered peer is never picked as a replacement again
+                                self.addressbook.retain(|addr| *addr != service);
+                                outbox.disconnect(peer);
+                            } else {
+                                log::info!("handshake complete: peer @ {}", redact::r(s));
+                                if wants_addr_v2 {
+                                    self.addr_v2.insert(peer);
+                                }
+                                // only negotiated if we sent ours too
+                                if wtxid_relay
+                                    && self.opts.protocol_version >= handshake::WTXID_RELAY_VERSION
+                                {
+                                    self.wtxid_relay.insert(peer);
+                                }
+                                self.state.insert(peer, Peer::Ready { service });
+                            }
+                        }
+                    },
+                    Some(Peer::Ready { service }) => match message.payload() {
+                        NetworkMessage::Inv(inv) => {
+                            if let Some(inv_log) = &self.opts.inv_log {
+                                log_invs(inv_log, *service, inv, self.clock.system_now());
+                            }
+                            for inv in inv {
+                                if let Inventory::Block(hash) = inv {
+                                    let watching =
+                                        !self.opts.watch_blocks.is_zero() && self.broadcast_started;
+                                    if watching && self.requested_blocks.insert(*hash) {
+                                        log::info!(
+                                            "new block: peer @ {}: {}",
+                                            redact::r(service),
+                                            hash
+                                        );
+                                        outbox.getdata(peer, vec![Inventory::CompactBlock(*hash)]);
+                                    }
+                                    continue;
+                                }
+                                let wanted_txid = match inv {
+                                    Inventory::Transaction(txid) => Some(txid),
+                                    Inventory::WTx(wtxid) => self.wtxids.get(wtxid),
+                                    _ => None,
+                                };
+                                let foreign = match inv {
+                                    Inventory::Transaction(txid) => !self.tx_map.contains_key(txid),
+                                    Inventory::WTx(_) => wanted_txid.is_none(),
+                                    _ => false,
+                                };
+                                if foreign && self.opts.conflict_alerts && self.broadcast_started {
+                                    let now = self.clock.now();
+                                    if now - self.conflict_window.0 >= Duration::from_secs(1) {
+                                        self.conflict_window = (now, 0);
+                                    }
+                                    if self.conflict_window.1 < MAX_CONFLICT_FETCHES_PER_SEC
+                                        && self.conflict_fetches.len() < MAX_CONFLICT_FETCHES
+                                        && self.conflict_fetches.insert(*inv)
+                                    {
+                                        self.conflict_window.1 += 1;
+                                        outbox.getdata(peer, vec![*inv]);
+                                    }
+                                }
+                                if let Some(wanted_txid) = wanted_txid {
+                                    if self.tx_map.contains_key(wanted_txid)
+                                        && !self.broadcast_netgroups.contains(&service.netgroup())
+                                    {
+                                        log::info!(
+                                            "txid seen: peer @ {}: {}",
+                                            redact::r(service),
+                                            redact::r(wanted_txid)
+                                        );
+                                        if !self.broadcast_started {
+                                            // relayed by someone else before we sent it
+                                            self.already_known.insert(*wanted_txid);
+                                        }
+                                        self.latency.ack(*wanted_txid, *service, self.clock.now());
+                                        self.attest.ack(*service, *wanted_txid);
+                                        if self.acks.insert(*wanted_txid) {
+                                            telemetry::ack(self.via_tor);
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                        NetworkMessage::GetData(inv) => {
+                            for inv in inv {
+                                let txid = match inv {
+                                    Inventory::Transaction(txid)
+                                    | Inventory::WitnessTransaction(txid) => Some(txid),
+                                    Inventory::WTx(wtxid) => self.wtxids.get(wtxid),
+                                    _ => None,
+                                };
+                                if let Some(txid) = txid {
+                                    self.latency.requested(*txid, *service, self.clock.now());
+                                    match self.tx_map.get(txid) {
+                                        // interest in an announced tx is what a dry run
+                                        // counts as success, the tx itself never goes out
+                                        Some(_) if self.opts.dry_run => {
+                                            log::info!(
+                                                "getdata (dry run, not served): peer @ {}: {}",
+                                                redact::r(service),
+                                                redact::r(txid)
+                                            );
+                                            self.latency.ack(*txid, *service, self.clock.now());
+                                            self.attest.ack(*service, *txid);
+                                            self.acks.insert(*txid);
+                                        }
+                                        Some(tx) => {
+                                            log::info!(
+                                                "getdata: peer @ {}: {}",
+                                                redact::r(service),
+                                                redact::r(txid)
+                                            );
+                                            outbox.tx(peer, tx.clone());
+                                            self.attest.sent(*service, "tx", *txid);
+                                            telemetry::broadcast_attempt(self.via_tor);
+                                        }
+                                        None => {}
+                                    }
+                                }
+                            }
+                        }
+                        NetworkMessage::Reject(reject) => {
+                            log::warn!(
+                                "reject: peer @ {}: type={}, code={:?}, reason={}",
+                                redact::r(service),
+                                reject.message,
+                                reject.ccode,
+                                reject.reason
+                            );
+                            if reject.message == "tx" {
+                                let txid = crate::Txid(reject.hash.into());
+                                telemetry::reject(self.via_tor, &reject.reason);
+                                if self.linger_until.is_some() {
+                                    log::info!("late reject while lingering: {}", redact::r(txid));
+                                }
+                                if self.opts.halt_on_reject
+                                    && self.tx_map.contains_key(&txid.0)
+                                    && !self.halted
+                                {
+                                    log::warn!("halting on reject: {}", redact::r(txid));
+                                    self.halted = true;
+                                    for selected in &mut self.selected {
+                                        selected.queue.clear();
+                                    }
+                                }
+                                let reject = Reject {
+                                    reason: reject.ccode.into(),
+                                    message: reject.reason.to_string(),
+                                    peer: Some(service),
+                                };
+                                self.rejects.insert(txid, reject);
+                            }
+                        }
+                        NetworkMessage::Tx(tx) if self.presence_peers.contains(&peer) => {
+                            let txid = tx.txid();
+                            if self.tx_map.contains_key(&txid) && !self.broadcast_started {
+                                log::info!(
+                                    "txid already known: peer @ {}: {}",
+                                    redact::r(service),
+                                    redact::r(txid)
+                                );
+                                self.already_known.insert(txid);
+                            }
+                        }
+                        NetworkMessage::NotFound(_) if self.presence_peers.contains(&peer) => {}
+                        NetworkMessage::Tx(tx) if self.probes.contains(&peer) => {
+                            let txid = tx.txid();
+                            if self.tx_map.contains_key(&txid) {
+                                log::info!(
+                                    "txid served: peer @ {}: {}",
+                                    redact::r(service),
+                                    redact::r(txid)
+                                );
+                                self.latency.ack(txid, *service, self.clock.now());
+                                self.attest.ack(*service, txid);
+                                if self.acks.insert(txid) {
+                                    telemetry::ack(self.via_tor);
+                                }
+                            }
+                        }
+                        NetworkMessage::NotFound(inv) if self.probes.contains(&peer) => {
+                            for inv in inv {
+                                let txid = match inv {
+                                    Inventory::Transaction(txid) => Some(txid),
+                                    Inventory::WTx(wtxid) => self.wtxids.get(wtxid),
+                                    _ => None,
+                                };
+                                if let Some(txid) = txid {
+                                    log::info!(
+                                        "txid not found: peer @ {}: {}",
+                                        redact::r(service),
+                                        redact::r(txid)
+                                    );
+                                    self.evidence.not_found(*txid);
+                                }
+                            }
+                        }
+                        NetworkMessage::FeeFilter(rate) => self.evidence.feefilter(*service, *rate),
+                        NetworkMessage::GetAddr if self.answered_getaddr.insert(peer) => {
+                            let addrs = match self.opts.getaddr_response {
+                                AddrResponse::Ignore => None,
+                                AddrResponse::Empty => Some(Vec::new()),
+                                AddrResponse::Sample(n) => Some(addr_sample(
+                                    &self.addressbook,
+                                    *service,
+                                    n.into(),
+                                    self.clock.system_now(),
+                                    &mut self.rng,
+                                )),
+                            };
+                            if let Some(addrs) = addrs {
+                                log::debug!(
+                                    "addr ({}): peer @ {}",
+                                    addrs.len(),
+                                    redact::r(service)
+                                );
+                                if self.addr_v2.contains(&peer) {
+                                    outbox.addrv2(peer, addr_v2(addrs));
+                                } else {
+                                    outbox.addr(peer, addrs);
+                                }
+                            }
+                        }
+                        NetworkMessage::CmpctBlock(cmpct)
+                            if self
+                                .requested_blocks
+                                .contains(&cmpct.compact_block.header.block_hash()) =>
+                        {
+                            let block = &cmpct.compact_block;
+                            let block_hash = block.header.block_hash();
+                            let keys = ShortId::calculate_siphash_keys(&block.header, block.nonce);
+                            let short_ids: HashSet<_> = block.short_ids.iter().collect();
+                            // short ids may collide, so a match is very likely but not certain
+                            let included = self
+                                .wtxids
+                                .iter()
+                                .filter(|(wtxid, _)| {
+                                    short_ids.contains(&ShortId::with_siphash_keys(*wtxid, keys))
+                                })
+                                .map(|(_, txid)| *txid)
+                                .chain(block.prefilled_txs.iter().map(|p| p.tx.txid()))
+                                .filter(|txid| self.tx_map.contains_key(txid))
+                                .collect::<Vec<_>>();
+                            let mut listening = true;
+                            for txid in included {
+                                if self.confirmed.insert(txid) {
+                                    log::info!(
+                                        "confirmed: {} in block {}",
+                                        redact::r(txid),
+                                        block_hash
+                                    );
+                                    let info = Info::Confirmed {
+                                        txid: crate::Txid(txid),
+                                        block_hash: crate::BlockHash(block_hash),
+                                    };
+                                    listening &= notify(info).is_some();
+                                }
+                            }
+                            if !listening {
+                                return ControlFlow::Break(None);
+                            }
+                        }
+                        NetworkMessage::Tx(tx)
+                            if self
+                                .conflict_fetches
+                                .contains(&Inventory::Transaction(tx.txid()))
+                                || self.conflict_fetches.contains(&Inventory::WTx(tx.wtxid())) =>
+                        {
+                            let theirs = tx.txid();
+                            let ours = tx
+                                .input
+                                .iter()
+                                .filter_map(|input| self.spent.get(&input.previous_output))
+                                .find(|ours| **ours != theirs);
+                            if let Some(ours) = ours {
+                                if self.conflicts.insert(theirs) {
+                                    log::warn!(
+                                        "double spend: peer @ {}: {} conflicts with {}",
+                                        redact::r(service),
+                                        redact::r(theirs),
+                                        redact::r(ours)
+                                    );
+                                    let warning = Warning::DoubleSpend {
+                                        ours: crate::Txid(*ours),
+                                        theirs: crate::Txid(theirs),
+                                    };
+                                    if notify(Info::Warning(warning)).is_none() {
+                                        return ControlFlow::Break(None);
+                                    }
+                                }
+                            }
+                        }
+                        NetworkMessage::Tx(_) | NetworkMessage::CmpctBlock(_) => {
+                            log::debug!(
+                                "unsolicited {}: peer @ {}",
+                                message.payload().cmd(),
+                                redact::r(service)
+                            );
+                            if self.scoreboard.record(*service, Misbehavior::Unsolicited) {
+                                outbox.disconnect(peer);
+                            }
+                        }
+                        _ => {}
+                    },
+                    None => {
+                        return ControlFlow::Break(Some(Err(Error::Internal(format!(
+                            "message from phantom peer {peer}"
+                        )))))
+                    }
+                }
+            }
+
+            p2p::Event::Disconnected { peer, reason } => match self.state.get_mut(&peer) {
+                Some(Peer::Ready { service } | Peer::Handshaking(service, _)) => {
+                    log::info!(
+                        "disconnected: peer @ {}, reason: {:?}",
+                        redact::r(service),
+                        reason
+                    );
+                    if matches!(reason, p2p::DisconnectReason::CodecViolation) {
+                        self.scoreboard.record(*service, Misbehavior::Malformed);
+                    }
+                    self.selected.retain(|selected| selected.id != peer);
+                    // a stalled peer was replaced already
+                    if !self.stall_replaced.remove(&peer) {
+                        self.need_replacements += 1;
+                    }
+                    self.handshake_started.remove(&peer);
+                    if let Some((service, dialed_at, connected_at, proxy)) =
+                        self.connections.remove(&peer)
+                    {
+                        log_connection(
+                            &self.opts.connection_log,
+                            &*self.clock,
+                            service,
+                            dialed_at,
+                            Some(connected_at),
+                            proxy,
+                            crate::ConnectionOutcome::Closed,
+                        );
+                    }
+                    self.state.remove(&peer);
+                    self.rate_limits.remove(&peer);
+                    self.answered_getaddr.remove(&peer);
+                    self.probes.remove(&peer);
+                    self.addr_v2.remove(&peer);
+                    self.wtxid_relay.remove(&peer);
+                }
+                None => {
+                    return ControlFlow::Break(Some(Err(Error::Internal(format!(
+                        "phantom peer {peer} disconnected"
+                    )))))
+                }
+            },
+
+            _ => {}
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    /// Does whatever is due by now regardless of events: selects broadcast peers and announces to
+    /// them, rotates, escalates, replaces lost peers and decides whether the broadcast is over.
+    pub fn tick(&mut self, outbox: &impl Outbox<P>, notify: &impl Fn(Info) -> Option<()>) -> Flow {
+        #[cfg(feature = "raw-messages")]
+        if let Some(raw_messages) = &self.opts.raw_messages {
+            for raw in raw_messages.try_iter() {
+                let targets = self.state.iter().filter_map(|(id, p)| match p {
+                    Peer::Ready { service } if raw.peer.is_none() => Some((*id, *service)),
+                    Peer::Ready { service } if raw.peer == Some(*service) => Some((*id, *service)),
+                    _ => None,
+                });
+                for (id, service) in targets {
+                    log::debug!("raw {}: peer @ {}", raw.message.cmd(), redact::r(service));
+                    outbox.raw(id, raw.message.clone());
+                }
+            }
+        }
+
+        if self.opts.escalation.is_none() {
+            // the local node is the user's own and is never rotated out
+            let stale = self.selected.iter().filter(|selected| {
+                selected.is_stale(self.clock.now())
+                    && self.state.get(&selected.id).map(Peer::service) != self.local_node
+            });
+            for selected in stale {
+                log::warn!("rotating broadcast peer");
+                outbox.disconnect(selected.id);
+            }
+        }
+
+        if !self.presence_settled {
+            match self.presence_until {
+                None => {
+                    let ready: Vec<_> = self
+                        .state
+                        .iter()
+                        .filter_map(|(id, p)| match p {
+                            Peer::Ready { service } => Some((*id, *service)),
+                            _ => None,
+                        })
+                        .collect();
+                    let enough = ready.len() >= PROBE_PEERS.min(self.opts.peer_count());
+                    if enough
+                        || (!ready.is_empty() && self.clock.elapsed(self.start) >= PRESENCE_WINDOW)
+                    {
+                        let txids: Vec<_> =
+                            self.tx_map.keys().take(INV_CHUNK_SIZE).copied().collect();
+                        for (id, service) in ready.into_iter().take(PROBE_PEERS) {
+                            log::info!(
+                                "presence probe for {} txs: peer @ {}",
+                                txids.len(),
+                                redact::r(service)
+                            );
+                            let by_wtxid = self.wtxid_relay.contains(&id);
+                            let inv = txids
+                                .iter()
+                                .map(|txid| inventory(*txid, by_wtxid, &self.wtxid_of))
+                                .collect();
+                            outbox.getdata(id, inv);
+                            self.presence_peers.insert(id);
+                        }
+                        self.presence_until = Some(self.clock.now() + PRESENCE_WINDOW);
+                    }
+                }
+                Some(until) => {
+                    if self.clock.now() >= until || self.already_known.len() == self.tx_map.len() {
+                        log::info!(
+                            "{} txs already known to the network",
+                            self.already_known.len()
+                        );
+                        self.presence_settled = true;
+                    }
+                }
+            }
+        }
+
+        let listened = match self.opts.listen_first {
+            Some(window) => {
+                let any_ready = self.state.values().any(|p| matches!(p, Peer::Ready { .. }));
+                if self.listen_until.is_none() && any_ready {
+                    log::info!("listening for {}s before announcing", window.as_secs());
+                    self.listen_until = Some(self.clock.now() + window);
+                }
+                self.listen_until
+                    .is_some_and(|until| self.clock.now() >= until)
+            }
+            None => true,
+        };
+
+        let mut listening = true;
+        while self.selected.len() < usize::from(self.opts.broadcast_peers)
+            && self.presence_settled
+            && listened
+            && !self.halted
+        {
+            let new_selected = self
+                .state
+                .iter()
+                .filter_map(|(id, p)| match p {
+                    Peer::Ready { service } if self.selected.iter().all(|s| s.id != *id) => {
+                        Some((*service, *id))
+                    }
+                    _ => None,
+                })
+                .min_by_key(|(service, _)| Some(*service) != self.local_node);
+
+            let Some((service, id)) = new_selected else {
+                break;
+            };
+            log::info!("selected broadcast peer @ {}", redact::r(service));
+            if !self.broadcast_started {
+                self.broadcast_started = true;
+                if let Some(budget) = self.opts.phase_timeouts.broadcast {
+                    self.deadline = self.deadline.min(self.clock.now() + budget);
+                }
+            }
+            let mut new_selected = BroadcastPeer::new(id, self.clock.now());
+            self.escalated.insert(id);
+            self.escalated_at.get_or_insert_with(|| self.clock.now());
+            self.broadcast_netgroups.insert(service.netgroup());
+            if self.tx_map.len() <= MAX_PUSHED_TXS && !self.opts.dry_run {
+                let unknown = self
+                    .tx_map
+                    .iter()
+                    .filter(|(t, _)| !self.already_known.contains(*t));
+                for (txid, tx) in unknown {
+                    #[cfg(feature = "chaos")]
+                    if self.chaos.reject() {
+                        log::info!("chaos: rejecting {txid} instead of sending it");
+                        self.rejects
+                            .insert(crate::Txid(*txid), synthetic_reject(service));
+                        continue;
+                    }
+                    log::info!("broadcasting to {}", redact::r(service));
+                    outbox.tx(id, tx.clone());
+                    self.attest.sent(service, "tx", *txid);
+                    self.pushed.insert(*txid);
+                    self.latency.sent(*txid, self.clock.now());
+                    telemetry::broadcast_attempt(self.via_tor);
+                }
+            } else {
+                log::info!(
+                    "announcing {} transactions to {}",
+                    self.tx_map.len(),
+                    redact::r(service)
+                );
+                new_selected.queue = self
+                    .tx_map
+                    .keys()
+                    .filter(|txid| {
+                        !self.acks.contains(*txid) && !self.already_known.contains(*txid)
+                    })
+                    .copied()
+                    .collect();
+            }
+            self.selected.push(new_selected);
+            if notify(Info::Broadcast { peer: service }).is_none() {
+                listening = false;
+                break;
+            }
+        }
+        if !listening {
+            return ControlFlow::Break(None);
+        }
+
+        for selected in &mut self.selected {
+            let chunk_size = selected.queue.len().min(INV_CHUNK_SIZE);
+            if chunk_size > 0 {
+                let service = self.state.get(&selected.id).map(Peer::service);
+                let by_wtxid = self.wtxid_relay.contains(&selected.id);
+                let inv = selected
+                    .queue
+                    .drain(..chunk_size)
+                    .inspect(|txid| {
+                        self.latency.sent(*txid, self.clock.now());
+                        if let Some(service) = service {
+                            self.attest.sent(service, "inv", *txid);
+                        }
+                    })
+                    .map(|txid| inventory(txid, by_wtxid, &self.wtxid_of))
+                    .collect();
+                outbox.inv(selected.id, inv);
+            }
+        }
+
+        if let (Some(escalation), Some(since)) = (&self.opts.escalation, self.escalated_at) {
+            let due = !self.halted
+                && self.escalation_stage < escalation.max_stage
+                && self.clock.elapsed(since) >= escalation.window;
+            let unacked: Vec<_> = if due {
+                self.tx_map
+                    .iter()
+                    .filter(|(txid, _)| {
+                        !self.acks.contains(*txid) && !self.already_known.contains(*txid)
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            if !unacked.is_empty() {
+                self.escalation_stage += 1;
+                self.escalated_at = Some(self.clock.now());
+                let peers: Vec<_> = self
+                    .state
+                    .iter()
+                    .filter_map(|(id, p)| match p {
+                        Peer::Ready { service } if !self.escalated.contains(id) => {
+                            Some((*id, *service))
+                        }
+                        _ => None,
+                    })
+                    .take(
+                        1usize
+                            .checked_shl(self.escalation_stage)
+                            .unwrap_or(usize::MAX),
+                    )
+                    .collect();
+                log::info!(
+                    "escalating to stage {}: {} more peers",
+                    self.escalation_stage,
+                    peers.len()
+                );
+
+                let mut listening = true;
+                for (id, service) in peers {
+                    self.escalated.insert(id);
+                    self.broadcast_netgroups.insert(service.netgroup());
+                    if !self.opts.dry_run {
+                        if unacked.len() <= MAX_PUSHED_TXS {
+                            for (txid, tx) in &unacked {
+                                #[cfg(feature = "chaos")]
+                                if self.chaos.reject() {
+                                    log::info!("chaos: rejecting {txid} instead of sending it");
+                                    let reject = synthetic_reject(service);
+                                    self.rejects.insert(crate::Txid(**txid), reject);
+                                    continue;
+                                }
+                                outbox.tx(id, (*tx).clone());
+                                self.attest.sent(service, "tx", **txid);
+                                self.pushed.insert(**txid);
+                                self.latency.sent(**txid, self.clock.now());
+                                telemetry::broadcast_attempt(self.via_tor);
+                            }
+                        } else {
+                            let by_wtxid = self.wtxid_relay.contains(&id);
+                            for chunk in unacked.chunks(INV_CHUNK_SIZE) {
+                                let inv = chunk
+                                    .iter()
+                                    .inspect(|(txid, _)| self.attest.sent(service, "inv", **txid))
+                                    .map(|(txid, _)| inventory(**txid, by_wtxid, &self.wtxid_of))
+                                    .collect();
+                                outbox.inv(id, inv);
+                            }
+                        }
+                    }
+                    listening &= notify(Info::Broadcast { peer: service }).is_some();
+                }
+                if !listening {
+                    return ControlFlow::Break(None);
+                }
+            }
+        }
+
+        let now = self.clock.now();
+
+        let connect_timed_out = self
+            .opts
+            .phase_timeouts
+            .connect
+            .is_some_and(|budget| now - self.start >= budget);
+        if !self.broadcast_started && connect_timed_out {
+            log::error!("no peer completed its handshake in time");
+            return ControlFlow::Break(Some(Err(Error::ConnectTimeout)));
+        }
+
+        if !self.opts.ack_timeout.is_zero() {
+            let expired: Vec<_> = self
+                .tx_map
+                .keys()
+                .filter(|txid| self.awaiting_ack(txid))
+                .filter(|txid| {
+                    self.latency
+                        .sent_at(txid)
+                        .is_some_and(|sent| now >= sent + self.opts.ack_timeout)
+                })
+                .copied()
+                .collect();
+            for txid in expired {
+                log::warn!("no ack in time, giving up: {}", redact::r(txid));
+                self.timed_out.insert(txid);
+                let warning = Warning::AckTimeout {
+                    txid: crate::Txid(txid),
+                    diagnosis: self.evidence.diagnose(&txid, &self.acks),
+                };
+                if notify(Info::Warning(warning)).is_none() {
+                    return ControlFlow::Break(None);
+                }
+            }
+        }
+
+        if !self.probed && !self.opts.dry_run && now + PROBE_WINDOW >= self.deadline {
+            self.probed = true;
+            let txids: Vec<_> = self
+                .tx_map
+                .keys()
+                .filter(|txid| {
+                    !self.acks.contains(*txid)
+                        && !self.rejects.contains_key(&crate::Txid(**txid))
+                        && !self.timed_out.contains(*txid)
+                })
+                .take(INV_CHUNK_SIZE)
+                .copied()
+                .collect();
+
+            if !txids.is_empty() {
+                let peers = self
+                    .state
+                    .iter()
+                    .filter_map(|(id, p)| match p {
+                        Peer::Ready { service }
+                            if !self.broadcast_netgroups.contains(&service.netgroup()) =>
+                        {
+                            Some((*id, *service))
+                        }
+                        _ => None,
+                    })
+                    .take(PROBE_PEERS);
+
+                for (id, service) in peers {
+                    log::info!(
+                        "probing for {} unacked txs: peer @ {}",
+                        txids.len(),
+                        redact::r(service)
+                    );
+                    let by_wtxid = self.wtxid_relay.contains(&id);
+                    let inv = txids
+                        .iter()
+                        .map(|txid| inventory(*txid, by_wtxid, &self.wtxid_of))
+                        .collect();
+                    outbox.getdata(id, inv);
+                    self.probes.insert(id);
+                }
+            }
+        }
+
+        let all_done = self.opts.success_policy.is_met(
+            self.tx_map
+                .keys()
+                .map(|txid| ack_count(txid, &self.acks, &self.already_known, &self.latency)),
+        );
+        // watching for blocks extends lingering
+        let stay = self.opts.linger.max(self.opts.watch_blocks);
+        if all_done && self.linger_until.is_none() && !stay.is_zero() {
+            log::info!("all txs seen, lingering for {}s", stay.as_secs());
+            self.linger_until = Some(now + stay);
+            self.deadline = now + stay;
+        }
+        // lingering ends early once every peer is gone since nothing more can arrive, or once
+        // every transaction is confirmed since nothing more can change
+        let lingered = !self.linger_until.is_some_and(|until| now < until)
+            || (self.state.is_empty() && self.dialing.is_empty())
+            || self.tx_map.keys().all(|txid| self.confirmed.contains(txid));
+
+        // once the transactions given up on rule out success there is nothing left to wait for
+        let hopeless = !self.timed_out.is_empty()
+            && !self
+                .opts
+                .success_policy
+                .is_met(self.tx_map.keys().map(|txid| {
+                    if self.timed_out.contains(txid) && !self.acks.contains(txid) {
+                        0
+                    } else {
+                        usize::MAX
+                    }
+                }));
+
+        if (all_done && lingered) || now >= self.deadline || self.halted || hopeless {
+            log::info!("broadcast stop");
+            // an announcement that nobody asked for but that came back anyway means the
+            // network had the transaction already
+            for txid in &self.acks {
+                if !self.pushed.contains(txid) && !self.latency.was_requested(txid) {
+                    log::info!(
+                        "seen but never requested, already known: {}",
+                        redact::r(txid)
+                    );
+                    self.already_known.insert(*txid);
+                }
+            }
+            let diagnoses = self
+                .tx_map
+                .keys()
+                .filter(|txid| {
+                    !self.acks.contains(*txid)
+                        && !self.already_known.contains(*txid)
+                        && !self.rejects.contains_key(&crate::Txid(**txid))
+                })
+                .map(|txid| (crate::Txid(*txid), self.evidence.diagnose(txid, &self.acks)))
+                .collect();
+            let policy_met = self.opts.success_policy.is_met(
+                self.tx_map
+                    .keys()
+                    .map(|txid| ack_count(txid, &self.acks, &self.already_known, &self.latency)),
+            );
+            let sent_and_acked = self.acks.difference(&self.already_known).next().is_some();
+            let escalation_stage = (self.opts.escalation.is_some() && all_done && sent_and_acked)
+                .then_some(self.escalation_stage);
+
+            let unsent = self
+                .tx_map
+                .keys()
+                .filter(|txid| !self.latency.was_sent(txid) && !self.already_known.contains(*txid))
+                .map(|txid| crate::Txid(*txid))
+                .collect();
+            let (latencies, interest) = std::mem::take(&mut self.latency).into_report();
+            let success = self
+                .acks
+                .difference(&self.already_known)
+                .copied()
+                .map(crate::Txid)
+                .collect();
+            let attestation = std::mem::take(&mut self.attest).finish(
+                &self.opts.attestation,
+                self.opts.network,
+                self.tx_map.keys().copied(),
+                &success,
+            );
+            return ControlFlow::Break(Some(Ok(Report {
+                success,
+                wtxids: self
+                    .wtxid_of
+                    .iter()
+                    .map(|(txid, wtxid)| (crate::Txid(*txid), *wtxid))
+                    .collect(),
+                already_known: std::mem::take(&mut self.already_known)
+                    .into_iter()
+                    .map(crate::Txid)
+                    .collect(),
+                policy_met,
+                unsent,
+                timed_out: self
+                    .timed_out
+                    .difference(&self.acks)
+                    .copied()
+                    .map(crate::Txid)
+                    .collect(),
+                rejects: std::mem::take(&mut self.rejects),
+                diagnoses,
+                explorer_seen: None,
+                nostr_published: None,
+                electrum_published: None,
+                rpc_submitted: None,
+                latencies,
+                interest,
+                escalation_stage,
+                connect_failures: std::mem::take(&mut self.connect_failures),
+                feefilters: self.evidence.feefilters().clone(),
+                attestation,
+            })));
+        }
+
+        if !self.opts.handshake_stall.is_zero() {
+            for (peer, started) in &self.handshake_started {
+                if self.clock.elapsed(*started) >= self.opts.handshake_stall
+                    && self.stall_replaced.insert(*peer)
+                {
+                    if let Some(service) = self.state.get(peer).map(Peer::service) {
+                        log::info!(
+                            "handshake stalled, dialing a replacement: peer @ {}",
+                            redact::r(service)
+                        );
+                    }
+                    self.need_replacements += 1;
+                }
+            }
+        }
+
+        let round_due = !matches!(
+            self.last_replacement_round,
+            Some(last) if self.clock.elapsed(last) < self.opts.replacement_interval
+        );
+        if self.need_replacements > 0 && round_due && self.linger_until.is_none() {
+            // the address book is scanned once per round rather than once per replacement,
+            // since a round can replace hundreds of peers
+            let busy: HashSet<_> = self
+                .state
+                .values()
+                .map(Peer::service)
+                .chain(self.dialing.keys().copied())
+                .collect();
+            let mut candidates: Vec<_> = self
+                .addressbook
+                .iter()
+                .filter(|addr| {
+                    !busy.contains(*addr)
+                        && !self.scoreboard.is_banned(addr)
+                        && self.attempts.get(*addr).copied().unwrap_or_default()
+                            < MAX_ATTEMPTS_PER_ADDRESS
+                })
+                .copied()
+                .collect();
+            self.rng.shuffle(&mut candidates);
+
+            let allowed = self
+                .opts
+                .max_connection_attempts
+                .saturating_sub(self.total_attempts);
+            if allowed < self.need_replacements {
+                log::warn!(
+                    "connection attempt limit of {} reached",
+                    self.total_attempts
+                );
+            }
+            let wanted = self.need_replacements.min(allowed);
+            if candidates.len() < wanted {
+                log::warn!("no unused peers left in the address book");
+            }
+            for replacement in candidates.into_iter().take(wanted) {
+                #[cfg(feature = "chaos")]
+                self.chaos.connect(outbox, replacement);
+                #[cfg(not(feature = "chaos"))]
+                outbox.connect(replacement);
+                self.dialing.insert(replacement, self.clock.now());
+                *self.attempts.entry(replacement).or_default() += 1;
+                self.total_attempts += 1;
+                log::info!("picked replacement peer @ {}", redact::r(replacement));
+            }
+            self.need_replacements = 0;
+            self.last_replacement_round = Some(self.clock.now());
+        }
+
+        let phase = match (self.broadcast_started, self.linger_until) {
+            (_, Some(_)) => Phase::Lingering,
+            (true, None) => Phase::Broadcasting,
+            (false, None) => Phase::Connecting,
+        };
+        let fraction = self.progress(phase, now).max(self.progress.1);
+        if phase != self.progress.0
+            || fraction - self.progress.1 >= PROGRESS_STEP
+            || now >= self.progress_sent + PROGRESS_INTERVAL
+        {
+            self.progress = (phase, fraction);
+            self.progress_sent = now;
+            if notify(Info::Progress { phase, fraction }).is_none() {
+                return ControlFlow::Break(None);
+            }
+        }
+
+        if let Some(monitor) = &self.opts.monitor {
+            let acks = self
+                .tx_map
+                .keys()
+                .filter(|txid| self.acks.contains(*txid))
+                .map(|txid| (crate::Txid(*txid), self.latency.ack_count(txid).max(1)))
+                .collect();
+            monitor.update(phase, self.peers(), acks);
+        }
+
+        // nothing connected, nothing in flight and no replacement was possible
+        if self.state.is_empty() && self.dialing.is_empty() && self.need_replacements == 0 {
+            log::error!("all {} connection attempts failed", self.total_attempts);
+            return ControlFlow::Break(Some(Err(Error::AllConnectionsFailed {
+                attempts: self.total_attempts,
+            })));
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    /// How long to wait for the next event before calling `tick` again. `pending` tells whether
+    /// the p2p client is holding back queued messages.
+    pub fn wait(&self, pending: bool) -> Duration {
+        // whatever was due by now was handled above, so only deadlines still ahead count
+        let mut wakeup = Wakeup::new(self.clock.now());
+        wakeup.at(self.deadline);
+        wakeup.at(self.progress_sent + PROGRESS_INTERVAL);
+        if !self.probed && !self.opts.dry_run {
+            wakeup.at(self
+                .deadline
+                .checked_sub(PROBE_WINDOW)
+                .unwrap_or(self.start));
+        }
+        if let (false, Some(budget)) = (self.broadcast_started, self.opts.phase_timeouts.connect) {
+            wakeup.at(self.start + budget);
+        }
+        if self.opts.escalation.is_none() {
+            for selected in &self.selected {
+                wakeup.at(selected.when + BROADCAST_PEER_LIFETIME);
+            }
+        }
+        if let (Some(escalation), Some(since)) = (&self.opts.escalation, self.escalated_at) {
+            wakeup.at(since + escalation.window);
+        }
+        if !self.presence_settled {
+            wakeup.at(self.presence_until.unwrap_or(self.start + PRESENCE_WINDOW));
+        }
+        if let Some(until) = self.listen_until {
+            wakeup.at(until);
+        }
+        if !self.opts.handshake_stall.is_zero() {
+            for started in self.handshake_started.values() {
+                wakeup.at(*started + self.opts.handshake_stall);
+            }
+        }
+        if !self.opts.ack_timeout.is_zero() {
+            for txid in self.tx_map.keys().filter(|txid| self.awaiting_ack(txid)) {
+                if let Some(sent) = self.latency.sent_at(txid) {
+                    wakeup.at(sent + self.opts.ack_timeout);
+                }
+            }
+        }
+        if let (true, Some(last)) = (self.need_replacements > 0, self.last_replacement_round) {
+            wakeup.at(last + self.opts.replacement_interval);
+        }
+        if pending {
+            wakeup.after(THROTTLE_RETRY);
+        }
+        #[cfg(feature = "chaos")]
+        if let Some(delay) = self.chaos.next_release() {
+            wakeup.after(delay);
+        }
+        #[cfg(feature = "raw-messages")]
+        if self.opts.raw_messages.is_some() {
+            wakeup.after(RAW_MESSAGE_POLL);
+        }
+        // announcements still queued go out on the next pass right away
+        if self
+            .selected
+            .iter()
+            .any(|selected| !selected.queue.is_empty())
+        {
+            Duration::ZERO
+        } else {
+            wakeup.wait()
+        }
+    }
+
+    /// An estimate of how far along the broadcast is, see `Info::Progress`. Connecting takes up
+    /// the first quarter and gathering acks the rest, while the time used up is a floor.
+    fn progress(&self, phase: Phase, now: time::Instant) -> f32 {
+        let work = match phase {
+            Phase::Connecting => {
+                let target = self.opts.broadcast_peers as usize + self.opts.monitor_peers as usize;
+                let ready = self
+                    .state
+                    .values()
+                    .filter(|p| matches!(p, Peer::Ready { .. }))
+                    .count();
+                0.25 * (ready as f32 / target.max(1) as f32).min(1.0)
+            }
+            _ => {
+                let acks = self
+                    .tx_map
+                    .keys()
+                    .map(|txid| ack_count(txid, &self.acks, &self.already_known, &self.latency));
+                0.25 + 0.75 * self.opts.success_policy.progress(acks)
+            }
+        };
+        let budget = self.deadline.saturating_duration_since(self.start);
+        let used = now.saturating_duration_since(self.start);
+        let time = used.as_secs_f32() / budget.as_secs_f32().max(f32::EPSILON);
+        work.max(time).min(1.0)
+    }
+
+    /// Whether a transaction is still waiting for its first ack.
+    fn awaiting_ack(&self, txid: &bitcoin::Txid) -> bool {
+        !self.acks.contains(txid)
+            && !self.already_known.contains(txid)
+            && !self.rejects.contains_key(&crate::Txid(*txid))
+            && !self.timed_out.contains(txid)
+    }
+
+    /// The peers being dialed or connected and how far along they are.
+    fn peers(&self) -> Vec<PeerStatus> {
+        let dialing = self.dialing.keys().map(|peer| PeerStatus {
+            peer: *peer,
+            state: PeerState::Connecting,
+        });
+        let connected = self.state.iter().map(|(id, p)| {
+            let state = match p {
+                Peer::Handshaking(..) => PeerState::Handshaking,
+                Peer::Ready { .. } if self.selected.iter().any(|s| s.id == *id) => {
+                    PeerState::Selected
+                }
+                Peer::Ready { .. } => PeerState::Ready,
+            };
+            PeerStatus {
+                peer: p.service(),
+                state,
+            }
+        });
+        dialing.chain(connected).collect()
+    }
+
+    /// Ends the broadcast: reports the connections still open or pending, which are over now, and
+    /// hands back the transactions and the random number generator for the follow-up work.
+    pub fn close(self) -> (HashMap<bitcoin::Txid, p2p::SharedTx>, fastrand::Rng) {
+        // whatever is still open or pending is over once the broadcast is
+        for (service, dialed_at, connected_at, proxy) in self.connections.into_values() {
+            let outcome = crate::ConnectionOutcome::Closed;
+            log_connection(
+                &self.opts.connection_log,
+                &*self.clock,
+                service,
+                dialed_at,
+                Some(connected_at),
+                proxy,
+                outcome,
+            );
+        }
+        for (service, when) in self.dialing {
+            let dialed_at = self.clock.system_now() - self.clock.elapsed(when);
+            let outcome = crate::ConnectionOutcome::Abandoned;
+            log_connection(
+                &self.opts.connection_log,
+                &*self.clock,
+                service,
+                dialed_at,
+                None,
+                self.proxies.as_ref().map(p2p::ProxyPool::current),
+                outcome,
+            );
+        }
+
+        (self.tx_map, self.rng)
+    }
+}
+
+/// How a transaction is referred to in `inv` and `getdata` messages: by wtxid to peers that
+/// negotiated wtxid relay, by txid otherwise.
+fn inventory(
+    txid: bitcoin::Txid,
+    by_wtxid: bool,
+    wtxid_of: &HashMap<bitcoin::Txid, bitcoin::Wtxid>,
+) -> Inventory {
+    match wtxid_of.get(&txid) {
+        Some(wtxid) if by_wtxid => Inventory::WTx(*wtxid),
+        _ => Inventory::Transaction(txid),
+    }
+}
+
+/// A reject injected in place of sending a transaction to a peer, see `Opts::chaos`.
+#[cfg(feature = "chaos")]
+fn synthetic_reject(peer: net::Service) -> Reject {
+    Reject {
+        reason: crate::RejectReason::Invalid,
+        message: "synthetic reject".to_string(),
+        peer: Some(peer),
+    }
+}
+
+/// Converts addresses for a peer that asked for `addrv2` messages.
+fn addr_v2(addrs: Vec<(u32, bitcoin::p2p::Address)>) -> Vec<AddrV2Message> {
+    addrs
+        .into_iter()
+        .filter_map(|(time, addr)| {
+            let socket = addr.socket_addr().ok()?;
+            let ip = match socket.ip() {
+                std::net::IpAddr::V4(ip) => AddrV2::Ipv4(ip),
+                std::net::IpAddr::V6(ip) => AddrV2::Ipv6(ip),
+            };
+            Some(AddrV2Message {
+                time,
+                services: addr.services,
+                addr: ip,
+                port: socket.port(),
+            })
+        })
+        .collect()
+}
+
+/// Peer status.
+enum Peer {
+    /// Currently handshaking.
+    Handshaking(net::Service, Handshake),
+    /// Handshake established, ready for interaction.
+    Ready { service: net::Service },
+}
+
+impl Peer {
+    /// The address of the peer.
+    fn service(&self) -> net::Service {
+        match self {
+            Peer::Handshaking(service, _) | Peer::Ready { service } => *service,
+        }
+    }
+}
+
+/// A single peer that we have selected for our transaction broadcast.
+struct BroadcastPeer<P: p2p::Peerlike> {
+    /// The id of the peer.
+    id: P,
+    /// The time the broadcast took place.
+    when: std::time::Instant,
+    /// The transactions still to be announced to the peer.
+    queue: VecDeque<bitcoin::Txid>,
+}
+
+impl<P: p2p::Peerlike> BroadcastPeer<P> {
+    fn new(id: P, now: time::Instant) -> Self {
+        Self {
+            id,
+            when: now,
+            queue: VecDeque::new(),
+        }
+    }
+    /// Whether the peer is stale and should be rotated.
+    fn is_stale(&self, now: time::Instant) -> bool {
+        now.saturating_duration_since(self.when) >= BROADCAST_PEER_LIFETIME
+    }
+}
+
+/// The earliest of the deadlines still ahead, to sleep until when nothing else happens.
+struct Wakeup {
+    now: time::Instant,
+    next: Option<time::Instant>,
+}
+
+impl Wakeup {
+    fn new(now: time::Instant) -> Self {
+        Self { now, next: None }
+    }
+
+    /// Adds a deadline. Deadlines that are not ahead are ignored.
+    fn at(&mut self, when: time::Instant) {
+        if when > self.now {
+            self.next = Some(self.next.map_or(when, |next| next.min(when)));
+        }
+    }
+
+    /// Adds a deadline relative to now.
+    fn after(&mut self, delay: Duration) {
+        self.at(self.now + delay);
+    }
+
+    /// How long to wait for. A deadline is always set, since `max_time` always applies.
+    fn wait(&self) -> Duration {
+        self.next
+            .map(|next| next.saturating_duration_since(self.now))
+            .unwrap_or(BROADCAST_PEER_LIFETIME)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::testing::ManualClock;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    struct TestPeer(u32);
+
+    impl std::fmt::Display for TestPeer {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "test#{}", self.0)
+        }
+    }
+
+    impl p2p::Peerlike for TestPeer {}
+
+    /// Records the addresses dialed, the peers disconnected and the peers that transactions were
+    /// pushed to, and ignores everything else.
+    #[derive(Default)]
+    struct Outgoing {
+        dialed: RefCell<Vec<net::Service>>,
+        disconnected: RefCell<Vec<TestPeer>>,
+        pushed: RefCell<Vec<TestPeer>>,
+    }
+
+    impl Outbox<TestPeer> for Outgoing {
+        fn connect(&self, target: net::Service) {
+            self.dialed.borrow_mut().push(target);
+        }
+        fn disconnect(&self, peer: TestPeer) {
+            self.disconnected.borrow_mut().push(peer);
+        }
+        fn version(&self, _peer: TestPeer, _target: net::Service) {}
+        fn verack(&self, _peer: TestPeer) {}
+        fn sendaddrv2(&self, _peer: TestPeer) {}
+        fn wtxidrelay(&self, _peer: TestPeer) {}
+        fn tx(&self, peer: TestPeer, _tx: p2p::SharedTx) {
+            self.pushed.borrow_mut().push(peer);
+        }
+        fn inv(&self, _peer: TestPeer, _inv: Vec<Inventory>) {}
+        fn getdata(&self, _peer: TestPeer, _inv: Vec<Inventory>) {}
+        fn addr(&self, _peer: TestPeer, _addrs: Vec<(u32, bitcoin::p2p::Address)>) {}
+        fn addrv2(&self, _peer: TestPeer, _addrs: Vec<AddrV2Message>) {}
+        #[cfg(feature = "raw-messages")]
+        fn raw(&self, _peer: TestPeer, _message: NetworkMessage) {}
+    }
+
+    /// A transaction without inputs or outputs, told apart from the others by its lock time.
+    fn tx(n: u32) -> bitcoin::Transaction {
+        bitcoin::Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::from_consensus(n),
+            input: vec![],
+            output: vec![],
+        }
+    }
+
+    fn machine(opts: Opts, addressbook: Vec<net::Service>) -> Machine<TestPeer> {
+        batch_machine(opts, addressbook, &[tx(0)])
+    }
+
+    fn batch_machine(
+        opts: Opts,
+        addressbook: Vec<net::Service>,
+        txs: &[bitcoin::Transaction],
+    ) -> Machine<TestPeer> {
+        let batch = Batch {
+            tx_map: txs
+                .iter()
+                .map(|tx| (tx.txid(), p2p::SharedTx::new(tx)))
+                .collect(),
+            wtxids: HashMap::new(),
+            spent: HashMap::new(),
+            evidence: Evidence::default(),
+            attest: attestation::Recorder::new(&opts.attestation),
+        };
+        Machine::new(
+            opts,
+            batch,
+            addressbook,
+            None,
+            None,
+            fastrand::Rng::with_seed(0),
+        )
+    }
+
+    fn refused(target: net::Service) -> p2p::Event<TestPeer> {
+        p2p::Event::ConnectedTo {
+            target,
+            result: Err(std::io::ErrorKind::ConnectionRefused.into()),
+        }
+    }
+
+    fn received(
+        machine: &Machine<TestPeer>,
+        peer: TestPeer,
+        payload: NetworkMessage,
+    ) -> p2p::Event<TestPeer> {
+        p2p::Event::Message {
+            peer,
+            message: p2p::Inbound::new(machine.magic, payload),
+        }
+    }
+
+    /// The version of a recent full node that meets the default peer requirements.
+    fn version() -> bitcoin::p2p::message_network::VersionMessage {
+        let address = bitcoin::p2p::Address {
+            services: bitcoin::p2p::ServiceFlags::NONE,
+            address: [0; 8],
+            port: 0,
+        };
+        bitcoin::p2p::message_network::VersionMessage {
+            version: 70016,
+            services: bitcoin::p2p::ServiceFlags::NETWORK | bitcoin::p2p::ServiceFlags::WITNESS,
+            timestamp: 0,
+            receiver: address.clone(),
+            sender: address,
+            nonce: 0,
+            user_agent: "/Satoshi:27.0.0/".to_string(),
+            start_height: 0,
+            relay: true,
+        }
+    }
+
+    /// Connects to `target` as `peer` and completes the handshake.
+    fn ready(
+        machine: &mut Machine<TestPeer>,
+        outbox: &Outgoing,
+        target: net::Service,
+        peer: TestPeer,
+    ) {
+        let connected = p2p::Event::ConnectedTo {
+            target,
+            result: Ok(peer),
+        };
+        assert!(machine.handle(connected, outbox, &listening).is_continue());
+        for payload in [NetworkMessage::Version(version()), NetworkMessage::Verack] {
+            let event = received(machine, peer, payload);
+            assert!(machine.handle(event, outbox, &listening).is_continue());
+        }
+    }
+
+    fn listening(_info: Info) -> Option<()> {
+        Some(())
+    }
+
+    #[test]
+    fn all_connections_failing_ends_the_broadcast() {
+        let peers: Vec<net::Service> = vec![
+            "10.1.0.1:8333".parse().unwrap(),
+            "10.2.0.1:8333".parse().unwrap(),
+        ];
+        let opts = Opts {
+            max_connection_attempts: 2,
+            ..Default::default()
+        };
+        let outbox = Outgoing::default();
+        let mut machine = machine(opts, peers.clone());
+        machine.dial(&outbox);
+        assert_eq!(*outbox.dialed.borrow(), peers);
+
+        for peer in &peers {
+            let flow = machine.handle(refused(*peer), &outbox, &listening);
+            assert!(flow.is_continue());
+        }
+        match machine.tick(&outbox, &listening) {
+            ControlFlow::Break(Some(Err(Error::AllConnectionsFailed { attempts }))) => {
+                assert_eq!(attempts, 2)
+            }
+            _ => panic!("expected the broadcast to fail"),
+        }
+    }
+
+    #[test]
+    fn lost_peers_are_redialed_a_limited_number_of_times() {
+        let peer: net::Service = "10.1.0.1:8333".parse().unwrap();
+        let opts = Opts {
+            replacement_interval: Duration::ZERO,
+            ..Default::default()
+        };
+        let outbox = Outgoing::default();
+        let mut machine = machine(opts, vec![peer]);
+        machine.dial(&outbox);
+
+        let connected = p2p::Event::ConnectedTo {
+            target: peer,
+            result: Ok(TestPeer(0)),
+        };
+        assert!(machine.handle(connected, &outbox, &listening).is_continue());
+        let lost = p2p::Event::Disconnected {
+            peer: TestPeer(0),
+            reason: p2p::DisconnectReason::Left,
+        };
+        assert!(machine.handle(lost, &outbox, &listening).is_continue());
+        assert!(machine.tick(&outbox, &listening).is_continue());
+        assert_eq!(*outbox.dialed.borrow(), vec![peer, peer]);
+
+        assert!(machine
+            .handle(refused(peer), &outbox, &listening)
+            .is_continue());
+        assert!(machine.tick(&outbox, &listening).is_break());
+        assert_eq!(outbox.dialed.borrow().len(), 2);
+    }
+
+    #[test]
+    fn monitor_follows_the_connections() {
+        let peer: net::Service = "10.1.0.1:8333".parse().unwrap();
+        let monitor = crate::Monitor::new();
+        let opts = Opts {
+            monitor: Some(monitor.clone()),
+            ..Default::default()
+        };
+        let outbox = Outgoing::default();
+        let mut machine = machine(opts, vec![peer]);
+        machine.dial(&outbox);
+        assert!(machine.tick(&outbox, &listening).is_continue());
+
+        let status = monitor.status();
+        assert_eq!(status.phase, Phase::Connecting);
+        assert_eq!(status.connected, 0);
+        assert!(status.acks_per_tx.is_empty());
+        assert_eq!(monitor.peers().len(), 1);
+        assert_eq!(monitor.peers()[0].state, PeerState::Connecting);
+
+        let connected = p2p::Event::ConnectedTo {
+            target: peer,
+            result: Ok(TestPeer(0)),
+        };
+        assert!(machine.handle(connected, &outbox, &listening).is_continue());
+        assert!(machine.tick(&outbox, &listening).is_continue());
+        assert_eq!(monitor.status().connected, 1);
+    }
+
+    #[test]
+    fn progress_is_sent_when_it_moves() {
+        let peer: net::Service = "10.1.0.1:8333".parse().unwrap();
+        let opts = Opts {
+            broadcast_peers: 1,
+            monitor_peers: 1,
+            clock: Arc::new(ManualClock::new()),
+            ..Default::default()
+        };
+        let outbox = Outgoing::default();
+        let mut machine = machine(opts, vec![peer]);
+        let sent = RefCell::new(Vec::new());
+        let record = |info: Info| {
+            if let Info::Progress { phase, fraction } = info {
+                sent.borrow_mut().push((phase, fraction));
+            }
+            Some(())
+        };
+        machine.dial(&outbox);
+        assert!(machine.tick(&outbox, &record).is_continue());
+        assert!(machine.tick(&outbox, &record).is_continue());
+        // nothing moved, so nothing is sent again
+        assert_eq!(sent.borrow().len(), 1);
+        assert_eq!(sent.borrow()[0], (Phase::Connecting, 0.0));
+
+        // a completed handshake is a step forward
+        ready(&mut machine, &outbox, peer, TestPeer(0));
+        assert!(machine.tick(&outbox, &record).is_continue());
+        let sent = sent.borrow();
+        assert_eq!(sent.len(), 2);
+        assert!(sent[1].1 >= sent[0].1 + PROGRESS_STEP);
+    }
+
+    #[test]
+    fn dropped_receiver_is_noticed_while_idle() {
+        let peer: net::Service = "10.1.0.1:8333".parse().unwrap();
+        let clock = Arc::new(ManualClock::new());
+        let opts = Opts {
+            max_time: Duration::from_secs(1000),
+            clock: clock.clone(),
+            ..Default::default()
+        };
+        let outbox = Outgoing::default();
+        let mut machine = machine(opts, vec![peer]);
+        machine.dial(&outbox);
+        assert!(machine.tick(&outbox, &listening).is_continue());
+
+        // nothing moves, yet the progress goes out again soon and finds nobody listening
+        let dropped = |_info: Info| None;
+        assert!(machine.tick(&outbox, &dropped).is_continue());
+        assert!(machine.wait(false) <= PROGRESS_INTERVAL);
+        clock.advance(PROGRESS_INTERVAL);
+        assert!(matches!(
+            machine.tick(&outbox, &dropped),
+            ControlFlow::Break(None)
+        ));
+    }
+
+    #[test]
+    fn handshakes_take_peers_to_ready_and_into_the_broadcast() {
+        let peer: net::Service = "10.1.0.1:8333".parse().unwrap();
+        let opts = Opts {
+            broadcast_peers: 1,
+            monitor_peers: 0,
+            ..Default::default()
+        };
+        let outbox = Outgoing::default();
+        let mut machine = machine(opts, vec![peer]);
+        machine.dial(&outbox);
+
+        let connected = p2p::Event::ConnectedTo {
+            target: peer,
+            result: Ok(TestPeer(0)),
+        };
+        assert!(machine.handle(connected, &outbox, &listening).is_continue());
+        assert!(matches!(
+            machine.state.get(&TestPeer(0)),
+            Some(Peer::Handshaking(..))
+        ));
+
+        let their_version = received(&machine, TestPeer(0), NetworkMessage::Version(version()));
+        assert!(machine
+            .handle(their_version, &outbox, &listening)
+            .is_continue());
+        assert!(matches!(
+            machine.state.get(&TestPeer(0)),
+            Some(Peer::Handshaking(..))
+        ));
+        // nothing goes out before the handshake is done
+        assert!(machine.tick(&outbox, &listening).is_continue());
+        assert!(outbox.pushed.borrow().is_empty());
+
+        let their_verack = received(&machine, TestPeer(0), NetworkMessage::Verack);
+        assert!(machine
+            .handle(their_verack, &outbox, &listening)
+            .is_continue());
+        assert!(matches!(
+            machine.state.get(&TestPeer(0)),
+            Some(Peer::Ready { service }) if *service == peer
+        ));
+        assert!(machine.tick(&outbox, &listening).is_continue());
+        assert_eq!(*outbox.pushed.borrow(), vec![TestPeer(0)]);
+    }
+
+    #[test]
+    fn every_broadcast_peer_rejecting_fails_the_policy() {
+        let peers: Vec<net::Service> = vec![
+            "10.1.0.1:8333".parse().unwrap(),
+            "10.2.0.1:8333".parse().unwrap(),
+        ];
+        let clock = Arc::new(ManualClock::new());
+        let opts = Opts {
+            broadcast_peers: 2,
+            monitor_peers: 0,
+            clock: clock.clone(),
+            ..Default::default()
+        };
+        let outbox = Outgoing::default();
+        let mut machine = machine(opts.clone(), peers.clone());
+        machine.dial(&outbox);
+        for (n, peer) in peers.iter().enumerate() {
+            ready(&mut machine, &outbox, *peer, TestPeer(n as u32));
+        }
+        assert!(machine.tick(&outbox, &listening).is_continue());
+        assert_eq!(outbox.pushed.borrow().len(), 2);
+
+        let txid = tx(0).txid();
+        for n in 0..2 {
+            let reject = bitcoin::p2p::message_network::Reject {
+                message: bitcoin::p2p::message::CommandString::try_from_static("tx").unwrap(),
+                ccode: bitcoin::p2p::message_network::RejectReason::Invalid,
+                reason: "bad-txns-inputs-missingorspent".into(),
+                hash: txid.to_raw_hash(),
+            };
+            let event = received(&machine, TestPeer(n), NetworkMessage::Reject(reject));
+            assert!(machine.handle(event, &outbox, &listening).is_continue());
+        }
+        assert!(machine.tick(&outbox, &listening).is_continue());
+
+        clock.advance(opts.max_time);
+        let report = match machine.tick(&outbox, &listening) {
+            ControlFlow::Break(Some(Ok(report))) => report,
+            _ => panic!("expected a report once the time is up"),
+        };
+        assert!(report.success.is_empty());
+        assert!(!report.policy_met);
+        assert!(report.rejects.contains_key(&crate::Txid(txid)));
+        assert!(report.diagnoses.is_empty());
+    }
+
+    #[test]
+    fn partial_acks_are_held_against_the_policy() {
+        let peers: Vec<net::Service> = vec![
+            "10.1.0.1:8333".parse().unwrap(),
+            "10.2.0.1:8333".parse().unwrap(),
+        ];
+        let txs = [tx(0), tx(1)];
+        let policies = [
+            (crate::SuccessPolicy::AllAcked, false),
+            (crate::SuccessPolicy::AtLeast(1), true),
+            (crate::SuccessPolicy::AtLeast(2), false),
+            (crate::SuccessPolicy::PeersPerTx(1), false),
+        ];
+        for (policy, met) in policies {
+            let opts = Opts {
+                broadcast_peers: 1,
+                monitor_peers: 1,
+                success_policy: policy,
+                ..Default::default()
+            };
+            let outbox = Outgoing::default();
+            let mut machine = batch_machine(opts, peers.clone(), &txs);
+            machine.dial(&outbox);
+            for (n, peer) in peers.iter().enumerate() {
+                ready(&mut machine, &outbox, *peer, TestPeer(n as u32));
+            }
+            assert!(machine.tick(&outbox, &listening).is_continue());
+
+            // the peer that was not broadcast to acks only the first transaction
+            let broadcast = machine.selected[0].id;
+            let monitor = if broadcast == TestPeer(0) {
+                TestPeer(1)
+            } else {
+                TestPeer(0)
+            };
+            let inv = NetworkMessage::Inv(vec![Inventory::Transaction(txs[0].txid())]);
+            let event = received(&machine, monitor, inv);
+            assert!(machine.handle(event, &outbox, &listening).is_continue());
+
+            match machine.tick(&outbox, &listening) {
+                ControlFlow::Break(Some(Ok(report))) => {
+                    assert!(met, "{policy:?} should not be met");
+                    assert!(report.policy_met);
+                    assert_eq!(report.success, HashSet::from([crate::Txid(txs[0].txid())]));
+                }
+                ControlFlow::Continue(()) => assert!(!met, "{policy:?} should be met"),
+                _ => panic!("unexpected outcome under {policy:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn stale_broadcast_peers_are_rotated_out_and_replaced() {
+        let peers: Vec<net::Service> = vec![
+            "10.1.0.1:8333".parse().unwrap(),
+            "10.2.0.1:8333".parse().unwrap(),
+        ];
+        let clock = Arc::new(ManualClock::new());
+        let opts = Opts {
+            broadcast_peers: 1,
+            monitor_peers: 0,
+            replacement_interval: Duration::ZERO,
+            max_time: Duration::from_secs(1000),
+            clock: clock.clone(),
+            ..Default::default()
+        };
+        let outbox = Outgoing::default();
+        let mut machine = machine(opts, peers.clone());
+        machine.dial(&outbox);
+        assert_eq!(*outbox.dialed.borrow(), vec![peers[0]]);
+        ready(&mut machine, &outbox, peers[0], TestPeer(0));
+        assert!(machine.tick(&outbox, &listening).is_continue());
+        assert_eq!(*outbox.pushed.borrow(), vec![TestPeer(0)]);
+
+        // still fresh, so nothing happens yet
+        clock.advance(BROADCAST_PEER_LIFETIME / 2);
+        assert!(machine.tick(&outbox, &listening).is_continue());
+        assert!(outbox.disconnected.borrow().is_empty());
+
+        clock.advance(BROADCAST_PEER_LIFETIME / 2);
+        assert!(machine.tick(&outbox, &listening).is_continue());
+        assert_eq!(*outbox.disconnected.borrow(), vec![TestPeer(0)]);
+
+        let rotated = p2p::Event::Disconnected {
+            peer: TestPeer(0),
+            reason: p2p::DisconnectReason::Requested,
+        };
+        assert!(machine.handle(rotated, &outbox, &listening).is_continue());
+        assert!(machine.selected.is_empty());
+        assert!(machine.tick(&outbox, &listening).is_continue());
+        let dialed = outbox.dialed.borrow();
+        assert_eq!(dialed.len(), 2);
+        assert!(peers.contains(&dialed[1]));
+    }
+
+    #[test]
+    fn a_storm_of_disconnects_is_redialed_within_the_limits() {
+        let peers: Vec<net::Service> = (1..=20)
+            .map(|n| format!("10.{n}.0.1:8333").parse().unwrap())
+            .collect();
+        let opts = Opts {
+            broadcast_peers: 1,
+            monitor_peers: 4,
+            max_connection_attempts: 12,
+            replacement_interval: Duration::ZERO,
+            ..Default::default()
+        };
+        let outbox = Outgoing::default();
+        let mut machine = machine(opts, peers.clone());
+        machine.dial(&outbox);
+        assert_eq!(outbox.dialed.borrow().len(), 5);
+
+        // every dial connects and the connection drops before the handshake is done
+        let mut next_id = 0;
+        let mut handled = 0;
+        let mut rounds = 0;
+        let outcome = loop {
+            let dialed = outbox.dialed.borrow()[handled..].to_vec();
+            handled += dialed.len();
+            let mut connected = Vec::new();
+            for target in dialed {
+                let peer = TestPeer(next_id);
+                next_id += 1;
+                let event = p2p::Event::ConnectedTo {
+                    target,
+                    result: Ok(peer),
+                };
+                assert!(machine.handle(event, &outbox, &listening).is_continue());
+                connected.push(peer);
+            }
+            for peer in connected {
+                let event = p2p::Event::Disconnected {
+                    peer,
+                    reason: p2p::DisconnectReason::Left,
+                };
+                assert!(machine.handle(event, &outbox, &listening).is_continue());
+            }
+            rounds += 1;
+            assert!(rounds <= 10, "the redials never stop");
+            if let ControlFlow::Break(outcome) = machine.tick(&outbox, &listening) {
+                break outcome;
+            }
+        };
+
+        assert!(matches!(
+            outcome,
+            Some(Err(Error::AllConnectionsFailed { attempts: 12 }))
+        ));
+        let dialed = outbox.dialed.borrow();
+        assert_eq!(dialed.len(), 12);
+        for peer in &peers {
+            let attempts = dialed.iter().filter(|dialed| *dialed == peer).count();
+            assert!(attempts <= MAX_ATTEMPTS_PER_ADDRESS as usize);
+        }
+    }
+
+    #[test]
+    fn flooding_peer_is_disconnected_and_ignored() {
+        let peers: Vec<net::Service> = vec![
+            "10.1.0.1:8333".parse().unwrap(),
+            "10.2.0.1:8333".parse().unwrap(),
+        ];
+        let clock = Arc::new(ManualClock::new());
+        let opts = Opts {
+            broadcast_peers: 1,
+            monitor_peers: 1,
+            clock: clock.clone(),
+            ..Default::default()
+        };
+        let outbox = Outgoing::default();
+        let mut machine = machine(opts, peers.clone());
+        machine.dial(&outbox);
+        for (n, peer) in peers.iter().enumerate() {
+            ready(&mut machine, &outbox, *peer, TestPeer(n as u32));
+        }
+        assert!(machine.tick(&outbox, &listening).is_continue());
+        let flooder = if machine.selected[0].id == TestPeer(0) {
+            TestPeer(1)
+        } else {
+            TestPeer(0)
+        };
+
+        // one flood is tolerated, the second gets the peer banned
+        for _ in 0..2 {
+            for _ in 0..=crate::misbehavior::MAX_MESSAGES_PER_SEC {
+                let ping = received(&machine, flooder, NetworkMessage::Ping(0));
+                assert!(machine.handle(ping, &outbox, &listening).is_continue());
+            }
+            clock.advance(Duration::from_secs(1));
+        }
+        assert_eq!(*outbox.disconnected.borrow(), vec![flooder]);
+
+        let inv = NetworkMessage::Inv(vec![Inventory::Transaction(tx(0).txid())]);
+        let ack = received(&machine, flooder, inv);
+        assert!(machine.handle(ack, &outbox, &listening).is_continue());
+        assert!(machine.acks.is_empty());
+    }
+}
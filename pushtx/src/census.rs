@@ -0,0 +1,167 @@
+//! A snapshot of the versions that peers advertise, taken by handshaking a number of peers
+//! without broadcasting anything.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{self, Duration};
+
+use crate::handshake::{self, Handshake};
+use crate::p2p::{self, Outbox, Receiver, Sender};
+use crate::{broadcast, net, redact, Error, Opts};
+
+/// What a single peer advertised in its `version` message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerVersion {
+    /// The address of the peer.
+    pub peer: net::Service,
+    /// The advertised user agent.
+    pub user_agent: String,
+    /// The advertised protocol version.
+    pub version: u32,
+    /// The advertised service bits.
+    pub services: u64,
+    /// The advertised height of the best block.
+    pub start_height: i32,
+}
+
+/// The versions advertised by the peers that completed a handshake.
+#[derive(Debug, Clone, Default)]
+pub struct Census {
+    /// One entry per peer.
+    pub peers: Vec<PeerVersion>,
+}
+
+impl Census {
+    /// How many peers advertised each user agent, most common first.
+    pub fn user_agents(&self) -> Vec<(String, usize)> {
+        tabulate(self.peers.iter().map(|p| p.user_agent.clone()))
+    }
+
+    /// How many peers advertised each protocol version, most common first.
+    pub fn versions(&self) -> Vec<(u32, usize)> {
+        tabulate(self.peers.iter().map(|p| p.version))
+    }
+
+    /// How many peers advertised each combination of service bits, most common first.
+    pub fn services(&self) -> Vec<(u64, usize)> {
+        tabulate(self.peers.iter().map(|p| p.services))
+    }
+}
+
+/// Counts the occurrences of each value, most common first.
+fn tabulate<T: Eq + std::hash::Hash + Ord>(values: impl Iterator<Item = T>) -> Vec<(T, usize)> {
+    let mut counts: HashMap<T, usize> = HashMap::new();
+    for value in values {
+        *counts.entry(value).or_default() += 1;
+    }
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_unstable_by(|(a, a_n), (b, b_n)| b_n.cmp(a_n).then(a.cmp(b)));
+    counts
+}
+
+/// Handshakes up to `peers` peers from the pool described by `opts` and records what they
+/// advertise. Nothing is broadcast and every peer is disconnected right after its handshake.
+///
+/// Up to `opts.broadcast_peers + opts.monitor_peers` handshakes are in flight at once. Blocks until enough peers were
+/// surveyed, the pool is exhausted or `opts.max_time` is up, and returns whatever was collected.
+pub fn census(opts: &Opts, peers: usize) -> Result<Census, Error> {
+    if opts.max_bandwidth == Some(0) {
+        return Err(Error::InvalidOptions("max_bandwidth is zero".to_string()));
+    }
+    let proxies = broadcast::tor_proxy(&opts.use_tor, &opts.socks_proxies)?;
+    let (addressbook, _) = broadcast::create_node_pool(opts, proxies.is_some(), &mut opts.rng())?;
+
+    let client = p2p::client(
+        proxies,
+        opts.network,
+        opts.protocol_version,
+        &opts.handshake,
+        opts.max_bandwidth,
+        opts.max_message_size,
+        opts.rng(),
+    )?;
+
+    let mut candidates = addressbook.into_iter();
+    let mut dialing = HashSet::new();
+    let mut handshaking = HashMap::new();
+    let mut census = Census::default();
+    let start = time::Instant::now();
+
+    loop {
+        while dialing.len() + handshaking.len() < opts.peer_count()
+            && census.peers.len() + dialing.len() + handshaking.len() < peers
+        {
+            match candidates.next() {
+                Some(addr) => {
+                    client.connect(addr);
+                    dialing.insert(addr);
+                }
+                None => break,
+            }
+        }
+
+        let done = census.peers.len() >= peers
+            || start.elapsed() >= opts.max_time
+            || (dialing.is_empty() && handshaking.is_empty());
+        if done {
+            break;
+        }
+
+        client.send()?;
+
+        let event: p2p::Event<_> = match client.receiver().recv_timeout(Duration::from_secs(1)) {
+            Ok(event) => event.into(),
+            Err(crossbeam_channel::RecvTimeoutError::Timeout) => continue,
+            Err(crossbeam_channel::RecvTimeoutError::Disconnected) => {
+                return Err(Error::Internal("p2p reactor disconnected".to_string()))
+            }
+        };
+
+        match event {
+            p2p::Event::ConnectedTo { target, result } => {
+                dialing.remove(&target);
+                if let Ok(id) = result {
+                    handshaking.insert(id, (target, Handshake::default()));
+                    client.version(id, target);
+                }
+            }
+            p2p::Event::Message { peer, message } => {
+                if let Some((service, h)) = handshaking.get_mut(&peer) {
+                    match h.update(message.payload().into()) {
+                        handshake::Event::Wait => {}
+                        handshake::Event::SendVerack { version } => {
+                            handshake::acknowledge(&client, peer, opts.protocol_version, version)
+                        }
+                        handshake::Event::Violation => {
+                            log::debug!(
+                                "census: handshake violated: peer @ {}",
+                                redact::r(service)
+                            );
+                            handshaking.remove(&peer);
+                            client.disconnect(peer);
+                        }
+                        handshake::Event::Done { version, .. } => {
+                            census.peers.push(PeerVersion {
+                                peer: *service,
+                                user_agent: version.user_agent.clone(),
+                                version: version.version,
+                                services: version.services.to_u64(),
+                                start_height: version.start_height,
+                            });
+                            handshaking.remove(&peer);
+                            client.disconnect(peer);
+                        }
+                    }
+                }
+            }
+            p2p::Event::Disconnected { peer, .. } => {
+                handshaking.remove(&peer);
+            }
+            _ => {}
+        }
+    }
+
+    client.send()?;
+    client.shutdown()?;
+
+    Ok(census)
+}
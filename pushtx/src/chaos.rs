@@ -0,0 +1,75 @@
+//! Fault injection for testing applications that embed the library. See `Opts::chaos`.
+
+use std::time::{Duration, Instant};
+
+use crate::net;
+use crate::p2p::{self, Outbox};
+use crate::{redact, Chaos};
+
+/// Applies a `Chaos` configuration to a single broadcast.
+pub struct Injector {
+    chaos: Chaos,
+    /// Connections held back, along with when to dial them.
+    delayed: Vec<(Instant, net::Service)>,
+    rng: fastrand::Rng,
+}
+
+impl Injector {
+    pub fn new(chaos: Chaos, rng: fastrand::Rng) -> Self {
+        Self {
+            chaos,
+            delayed: Vec::new(),
+            rng,
+        }
+    }
+
+    /// Dials a peer right away or after a random delay.
+    pub fn connect<P: p2p::Peerlike>(&mut self, outbox: &impl Outbox<P>, target: net::Service) {
+        match self.chaos.max_connect_delay {
+            Some(max) if !max.is_zero() => {
+                let delay = Duration::from_millis(self.rng.u64(..=max.as_millis() as u64));
+                log::debug!(
+                    "chaos: delaying connection to {} by {delay:?}",
+                    redact::r(target)
+                );
+                self.delayed.push((Instant::now() + delay, target));
+            }
+            _ => outbox.connect(target),
+        }
+    }
+
+    /// Dials the held back connections that are due.
+    pub fn release<P: p2p::Peerlike>(&mut self, outbox: &impl Outbox<P>) {
+        let now = Instant::now();
+        self.delayed.retain(|(when, target)| {
+            let due = *when <= now;
+            if due {
+                outbox.connect(*target);
+            }
+            !due
+        });
+    }
+
+    /// How long until the next held back connection is due, if any.
+    pub fn next_release(&self) -> Option<Duration> {
+        let now = Instant::now();
+        self.delayed
+            .iter()
+            .map(|(when, _)| when.saturating_duration_since(now))
+            .min()
+    }
+
+    /// Whether to drop a peer that is halfway through its handshake.
+    pub fn drop_handshake(&mut self) -> bool {
+        roll(&mut self.rng, self.chaos.handshake_drop)
+    }
+
+    /// Whether to reject a transaction instead of sending it.
+    pub fn reject(&mut self) -> bool {
+        roll(&mut self.rng, self.chaos.reject)
+    }
+}
+
+fn roll(rng: &mut fastrand::Rng, probability: f64) -> bool {
+    probability > 0.0 && rng.f64() < probability
+}
@@ -0,0 +1,77 @@
+//! Evidence gathered during a broadcast that helps explain why a transaction did not propagate.
+//! Modern nodes no longer send BIP-61 `reject` messages, so a failed transaction otherwise just
+//! looks like one that was never acked.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{net, Diagnosis, FeeFilters, Txid};
+
+/// The long-standing default minimum relay fee rate, in sat/kvB. Peers that announce a higher
+/// `feefilter` have a mempool that is full enough to turn away transactions paying the minimum.
+const MIN_RELAY_FEE: u64 = 1_000;
+
+#[derive(Debug, Default)]
+pub struct Evidence {
+    /// The transactions spent by each transaction in the batch.
+    inputs: HashMap<bitcoin::Txid, Vec<bitcoin::Txid>>,
+    /// The minimum fee rate (sat/kvB) each peer announced through `feefilter`.
+    feefilters: FeeFilters,
+    /// Transactions that a probed peer reported as not found.
+    not_found: HashSet<bitcoin::Txid>,
+    /// Transactions known to violate relay policy.
+    non_standard: HashSet<bitcoin::Txid>,
+}
+
+impl Evidence {
+    /// Records a transaction of the batch.
+    pub fn transaction(&mut self, tx: &bitcoin::Transaction) {
+        let inputs = tx.input.iter().map(|i| i.previous_output.txid).collect();
+        self.inputs.insert(tx.txid(), inputs);
+    }
+
+    /// Records that a transaction violates relay policy.
+    pub fn non_standard(&mut self, txid: bitcoin::Txid) {
+        self.non_standard.insert(txid);
+    }
+
+    /// Records a `feefilter` announced by a peer.
+    pub fn feefilter(&mut self, peer: net::Service, rate: i64) {
+        self.feefilters.peers.insert(peer, rate.max(0) as u64);
+    }
+
+    /// The fee rates announced so far.
+    pub fn feefilters(&self) -> &FeeFilters {
+        &self.feefilters
+    }
+
+    /// Records that a peer did not have a transaction when asked for it.
+    pub fn not_found(&mut self, txid: bitcoin::Txid) {
+        self.not_found.insert(txid);
+    }
+
+    /// Comes up with the likeliest explanation for a transaction that was neither acked nor
+    /// rejected.
+    pub fn diagnose(&self, txid: &bitcoin::Txid, acks: &HashSet<bitcoin::Txid>) -> Diagnosis {
+        let missing_parent = self.inputs.get(txid).and_then(|inputs| {
+            inputs
+                .iter()
+                .find(|input| self.inputs.contains_key(*input) && !acks.contains(*input))
+        });
+
+        if let Some(parent) = missing_parent {
+            return Diagnosis::LikelyMissingParent(Txid(*parent));
+        }
+
+        if self.non_standard.contains(txid) {
+            return Diagnosis::LikelyNonStandard;
+        }
+
+        match self.feefilters.median() {
+            Some(feefilter) if feefilter > MIN_RELAY_FEE => {
+                Diagnosis::LikelyBelowRelayFee { feefilter }
+            }
+            _ if self.not_found.contains(txid) => Diagnosis::LikelyNonStandard,
+            _ => Diagnosis::Unknown,
+        }
+    }
+}
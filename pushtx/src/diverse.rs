@@ -0,0 +1,302 @@
+use crate::broadcast::Runner;
+use crate::{Error, Info, Opts, Report, SuccessPolicy, TorMode, Transaction, Txid};
+
+/// Runs the same broadcast once through Tor and once through clearnet, each with its own reactor,
+/// handshake nonces and peer set, and merges the outcomes into a single `Info::Done`. Progress
+/// updates of both paths are forwarded as they arrive.
+pub(crate) fn run(tx: Vec<Transaction>, opts: Opts) -> crossbeam_channel::Receiver<Info> {
+    let (info_tx, info_rx) = crossbeam_channel::unbounded();
+    let policy = opts.success_policy;
+    let txids: Vec<_> = tx.iter().map(Transaction::txid).collect();
+
+    // a seeded run stays reproducible, yet the paths draw nonces and credentials of their own
+    let mut seeds = opts.rng_seed.map(fastrand::Rng::with_seed);
+    // each path reports to a monitor of its own, which the caller's monitor combines
+    let (tor_monitor, clearnet_monitor) = match &opts.monitor {
+        Some(monitor) => {
+            let (tor, clearnet) = monitor.split();
+            (Some(tor), Some(clearnet))
+        }
+        None => (None, None),
+    };
+    let tor_opts = Opts {
+        use_tor: TorMode::Must,
+        rng_seed: seeds.as_mut().map(|rng| rng.u64(..)),
+        monitor: tor_monitor,
+        ..opts.clone()
+    };
+    let clearnet_opts = Opts {
+        use_tor: TorMode::No,
+        rng_seed: seeds.as_mut().map(|rng| rng.u64(..)),
+        monitor: clearnet_monitor,
+        ..opts
+    };
+    let (tor, tor_rx) = Runner::new(tx.clone(), tor_opts, None);
+    let (clearnet, clearnet_rx) = Runner::new(tx, clearnet_opts, None);
+    tor.run();
+    clearnet.run();
+
+    std::thread::spawn(move || {
+        let (mut tor_rx, mut clearnet_rx) = (tor_rx, clearnet_rx);
+        let mut tor_result = None;
+        let mut clearnet_result = None;
+        // the paths progress separately, the one further along sets the fraction passed on
+        let mut progress = 0.0;
+
+        while tor_result.is_none() || clearnet_result.is_none() {
+            let (info, tor) = crossbeam_channel::select! {
+                recv(tor_rx) -> info => (info, true),
+                recv(clearnet_rx) -> info => (info, false),
+            };
+            let (path, rx) = if tor {
+                (&mut tor_result, &mut tor_rx)
+            } else {
+                (&mut clearnet_result, &mut clearnet_rx)
+            };
+            match info {
+                Ok(Info::Done(result)) => {
+                    *path = Some(result);
+                    // a finished runner drops its sender, which would make its receiver ready
+                    // for good
+                    *rx = crossbeam_channel::never();
+                }
+                Ok(info) => {
+                    // updates of the path behind still go out so that a dropped receiver is noticed
+                    let info = match info {
+                        Info::Progress { phase, fraction } => {
+                            progress = progress.max(fraction);
+                            Info::Progress {
+                                phase,
+                                fraction: progress,
+                            }
+                        }
+                        info => info,
+                    };
+                    if info_tx.send(info).is_err() {
+                        log::info!("info receiver dropped, diverse broadcast abandoned");
+                        return;
+                    }
+                }
+                // the runner thread went away without reporting, most likely a panic
+                Err(_) => {
+                    *path = Some(Err(Error::Io(std::io::ErrorKind::Other)));
+                    *rx = crossbeam_channel::never();
+                }
+            }
+        }
+
+        if let (Some(tor), Some(clearnet)) = (tor_result, clearnet_result) {
+            let _ = info_tx.send(Info::Done(merge(tor, clearnet, policy, &txids)));
+        }
+    });
+
+    info_rx
+}
+
+/// Succeeds if either path succeeded. The Tor error wins if both failed.
+fn merge(
+    tor: Result<Report, Error>,
+    clearnet: Result<Report, Error>,
+    policy: SuccessPolicy,
+    txids: &[Txid],
+) -> Result<Report, Error> {
+    match (tor, clearnet) {
+        (Ok(tor), Ok(clearnet)) => Ok(merge_reports(tor, clearnet, policy, txids)),
+        (Ok(report), Err(err)) | (Err(err), Ok(report)) => {
+            log::warn!("one broadcast path failed: {err}");
+            Ok(report)
+        }
+        (Err(err), Err(_)) => Err(err),
+    }
+}
+
+fn merge_reports(mut a: Report, b: Report, policy: SuccessPolicy, txids: &[Txid]) -> Report {
+    a.success.extend(b.success);
+    a.already_known.extend(b.already_known);
+    // sent through either path is sent
+    a.unsent.retain(|txid| b.unsent.contains(txid));
+    // acked through the other path is not timed out
+    a.timed_out.extend(b.timed_out);
+    a.timed_out
+        .retain(|txid| !a.success.contains(txid) && !a.already_known.contains(txid));
+    a.wtxids.extend(b.wtxids);
+    for (txid, reason) in b.rejects {
+        a.rejects.entry(txid).or_insert(reason);
+    }
+    for (txid, diagnosis) in b.diagnoses {
+        a.diagnoses.entry(txid).or_insert(diagnosis);
+    }
+    a.diagnoses
+        .retain(|txid, _| !a.success.contains(txid) && !a.already_known.contains(txid));
+    a.explorer_seen = match (a.explorer_seen, b.explorer_seen) {
+        (Some(mut a), Some(b)) => {
+            a.extend(b);
+            Some(a)
+        }
+        (a, b) => a.or(b),
+    };
+    a.nostr_published = match (a.nostr_published, b.nostr_published) {
+        (Some(mut a), Some(b)) => {
+            a.extend(b);
+            Some(a)
+        }
+        (a, b) => a.or(b),
+    };
+    a.electrum_published = match (a.electrum_published, b.electrum_published) {
+        (Some(mut a), Some(b)) => {
+            a.extend(b);
+            Some(a)
+        }
+        (a, b) => a.or(b),
+    };
+    a.rpc_submitted = match (a.rpc_submitted, b.rpc_submitted) {
+        (Some(mut a), Some(b)) => {
+            a.extend(b);
+            Some(a)
+        }
+        (a, b) => a.or(b),
+    };
+    a.escalation_stage = match (a.escalation_stage, b.escalation_stage) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    a.attestation = match (a.attestation, b.attestation) {
+        (Some(a), Some(b)) => Some(format!("[{a},{b}]")),
+        (a, b) => a.or(b),
+    };
+    for (cause, count) in b.connect_failures {
+        *a.connect_failures.entry(cause).or_default() += count;
+    }
+    a.feefilters.peers.extend(b.feefilters.peers);
+    for (txid, requests) in b.interest {
+        let merged = a.interest.entry(txid).or_default();
+        merged.extend(requests);
+        merged.sort_unstable_by_key(|interest| interest.after);
+    }
+    for (txid, latency) in b.latencies {
+        let merged = a.latencies.entry(txid).or_default();
+        merged.acks.extend(latency.acks);
+        merged.acks.sort_unstable();
+    }
+    // the acks of both paths count towards the policy, while transactions that were dropped in
+    // favor of a replacement appear nowhere in the reports and do not count at all
+    let batch = txids.iter().filter(|txid| {
+        a.success.contains(*txid)
+            || a.already_known.contains(*txid)
+            || a.rejects.contains_key(*txid)
+            || a.diagnoses.contains_key(*txid)
+            || a.unsent.contains(*txid)
+    });
+    let policy_met = policy.is_met(batch.map(|txid| {
+        if a.already_known.contains(txid) {
+            usize::MAX
+        } else {
+            let acks = a
+                .latencies
+                .get(txid)
+                .map_or(0, |latency| latency.acks.len());
+            acks.max(usize::from(a.success.contains(txid)))
+        }
+    }));
+    a.policy_met = policy_met;
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::time::Duration;
+
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+    use crate::Latency;
+
+    fn txid(n: u8) -> Txid {
+        bitcoin::Txid::from_byte_array([n; 32]).into()
+    }
+
+    fn report(success: &[Txid], unsent: &[Txid]) -> Report {
+        Report {
+            success: success.iter().copied().collect(),
+            wtxids: HashMap::new(),
+            rejects: HashMap::new(),
+            already_known: HashSet::new(),
+            policy_met: false,
+            unsent: unsent.iter().copied().collect(),
+            timed_out: HashSet::new(),
+            diagnoses: HashMap::new(),
+            explorer_seen: None,
+            nostr_published: None,
+            electrum_published: None,
+            rpc_submitted: None,
+            latencies: success
+                .iter()
+                .map(|txid| {
+                    let latency = Latency {
+                        acks: vec![Duration::from_secs(1)],
+                    };
+                    (*txid, latency)
+                })
+                .collect(),
+            interest: HashMap::new(),
+            connect_failures: HashMap::new(),
+            feefilters: crate::FeeFilters::default(),
+            escalation_stage: None,
+            attestation: None,
+        }
+    }
+
+    #[test]
+    fn either_path_succeeding_is_a_success() {
+        let txids = [txid(1)];
+        let ok = || Ok(report(&txids, &[]));
+        let failed = || Err(Error::ConnectTimeout);
+
+        assert!(merge(ok(), failed(), SuccessPolicy::AllAcked, &txids).is_ok());
+        assert!(merge(failed(), ok(), SuccessPolicy::AllAcked, &txids).is_ok());
+        assert!(merge(failed(), failed(), SuccessPolicy::AllAcked, &txids).is_err());
+    }
+
+    #[test]
+    fn acks_of_both_paths_count_towards_the_policy() {
+        let txids = [txid(1), txid(2)];
+        let tor = report(&[txids[0]], &[txids[1]]);
+        let clearnet = report(&[txids[1]], &[]);
+
+        let merged = merge_reports(tor, clearnet, SuccessPolicy::AllAcked, &txids);
+        assert_eq!(merged.success, txids.into_iter().collect());
+        assert!(merged.unsent.is_empty());
+        assert!(merged.policy_met);
+    }
+
+    #[test]
+    fn acks_of_both_paths_add_up_per_transaction() {
+        let txids = [txid(1)];
+        let policy = SuccessPolicy::PeersPerTx(2);
+
+        let merged = merge_reports(report(&txids, &[]), report(&txids, &[]), policy, &txids);
+        assert_eq!(merged.latencies[&txids[0]].acks.len(), 2);
+        assert!(merged.policy_met);
+
+        let merged = merge_reports(report(&txids, &[]), report(&[], &[]), policy, &txids);
+        assert!(!merged.policy_met);
+    }
+
+    #[test]
+    fn monitor_waits_for_both_paths() {
+        let monitor = crate::Monitor::new();
+        let (tor, clearnet) = monitor.split();
+        let acks = HashMap::from([(txid(1), 1)]);
+        tor.update(crate::Phase::Broadcasting, Vec::new(), acks.clone());
+        clearnet.update(crate::Phase::Broadcasting, Vec::new(), acks);
+        tor.finish();
+
+        let status = monitor.status();
+        assert_eq!(status.phase, crate::Phase::Broadcasting);
+        assert_eq!(status.acks_per_tx[&txid(1)], 2);
+
+        clearnet.finish();
+        assert_eq!(monitor.status().phase, crate::Phase::Done);
+    }
+}
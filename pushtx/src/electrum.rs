@@ -0,0 +1,156 @@
+//! Fallback publication of raw transactions through Electrum servers, for when no P2P connection
+//! gets through at all.
+
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{redact, ElectrumFallback, Txid};
+
+/// How long to wait for a server to connect or answer.
+const SERVER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Publishes the transactions through every server with `blockchain.transaction.broadcast`.
+/// Returns the transactions that at least one server accepted. Servers are reached through the
+/// Tor proxy if one is provided, each on a circuit of its own. Plaintext servers are skipped
+/// unless Tor is used.
+pub fn publish(
+    fallback: &ElectrumFallback,
+    txs: &[(Txid, &[u8])],
+    proxy: Option<SocketAddr>,
+    rng: &mut fastrand::Rng,
+) -> HashSet<Txid> {
+    let mut accepted = HashSet::new();
+    for server in &fallback.servers {
+        let pending: Vec<_> = txs
+            .iter()
+            .filter(|(txid, _)| !accepted.contains(txid))
+            .collect();
+        if pending.is_empty() {
+            break;
+        }
+        match publish_to(server, &pending, proxy, rng) {
+            Ok(ok) => accepted.extend(ok),
+            Err(err) => log::warn!("electrum: server {server} failed: {err}"),
+        }
+    }
+
+    accepted
+}
+
+/// Sends the transactions to a single server and collects the ones it accepted.
+fn publish_to(
+    server: &str,
+    txs: &[&(Txid, &[u8])],
+    proxy: Option<SocketAddr>,
+    rng: &mut fastrand::Rng,
+) -> Result<HashSet<Txid>, Box<dyn std::error::Error>> {
+    let (tls, address) = match server.split_once("://") {
+        Some(("ssl", address)) => (true, address),
+        Some(("tcp", address)) => (false, address),
+        _ => return Err("expected an ssl:// or tcp:// URL".into()),
+    };
+    if !tls && proxy.is_none() {
+        return Err("plaintext servers are only used through Tor".into());
+    }
+    let (host, port) = address
+        .rsplit_once(':')
+        .ok_or("server URL without a port")?;
+    let port: u16 = port.parse()?;
+
+    let stream = match proxy {
+        // distinct credentials isolate the circuit from the ones used for the peers
+        Some(proxy) => socks::Socks5Stream::connect_with_password(
+            proxy,
+            (host, port),
+            &rng.u32(..).to_string(),
+            &rng.u32(..).to_string(),
+        )?
+        .into_inner(),
+        None => TcpStream::connect((host, port))?,
+    };
+    stream.set_read_timeout(Some(SERVER_TIMEOUT))?;
+    stream.set_write_timeout(Some(SERVER_TIMEOUT))?;
+
+    let stream: Box<dyn ReadWrite> = if tls {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        let config = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let name = rustls::pki_types::ServerName::try_from(host.to_string())?;
+        let conn = rustls::ClientConnection::new(Arc::new(config), name)?;
+        Box::new(rustls::StreamOwned::new(conn, stream))
+    } else {
+        Box::new(stream)
+    };
+    let mut stream = BufReader::new(stream);
+
+    // servers expect the protocol version to be negotiated before anything else
+    let mut requests = String::from(
+        "{\"jsonrpc\":\"2.0\",\"id\":0,\"method\":\"server.version\",\"params\":[\"\",\"1.4\"]}\n",
+    );
+    for (id, (_, tx)) in txs.iter().enumerate() {
+        requests.push_str(&format!(
+            "{{\"jsonrpc\":\"2.0\",\"id\":{},\"method\":\"blockchain.transaction.broadcast\",\
+             \"params\":[\"{}\"]}}\n",
+            id + 1,
+            hex::encode(tx)
+        ));
+    }
+    stream.get_mut().write_all(requests.as_bytes())?;
+    stream.get_mut().flush()?;
+
+    let mut accepted = HashSet::new();
+    let mut pending = txs.len() + 1;
+    let mut line = String::new();
+    while pending > 0 {
+        line.clear();
+        if stream.read_line(&mut line)? == 0 {
+            break;
+        }
+
+        let compact: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+        let Some(id) = response_id(&compact) else {
+            continue;
+        };
+        pending -= 1;
+        let Some((txid, _)) = id.checked_sub(1).and_then(|i| txs.get(i)) else {
+            if compact.contains("\"error\":") {
+                log::warn!(
+                    "electrum: server {server} refused the handshake: {}",
+                    line.trim()
+                );
+            }
+            continue;
+        };
+        if compact.contains("\"result\":") && !compact.contains("\"error\":{") {
+            log::info!("electrum: server {server} accepted {}", redact::r(txid));
+            accepted.insert(*txid);
+        } else {
+            log::warn!(
+                "electrum: server {server} refused {}: {}",
+                redact::r(txid),
+                line.trim()
+            );
+        }
+    }
+
+    Ok(accepted)
+}
+
+/// The request id that a compacted response answers, if any.
+fn response_id(compact: &str) -> Option<usize> {
+    let (_, rest) = compact.split_once("\"id\":")?;
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
+
+/// A stream that is either plain or wrapped in TLS.
+trait ReadWrite: Read + Write {}
+
+impl<T: Read + Write> ReadWrite for T {}
@@ -0,0 +1,58 @@
+//! Independent confirmation of propagation through an Esplora-compatible block explorer.
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::{redact, ExplorerCheck, Txid};
+
+/// How long a single explorer request may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Waits for the configured delay and then asks the explorer about each transaction. Returns
+/// the ones it knows about. Requests go through the Tor proxy if one is provided, on a circuit of
+/// their own so that the explorer cannot tie them to the peers we broadcast to.
+pub fn cross_check(
+    check: &ExplorerCheck,
+    txids: &HashSet<Txid>,
+    proxy: Option<SocketAddr>,
+    rng: &mut fastrand::Rng,
+) -> HashSet<Txid> {
+    std::thread::sleep(check.delay);
+
+    let mut agent = ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT);
+    if let Some(proxy) = proxy {
+        let url = format!("socks5://{}:{}@{proxy}", rng.u32(..), rng.u32(..));
+        match ureq::Proxy::new(url) {
+            Ok(proxy) => agent = agent.proxy(proxy),
+            Err(err) => {
+                log::warn!("explorer: cannot use the Tor proxy: {err}");
+                return HashSet::new();
+            }
+        }
+    }
+    let agent = agent.build();
+
+    let base = check.url.trim_end_matches('/');
+    txids
+        .iter()
+        .filter(|txid| {
+            let url = format!("{base}/tx/{txid}/status");
+            match agent.get(&url).call() {
+                Ok(_) => {
+                    log::info!("explorer: txid seen: {}", redact::r(txid));
+                    true
+                }
+                Err(ureq::Error::Status(404, _)) => {
+                    log::warn!("explorer: txid not found: {}", redact::r(txid));
+                    false
+                }
+                Err(err) => {
+                    log::warn!("explorer: request for {} failed: {err}", redact::r(txid));
+                    false
+                }
+            }
+        })
+        .copied()
+        .collect()
+}
@@ -0,0 +1,249 @@
+//! Country-based peer exclusion.
+//!
+//! Peers are mapped to countries through a plain text CIDR to country map, one range per line:
+//!
+//! ```text
+//! # comments and blank lines are ignored
+//! 1.2.3.0/24 DE
+//! 2001:db8::/32,FR
+//! ```
+//!
+//! Ranges must not overlap. MaxMind databases can be converted to this format with their own CSV
+//! tooling by joining the network column with the country code.
+
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::net::{Address, Service};
+
+/// A map from IP ranges to two letter country codes. Cloning it is cheap.
+#[derive(Clone)]
+pub struct CountryMap {
+    /// Inclusive ranges of IPv6 (or IPv4-mapped) addresses, sorted by their start.
+    ranges: Arc<Vec<(u128, u128, [u8; 2])>>,
+}
+
+impl CountryMap {
+    /// Returns the country of an address, if it is in the map. Onion addresses have none.
+    pub fn country(&self, address: &Address) -> Option<&str> {
+        let ip = match address {
+            Address::Ipv4(ip) => u128::from(ip.to_ipv6_mapped()),
+            Address::Ipv6(ip) => u128::from(*ip),
+            #[cfg(feature = "onion")]
+            Address::TorV3(_) => return None,
+        };
+
+        let idx = self.ranges.partition_point(|(start, _, _)| *start <= ip);
+        let (_, end, country) = self.ranges.get(idx.checked_sub(1)?)?;
+        (ip <= *end)
+            .then(|| std::str::from_utf8(country).ok())
+            .flatten()
+    }
+
+    /// Returns the number of ranges in the map.
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// Returns whether the map has no ranges.
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+}
+
+impl std::fmt::Debug for CountryMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CountryMap")
+            .field("ranges", &self.ranges.len())
+            .finish()
+    }
+}
+
+impl FromStr for CountryMap {
+    type Err = ParseCountryMapError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ranges = s
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(line_no, line)| parse_line(line).ok_or(ParseCountryMapError { line: line_no }))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        ranges.sort_unstable_by_key(|(start, _, _)| *start);
+
+        Ok(Self {
+            ranges: Arc::new(ranges),
+        })
+    }
+}
+
+/// Parses a `CIDR COUNTRY` (or `CIDR,COUNTRY`) line into an inclusive range.
+fn parse_line(line: &str) -> Option<(u128, u128, [u8; 2])> {
+    let (cidr, country) = line.split_once(|c: char| c == ',' || c.is_whitespace())?;
+    let (ip, prefix) = cidr.split_once('/')?;
+    let prefix: u32 = prefix.parse().ok()?;
+
+    let (ip, prefix) = match ip.parse().ok()? {
+        IpAddr::V4(ip) if prefix <= 32 => (u128::from(ip.to_ipv6_mapped()), prefix + 96),
+        IpAddr::V6(ip) if prefix <= 128 => (u128::from(ip), prefix),
+        _ => return None,
+    };
+
+    let host_mask = u128::MAX.checked_shr(prefix).unwrap_or(0);
+    let country: [u8; 2] = country
+        .trim_start_matches(|c: char| c == ',' || c.is_whitespace())
+        .trim()
+        .to_ascii_uppercase()
+        .as_bytes()
+        .try_into()
+        .ok()?;
+
+    Some((ip & !host_mask, ip | host_mask, country))
+}
+
+/// A line of a country map could not be parsed.
+#[derive(Debug)]
+pub struct ParseCountryMapError {
+    /// The offending line, starting at 1.
+    pub line: usize,
+}
+
+impl std::error::Error for ParseCountryMapError {}
+
+impl std::fmt::Display for ParseCountryMapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid country map entry on line {}", self.line)
+    }
+}
+
+/// Peers located in any of the listed countries are never connected to, and therefore neither
+/// broadcast to nor counted as acks.
+#[derive(Debug, Clone)]
+pub struct CountryExclusion {
+    /// The map used to locate peers.
+    pub map: CountryMap,
+    /// Two letter country codes to exclude, in upper case.
+    pub countries: HashSet<String>,
+}
+
+impl CountryExclusion {
+    /// Whether a peer is located in an excluded country. Peers missing from the map are allowed.
+    pub(crate) fn excludes(&self, service: &Service) -> bool {
+        self.map
+            .country(&service.address())
+            .is_some_and(|country| self.countries.contains(country))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    fn v4(ip: &str) -> u128 {
+        u128::from(ip.parse::<Ipv4Addr>().unwrap().to_ipv6_mapped())
+    }
+
+    fn v6(ip: &str) -> u128 {
+        u128::from(ip.parse::<Ipv6Addr>().unwrap())
+    }
+
+    fn country<'a>(map: &'a CountryMap, ip: &str) -> Option<&'a str> {
+        let address = match ip.parse().unwrap() {
+            IpAddr::V4(ip) => Address::Ipv4(ip),
+            IpAddr::V6(ip) => Address::Ipv6(ip),
+        };
+        map.country(&address)
+    }
+
+    #[test]
+    fn parse_line_covers_the_whole_prefix() {
+        let cases = [
+            ("1.2.3.4/32 DE", v4("1.2.3.4"), v4("1.2.3.4")),
+            ("1.2.3.77/24 DE", v4("1.2.3.0"), v4("1.2.3.255")),
+            ("0.0.0.0/0 DE", v4("0.0.0.0"), v4("255.255.255.255")),
+            ("2001:db8::1/128 DE", v6("2001:db8::1"), v6("2001:db8::1")),
+            (
+                "2001:db8::/32 DE",
+                v6("2001:db8::"),
+                v6("2001:db8:ffff:ffff:ffff:ffff:ffff:ffff"),
+            ),
+            ("::/0 DE", 0, u128::MAX),
+        ];
+        for (line, start, end) in cases {
+            assert_eq!(parse_line(line), Some((start, end, *b"DE")), "{line}");
+        }
+    }
+
+    #[test]
+    fn parse_line_takes_either_separator() {
+        for line in [
+            "1.2.3.0/24 DE",
+            "1.2.3.0/24\tDE",
+            "1.2.3.0/24   de",
+            "1.2.3.0/24,DE",
+            "1.2.3.0/24, de ",
+        ] {
+            let parsed = parse_line(line);
+            assert_eq!(
+                parsed,
+                Some((v4("1.2.3.0"), v4("1.2.3.255"), *b"DE")),
+                "{line}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_line_refuses_malformed_lines() {
+        for line in [
+            "1.2.3.0/33 DE",
+            "2001:db8::/129 DE",
+            "1.2.3.0 DE",
+            "1.2.3.0/24",
+            "1.2.3.0/24 DEU",
+            "1.2.3/24 DE",
+            "1.2.3.0/x DE",
+        ] {
+            assert_eq!(parse_line(line), None, "{line}");
+        }
+    }
+
+    #[test]
+    fn country_is_found_up_to_the_range_boundaries() {
+        let map: CountryMap = "1.2.3.0/24 DE\n1.2.4.0/24 FR\n2001:db8::1/128 NL\n"
+            .parse()
+            .unwrap();
+        assert_eq!(map.len(), 3);
+
+        assert_eq!(country(&map, "1.2.2.255"), None);
+        assert_eq!(country(&map, "1.2.3.0"), Some("DE"));
+        assert_eq!(country(&map, "1.2.3.255"), Some("DE"));
+        assert_eq!(country(&map, "1.2.4.0"), Some("FR"));
+        assert_eq!(country(&map, "1.2.4.255"), Some("FR"));
+        assert_eq!(country(&map, "1.2.5.0"), None);
+        assert_eq!(country(&map, "2001:db8::"), None);
+        assert_eq!(country(&map, "2001:db8::1"), Some("NL"));
+        assert_eq!(country(&map, "2001:db8::2"), None);
+    }
+
+    #[test]
+    fn whole_ipv4_range_leaves_ipv6_out() {
+        let map: CountryMap = "0.0.0.0/0 DE".parse().unwrap();
+        assert_eq!(country(&map, "0.0.0.0"), Some("DE"));
+        assert_eq!(country(&map, "255.255.255.255"), Some("DE"));
+        assert_eq!(country(&map, "2001:db8::1"), None);
+    }
+
+    #[test]
+    fn malformed_line_is_reported_by_number() {
+        let err = "# header\n\n1.2.3.0/24 DE\n1.2.4.0/24 FRANCE\n"
+            .parse::<CountryMap>()
+            .unwrap_err();
+        assert_eq!(err.line, 4);
+    }
+}
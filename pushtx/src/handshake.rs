@@ -1,6 +1,12 @@
 use bitcoin::p2p::message::NetworkMessage;
 use bitcoin::p2p::message_network::VersionMessage;
 
+use crate::p2p::{Outbox, Peerlike};
+
+/// The first protocol version with wtxid relay (BIP-339). `wtxidrelay` is only sent when both
+/// sides are at least at this version.
+pub const WTXID_RELAY_VERSION: u32 = 70016;
+
 /// Types of updates that an in-progress handshake wants to know about.
 #[derive(Debug)]
 pub enum Update {
@@ -31,8 +37,11 @@ impl From<&NetworkMessage> for Update {
 #[derive(Debug)]
 pub enum Event<'a> {
     Wait,
-    /// Send a `Verack` message to the peer.
-    SendVerack,
+    /// Send a `Verack` message to the peer, see `acknowledge`.
+    SendVerack {
+        /// The peer's advertised version.
+        version: &'a VersionMessage,
+    },
     /// The peer violated the handshake protocol.
     Violation,
     /// The handshake is done.
@@ -70,10 +79,9 @@ impl Handshake {
                     ..
                 },
                 Update::Version(v),
-            ) => {
-                *their_version = Some(v);
-                Event::SendVerack
-            }
+            ) => Event::SendVerack {
+                version: their_version.insert(v),
+            },
 
             (
                 Self {
@@ -122,3 +130,19 @@ impl Handshake {
         }
     }
 }
+
+/// Answers the peer's version: announces support for `addrv2` (BIP-155) and, if both sides are
+/// recent enough, for wtxid relay (BIP-339), then sends our verack. Both announcements must come
+/// before the verack.
+pub fn acknowledge<P: Peerlike>(
+    outbox: &impl Outbox<P>,
+    peer: P,
+    our_version: u32,
+    their_version: &VersionMessage,
+) {
+    if our_version >= WTXID_RELAY_VERSION && their_version.version >= WTXID_RELAY_VERSION {
+        outbox.wtxidrelay(peer);
+    }
+    outbox.sendaddrv2(peer);
+    outbox.verack(peer);
+}
@@ -0,0 +1,88 @@
+//! Propagation latency bookkeeping: when each transaction first went out, when each peer first
+//! asked for it and when each peer first announced it back.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+use crate::{net, Interest, Latency, Txid};
+
+#[derive(Debug, Default)]
+pub struct Tracker {
+    /// When each transaction was first sent or announced to a broadcast peer.
+    sent: HashMap<bitcoin::Txid, Instant>,
+    /// The acks of each transaction, as time since it was sent, in order of arrival.
+    acks: HashMap<bitcoin::Txid, Vec<std::time::Duration>>,
+    /// The peers that acked each transaction so far. Each peer counts once.
+    ackers: HashSet<(bitcoin::Txid, net::Service)>,
+    /// The getdata requests for each transaction, in order of arrival. Each peer counts once.
+    requests: HashMap<bitcoin::Txid, Vec<Interest>>,
+    /// The peers that requested each transaction so far.
+    requesters: HashSet<(bitcoin::Txid, net::Service)>,
+}
+
+impl Tracker {
+    /// Records that a transaction went out. Only the first time counts.
+    pub fn sent(&mut self, txid: bitcoin::Txid, now: Instant) {
+        self.sent.entry(txid).or_insert(now);
+    }
+
+    /// Records an ack from a peer. Acks for transactions that never went out are ignored.
+    pub fn ack(&mut self, txid: bitcoin::Txid, peer: net::Service, now: Instant) {
+        if let Some(sent) = self.sent.get(&txid) {
+            if self.ackers.insert((txid, peer)) {
+                self.acks
+                    .entry(txid)
+                    .or_default()
+                    .push(now.saturating_duration_since(*sent));
+            }
+        }
+    }
+
+    /// Records a getdata request from a peer. Requests for transactions that never went out are
+    /// ignored.
+    pub fn requested(&mut self, txid: bitcoin::Txid, peer: net::Service, now: Instant) {
+        if let Some(sent) = self.sent.get(&txid) {
+            if self.requesters.insert((txid, peer)) {
+                self.requests.entry(txid).or_default().push(Interest {
+                    peer,
+                    after: now.saturating_duration_since(*sent),
+                });
+            }
+        }
+    }
+
+    /// How many peers acked a transaction.
+    pub fn ack_count(&self, txid: &bitcoin::Txid) -> usize {
+        self.acks.get(txid).map_or(0, Vec::len)
+    }
+
+    /// When a transaction first went out, if it did.
+    pub fn sent_at(&self, txid: &bitcoin::Txid) -> Option<Instant> {
+        self.sent.get(txid).copied()
+    }
+
+    /// Whether a transaction went out to any peer.
+    pub fn was_sent(&self, txid: &bitcoin::Txid) -> bool {
+        self.sent.contains_key(txid)
+    }
+
+    /// Whether any peer requested a transaction.
+    pub fn was_requested(&self, txid: &bitcoin::Txid) -> bool {
+        self.requests.contains_key(txid)
+    }
+
+    /// The latencies of every acked transaction and the requests for every requested one.
+    pub fn into_report(self) -> (HashMap<Txid, Latency>, HashMap<Txid, Vec<Interest>>) {
+        let latencies = self
+            .acks
+            .into_iter()
+            .map(|(txid, acks)| (Txid(txid), Latency { acks }))
+            .collect();
+        let interest = self
+            .requests
+            .into_iter()
+            .map(|(txid, requests)| (Txid(txid), requests))
+            .collect();
+        (latencies, interest)
+    }
+}
@@ -12,6 +12,41 @@
 //! The broadcast process can be fine-tuned using the `Opts` struct. Please refer to its
 //! documentation for details.
 //!
+//! ## Features
+//! - `tor` (default): Tor proxy detection and connectivity through SOCKS5.
+//! - `dns` (default): peer discovery through DNS seeds.
+//! - `onion` (default): onion service (Tor V3) addresses.
+//! - `metrics`: broadcast attempts, acks, rejects and peer connection latency reported through
+//!   the [`metrics`](https://docs.rs/metrics) facade, for long-lived embedders that install a
+//!   recorder such as a Prometheus exporter.
+//! - `chaos`: fault injection (delayed connections, peers dropped mid-handshake and synthetic
+//!   rejects) for testing applications against a misbehaving network. See `Opts::chaos`.
+//! - `consensus-validation`: verifies input scripts and signatures against the prevouts in
+//!   `Opts::prevouts` through libbitcoinconsensus and refuses to broadcast invalid transactions.
+//! - `electrum`: an opt-in fallback that publishes the transactions through Electrum servers
+//!   (over TLS, or through Tor) when no P2P connection gets through. See `Opts::electrum`.
+//! - `explorer`: an opt-in check that asks an Esplora-compatible explorer (through Tor, if it is
+//!   used) about the broadcast transactions once the broadcast is over. See `Opts::explorer`.
+//! - `nostr`: an opt-in fallback that publishes the transactions that did not propagate to
+//!   Nostr relays, for networks where P2P and Tor are blocked. See `Opts::nostr`.
+//! - `raw-messages`: an escape hatch that sends arbitrary P2P messages to the connected peers,
+//!   for experiments that want to reuse the connection management. See `Opts::raw_messages`.
+//! - `regtest`: the `regtest` module, a harness that broadcasts to a local regtest `bitcoind`
+//!   and checks mempool acceptance through RPC. Used by the crate's own integration tests.
+//! - `rpc`: opt-in use of a node the user runs, through its RPC interface, to check the
+//!   transactions before broadcasting and to submit the ones that did not propagate. See
+//!   `Opts::rpc`.
+//! - `testing`: the `testing` module, an in-memory mock of the p2p network with scriptable
+//!   peers, for testing code that drives broadcasts without network access.
+//! - `ua-filter`: allow and deny patterns for the user agents that peers advertise, so that
+//!   implementations that do not relay third-party transactions are never broadcast to. See
+//!   `Opts::ua_filter`.
+//! - `zeroize`: wipes raw transaction data and the buffers it was decoded from once a broadcast
+//!   is over, shortening the window in which it can be scraped from memory.
+//!
+//! Users that only ever pass custom clearnet peers can disable default features to get a slimmer
+//! dependency tree.
+//!
 //! ## Example
 //!
 //!```no_run
@@ -37,11 +72,43 @@
 //! }
 //!```
 
+mod attestation;
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
 mod broadcast;
+pub mod census;
+#[cfg(feature = "chaos")]
+mod chaos;
+mod diagnosis;
+mod diverse;
+#[cfg(feature = "electrum")]
+mod electrum;
+#[cfg(feature = "explorer")]
+mod explorer;
+pub mod geo;
 mod handshake;
+mod latency;
+mod local_node;
+mod misbehavior;
 mod net;
+#[cfg(feature = "nostr")]
+mod nostr;
 mod p2p;
-mod seeds;
+mod policy;
+mod redact;
+#[cfg(feature = "regtest")]
+pub mod regtest;
+#[cfg(feature = "rpc")]
+mod rpc;
+pub mod seeds;
+mod telemetry;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+#[cfg(feature = "consensus-validation")]
+mod validation;
+#[cfg(feature = "zeroize")]
+mod wipe;
 
 use std::{
     collections::{HashMap, HashSet},
@@ -49,6 +116,8 @@ use std::{
     str::FromStr,
 };
 
+pub use net::{Address, InvalidConnectTarget, Service, UnsupportedNetworkError};
+
 use bitcoin::consensus::Decodable;
 
 /// A Bitcoin transaction to be broadcast into the network.
@@ -56,7 +125,8 @@ use bitcoin::consensus::Decodable;
 pub struct Transaction(bitcoin::Transaction);
 
 impl Transaction {
-    /// Tries to parse a hex-encoded string into `Transaction`.
+    /// Tries to parse a hex-encoded string into `Transaction`. Formatting noise from copy-pasting
+    /// is tolerated: whitespace anywhere (line breaks included), a `0x` prefix and upper case.
     pub fn from_hex(tx: impl AsRef<str>) -> Result<Self, ParseTxError> {
         tx.as_ref().parse()
     }
@@ -66,18 +136,51 @@ impl Transaction {
         tx.as_ref().try_into()
     }
 
+    /// Extracts the transaction from a binary, fully finalized PSBT (BIP-174).
+    pub fn from_psbt(psbt: impl AsRef<[u8]>) -> Result<Self, ParseTxError> {
+        let psbt = bitcoin::psbt::Psbt::deserialize(psbt.as_ref())
+            .map_err(|_| ParseTxError::InvalidPsbt)?;
+        let finalized = psbt
+            .inputs
+            .iter()
+            .all(|input| input.final_script_sig.is_some() || input.final_script_witness.is_some());
+        if !finalized {
+            return Err(ParseTxError::UnfinalizedPsbt);
+        }
+        // the fee was settled when the PSBT was signed, there is nothing to double-check here
+        Ok(Self(psbt.extract_tx_unchecked_fee_rate()))
+    }
+
     /// Returns the txid of this transaction.
     pub fn txid(&self) -> Txid {
         Txid(self.0.txid())
     }
+
+    /// Returns the wtxid of this transaction, which commits to the witness as well. It is the
+    /// same as the txid for transactions without witness data.
+    pub fn wtxid(&self) -> bitcoin::Wtxid {
+        self.0.wtxid()
+    }
 }
 
 impl FromStr for Transaction {
     type Err = ParseTxError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let bytes = hex::decode(s).map_err(|_| ParseTxError::NotHex)?;
-        bytes.as_slice().try_into()
+        let s = s.trim();
+        let s = s
+            .strip_prefix("0x")
+            .or_else(|| s.strip_prefix("0X"))
+            .unwrap_or(s);
+        let hex: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+        let bytes = hex::decode(&hex).map_err(|_| ParseTxError::NotHex);
+        #[cfg(feature = "zeroize")]
+        wipe::bytes(hex.into_bytes());
+        let bytes = bytes?;
+        let tx = bytes.as_slice().try_into();
+        #[cfg(feature = "zeroize")]
+        wipe::bytes(bytes);
+        tx
     }
 }
 
@@ -91,7 +194,8 @@ impl TryFrom<&[u8]> for Transaction {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// The id of a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Txid(bitcoin::Txid);
 
 impl std::fmt::Display for Txid {
@@ -100,6 +204,60 @@ impl std::fmt::Display for Txid {
     }
 }
 
+impl FromStr for Txid {
+    type Err = ParseTxidError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.trim().parse().map(Self).map_err(|_| ParseTxidError)
+    }
+}
+
+impl From<bitcoin::Txid> for Txid {
+    fn from(value: bitcoin::Txid) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Txid> for bitcoin::Txid {
+    fn from(value: Txid) -> Self {
+        value.0
+    }
+}
+
+/// The hash of a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlockHash(bitcoin::BlockHash);
+
+impl std::fmt::Display for BlockHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<bitcoin::BlockHash> for BlockHash {
+    fn from(value: bitcoin::BlockHash) -> Self {
+        Self(value)
+    }
+}
+
+impl From<BlockHash> for bitcoin::BlockHash {
+    fn from(value: BlockHash) -> Self {
+        value.0
+    }
+}
+
+/// The input could not be interpreted as a txid (64 hex characters).
+#[derive(Debug)]
+pub struct ParseTxidError;
+
+impl std::error::Error for ParseTxidError {}
+
+impl std::fmt::Display for ParseTxidError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Txid is not 64 hex characters")
+    }
+}
+
 /// Why an input could not be interpereted as a valid transaction.
 #[derive(Debug)]
 pub enum ParseTxError {
@@ -107,6 +265,10 @@ pub enum ParseTxError {
     NotHex,
     /// The provided bytes did not deserialize to a valid transaction.
     InvalidTxBytes,
+    /// The provided bytes did not deserialize to a valid PSBT.
+    InvalidPsbt,
+    /// The PSBT has inputs that are not finalized yet.
+    UnfinalizedPsbt,
 }
 
 impl std::error::Error for ParseTxError {}
@@ -116,6 +278,8 @@ impl std::fmt::Display for ParseTxError {
         match self {
             ParseTxError::NotHex => write!(f, "Transaction is not valid hex"),
             ParseTxError::InvalidTxBytes => write!(f, "Transaction bytes are invalid"),
+            ParseTxError::InvalidPsbt => write!(f, "PSBT is invalid"),
+            ParseTxError::UnfinalizedPsbt => write!(f, "PSBT is not finalized"),
         }
     }
 }
@@ -133,17 +297,477 @@ pub enum TorMode {
     Must,
 }
 
+/// The P2P protocol version advertised by default.
+pub const PROTOCOL_VERSION: u32 = 70016;
+
+/// Settings for an escalating broadcast. The transactions are first sent to the broadcast peers
+/// alone and no broadcast peer is rotated out. Each
+/// time `window` passes without every transaction being acked, they are also sent to `2^n` more
+/// peers, where `n` is the stage reached, up to `max_stage`.
+#[derive(Debug, Clone)]
+pub struct Escalation {
+    /// How long to wait for acks before moving on to the next stage.
+    pub window: std::time::Duration,
+    /// The last stage. Stage 0 is the broadcast peers alone, so with a single broadcast peer the
+    /// default of 4 reaches 31 peers.
+    pub max_stage: u32,
+}
+
+impl Default for Escalation {
+    fn default() -> Self {
+        Self {
+            window: std::time::Duration::from_secs(10),
+            max_stage: 4,
+        }
+    }
+}
+
+/// What it takes for a broadcast to be done. Transactions that the network already had count as
+/// acked by any number of peers. The default is `AllAcked`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SuccessPolicy {
+    /// Every transaction is acked by at least one peer.
+    #[default]
+    AllAcked,
+    /// At least this many of the transactions are acked by at least one peer.
+    AtLeast(usize),
+    /// Every transaction is acked by at least this many peers.
+    PeersPerTx(usize),
+}
+
+impl SuccessPolicy {
+    /// Whether the policy is met, given how many peers acked each transaction of the batch.
+    pub(crate) fn is_met(&self, mut acks: impl Iterator<Item = usize>) -> bool {
+        match *self {
+            SuccessPolicy::AllAcked => acks.all(|n| n >= 1),
+            SuccessPolicy::AtLeast(k) => acks.filter(|n| *n >= 1).count() >= k,
+            SuccessPolicy::PeersPerTx(p) => acks.all(|n| n >= p),
+        }
+    }
+
+    /// How much of the policy is met, from 0 to 1, given the same counts as `is_met`.
+    pub(crate) fn progress(&self, acks: impl Iterator<Item = usize>) -> f32 {
+        let (have, need) = match *self {
+            SuccessPolicy::AllAcked => {
+                acks.fold((0, 0), |(have, need), n| (have + n.min(1), need + 1))
+            }
+            SuccessPolicy::AtLeast(k) => (acks.filter(|n| *n >= 1).count().min(k), k),
+            SuccessPolicy::PeersPerTx(p) => {
+                acks.fold((0, 0), |(have, need), n| (have + n.min(p), need + p))
+            }
+        };
+        if need == 0 {
+            1.0
+        } else {
+            have as f32 / need as f32
+        }
+    }
+}
+
+/// Time budgets for the individual phases of a broadcast. A phase without a budget is only
+/// bounded by `Opts::max_time`, which always applies.
+#[derive(Debug, Clone, Default)]
+pub struct PhaseTimeouts {
+    /// How long to wait for DNS seeds. Seeds that have not answered by then are left out.
+    pub resolve: Option<std::time::Duration>,
+    /// How long to wait for the first peer to complete its handshake. The broadcast fails with
+    /// `Error::ConnectTimeout` if none does.
+    pub connect: Option<std::time::Duration>,
+    /// How long to wait for acks once the transactions first go out, so that a slow connection
+    /// phase does not eat into the time needed to observe propagation.
+    pub broadcast: Option<std::time::Duration>,
+}
+
+/// The contents of the `version` message sent to peers during handshakes. The defaults blend in
+/// with other light clients; unusual values make the broadcast easier to fingerprint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandshakeConfig {
+    /// The user agent, e.g. `/Satoshi:27.0.0/`.
+    pub user_agent: String,
+    /// The height of our best block.
+    pub start_height: u32,
+    /// The POSIX time in seconds to claim as ours. The system time is used if not set.
+    pub timestamp: Option<u64>,
+    /// The timestamp of each connection is moved by a random amount of up to this much in either
+    /// direction, so that simultaneous connections cannot be linked by identical timestamps.
+    pub timestamp_jitter: std::time::Duration,
+    /// The service bits we claim to offer.
+    pub services: u64,
+    /// Whether peers should announce their transactions to us.
+    pub relay: bool,
+}
+
+impl Default for HandshakeConfig {
+    fn default() -> Self {
+        Self {
+            user_agent: "/pynode:0.0.1/".to_string(),
+            start_height: 0,
+            timestamp: None,
+            timestamp_jitter: std::time::Duration::from_secs(5),
+            services: 0,
+            relay: true,
+        }
+    }
+}
+
+/// What a peer has to advertise in its `version` message to be kept after the handshake. Peers
+/// that fall short are disconnected and replaced before they can be picked for broadcasting.
+#[derive(Debug, Clone)]
+pub struct PeerRequirements {
+    /// Service bits that must all be set. Peers from DNS seeds and the fixed list must also set
+    /// `NODE_NETWORK` (1), which is what the seeds vouch for. Defaults to `NODE_WITNESS` (8).
+    pub services: u64,
+    /// The lowest acceptable protocol version. Defaults to 70015 (Bitcoin Core 0.18).
+    pub min_version: u32,
+    /// Whether to drop peers that turned off transaction relay, such as `-blocksonly` nodes.
+    pub relay: bool,
+}
+
+impl Default for PeerRequirements {
+    fn default() -> Self {
+        Self {
+            services: 8,
+            min_version: 70015,
+            relay: true,
+        }
+    }
+}
+
 /// Defines how the initial pool of peers that we broadcast to is found.
 #[derive(Debug, Default, Clone)]
 pub enum FindPeerStrategy {
     /// First resolve peers from DNS seeds (same as Bitcoin Core). Fall back on a fixed peer list
-    /// (also taken from Bitcoin Core) if that fails. Failure is defined a finding less than 20 peers.
+    /// (also taken from Bitcoin Core) if that fails. Failure is defined as finding fewer peers
+    /// than `Opts::fixed_fallback_threshold`.
     #[default]
     DnsSeedWithFixedFallback,
+    /// Resolve peers from DNS seeds and always mix in the fixed peer list.
+    DnsSeedAndFixed,
     /// Resolve peers from DNS seeds only.
     DnsSeedOnly,
+    /// Use the fixed peer list only, or a list in the same format read from the given file. No
+    /// DNS query is ever made.
+    FixedOnly(Option<std::path::PathBuf>),
     /// Use a user provided list of nodes.
-    Custom(Vec<SocketAddr>),
+    Custom(Vec<Service>),
+}
+
+/// Faults to inject into a broadcast, for testing how an application copes with them. Nothing is
+/// injected by default.
+#[cfg(feature = "chaos")]
+#[derive(Debug, Clone, Default)]
+pub struct Chaos {
+    /// Every connection is dialed after a random delay of up to this long.
+    pub max_connect_delay: Option<std::time::Duration>,
+    /// The probability, from 0 to 1, that a peer is disconnected halfway through its handshake.
+    pub handshake_drop: f64,
+    /// The probability, from 0 to 1, that a transaction is not sent to a broadcast peer and
+    /// reported as rejected instead.
+    pub reject: f64,
+}
+
+/// An arbitrary message to send to connected peers.
+#[cfg(feature = "raw-messages")]
+#[derive(Debug, Clone)]
+pub struct RawMessage {
+    /// The peer to send the message to, or every peer that completed its handshake if not set.
+    /// Dropped if the peer is not connected.
+    pub peer: Option<Service>,
+    /// The message itself.
+    pub message: bitcoin::p2p::message::NetworkMessage,
+}
+
+/// A transaction announcement seen from a peer.
+#[derive(Debug, Clone)]
+pub struct InvObservation {
+    /// The peer that announced the transaction.
+    pub peer: Service,
+    /// The announced transaction.
+    pub tx: ObservedTx,
+    /// When the announcement was received.
+    pub at: std::time::SystemTime,
+}
+
+/// A connection to a peer, reported once it is over.
+#[derive(Debug, Clone)]
+pub struct ConnectionRecord {
+    /// The address that was dialed.
+    pub peer: Service,
+    /// When the address was dialed.
+    pub dialed_at: std::time::SystemTime,
+    /// When the connection was established, if it was.
+    pub connected_at: Option<std::time::SystemTime>,
+    /// When the attempt or the connection ended.
+    pub ended_at: std::time::SystemTime,
+    /// The SOCKS proxy in use when the connection was established or failed, if any.
+    pub proxy: Option<SocketAddr>,
+    /// How it ended.
+    pub outcome: ConnectionOutcome,
+}
+
+/// How a connection to a peer ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionOutcome {
+    /// The connection attempt failed.
+    Failed(ConnectFailure),
+    /// The connection was established and closed later, by either side or when the broadcast
+    /// ended.
+    Closed,
+    /// The broadcast ended before the connection attempt did.
+    Abandoned,
+}
+
+impl std::fmt::Display for ConnectionOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionOutcome::Failed(cause) => write!(f, "failed: {cause}"),
+            ConnectionOutcome::Closed => write!(f, "closed"),
+            ConnectionOutcome::Abandoned => write!(f, "abandoned"),
+        }
+    }
+}
+
+/// A live view of a running broadcast, for use as `Opts::monitor`. It can be polled from any
+/// thread at any time, as an alternative to following the `Info` events. Clones share the same
+/// view, so keep one and hand a clone to the broadcast.
+#[derive(Debug, Clone, Default)]
+pub struct Monitor(std::sync::Arc<std::sync::Mutex<MonitorState>>);
+
+#[derive(Debug, Default)]
+struct MonitorState {
+    peers: Vec<PeerStatus>,
+    status: Status,
+    /// The clock of the broadcast and when it started, until it is over.
+    started: Option<(std::sync::Arc<dyn Clock>, std::time::Instant)>,
+    /// The monitors of the paths of a diverse broadcast, which the view combines.
+    paths: Vec<Monitor>,
+}
+
+impl Monitor {
+    /// Creates an empty view.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The peers being dialed or connected right now. Empty before the first peer is dialed and
+    /// once the broadcast is over.
+    pub fn peers(&self) -> Vec<PeerStatus> {
+        let state = self.state();
+        if state.paths.is_empty() {
+            state.peers.clone()
+        } else {
+            state.paths.iter().flat_map(Monitor::peers).collect()
+        }
+    }
+
+    /// Where the broadcast stands right now. A diverse broadcast is as far along as its slower
+    /// path and counts the peers and acks of both.
+    pub fn status(&self) -> Status {
+        let state = self.state();
+        if !state.paths.is_empty() {
+            let mut combined = Status {
+                phase: Phase::Done,
+                ..Default::default()
+            };
+            for status in state.paths.iter().map(Monitor::status) {
+                combined.phase = combined.phase.min(status.phase);
+                combined.connected += status.connected;
+                for (txid, acks) in status.acks_per_tx {
+                    *combined.acks_per_tx.entry(txid).or_default() += acks;
+                }
+                combined.elapsed = combined.elapsed.max(status.elapsed);
+            }
+            return combined;
+        }
+        let mut status = state.status.clone();
+        if let Some((clock, start)) = &state.started {
+            status.elapsed = clock.elapsed(*start);
+        }
+        status
+    }
+
+    /// Marks the start of a broadcast.
+    pub(crate) fn start(&self, clock: std::sync::Arc<dyn Clock>) {
+        let start = clock.now();
+        *self.state() = MonitorState {
+            status: Status {
+                phase: Phase::ResolvingPeers,
+                ..Default::default()
+            },
+            started: Some((clock, start)),
+            ..Default::default()
+        };
+    }
+
+    /// Splits the view into one monitor for each path of a diverse broadcast, which it combines
+    /// from then on.
+    pub(crate) fn split(&self) -> (Monitor, Monitor) {
+        let paths = (Monitor::new(), Monitor::new());
+        *self.state() = MonitorState {
+            paths: vec![paths.0.clone(), paths.1.clone()],
+            ..Default::default()
+        };
+        paths
+    }
+
+    /// Moves on to another phase.
+    pub(crate) fn phase(&self, phase: Phase) {
+        self.state().status.phase = phase;
+    }
+
+    /// Replaces the peers and the progress of the running broadcast.
+    pub(crate) fn update(&self, phase: Phase, peers: Vec<PeerStatus>, acks: HashMap<Txid, usize>) {
+        let mut state = self.state();
+        state.status.phase = phase;
+        state.status.connected = peers
+            .iter()
+            .filter(|peer| peer.state != PeerState::Connecting)
+            .count();
+        state.status.acks_per_tx = acks;
+        state.peers = peers;
+    }
+
+    /// Marks the end of the broadcast, which stops the time.
+    pub(crate) fn finish(&self) {
+        let mut state = self.state();
+        if let Some((clock, start)) = state.started.take() {
+            state.status.elapsed = clock.elapsed(start);
+        }
+        state.status.phase = Phase::Done;
+        state.status.connected = 0;
+        state.peers.clear();
+    }
+
+    /// The state behind the view. A panic elsewhere while it was locked leaves it as it was,
+    /// which is still good enough to look at.
+    fn state(&self) -> std::sync::MutexGuard<'_, MonitorState> {
+        self.0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// A snapshot of a broadcast, see `Monitor::status`.
+#[derive(Debug, Clone, Default)]
+pub struct Status {
+    /// What the broadcast is doing.
+    pub phase: Phase,
+    /// How many peers are connected, whether handshaking or not.
+    pub connected: usize,
+    /// How many peers acked each transaction so far. Transactions that nobody acked yet are
+    /// missing.
+    pub acks_per_tx: HashMap<Txid, usize>,
+    /// The time since the broadcast started, or how long it took once it is over.
+    pub elapsed: std::time::Duration,
+}
+
+/// The phases a broadcast goes through, in order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Phase {
+    /// The broadcast has not started.
+    #[default]
+    Pending,
+    /// Looking up peers.
+    ResolvingPeers,
+    /// Connecting to peers, before anything is sent.
+    Connecting,
+    /// Sending the transactions and watching for acks.
+    Broadcasting,
+    /// Staying connected after success, see `Opts::linger`.
+    Lingering,
+    /// The broadcast is over.
+    Done,
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Phase::Pending => write!(f, "pending"),
+            Phase::ResolvingPeers => write!(f, "resolving peers"),
+            Phase::Connecting => write!(f, "connecting"),
+            Phase::Broadcasting => write!(f, "broadcasting"),
+            Phase::Lingering => write!(f, "lingering"),
+            Phase::Done => write!(f, "done"),
+        }
+    }
+}
+
+/// A peer as seen through `Monitor::peers`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerStatus {
+    /// The address of the peer.
+    pub peer: Service,
+    /// How far along the peer is.
+    pub state: PeerState,
+}
+
+/// The state of a peer of a running broadcast.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PeerState {
+    /// Being dialed.
+    Connecting,
+    /// Connected and handshaking.
+    Handshaking,
+    /// Done handshaking and monitoring for acks.
+    Ready,
+    /// Picked as a broadcast peer.
+    Selected,
+}
+
+impl std::fmt::Display for PeerState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PeerState::Connecting => write!(f, "connecting"),
+            PeerState::Handshaking => write!(f, "handshaking"),
+            PeerState::Ready => write!(f, "ready"),
+            PeerState::Selected => write!(f, "selected"),
+        }
+    }
+}
+
+/// How a peer identified an announced transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ObservedTx {
+    /// By txid.
+    Txid(Txid),
+    /// By wtxid, for peers that negotiated wtxid relay (BIP-339).
+    Wtxid(bitcoin::Wtxid),
+}
+
+impl std::fmt::Display for ObservedTx {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObservedTx::Txid(txid) => write!(f, "txid:{txid}"),
+            ObservedTx::Wtxid(wtxid) => write!(f, "wtxid:{wtxid}"),
+        }
+    }
+}
+
+/// How to answer a `getaddr` message. Each peer gets at most one answer per connection.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AddrResponse {
+    /// Do not answer. This is what Bitcoin Core does on outbound connections, which are the only
+    /// kind that pushtx makes.
+    #[default]
+    Ignore,
+    /// Answer with an empty `addr` message.
+    Empty,
+    /// Answer with up to this many clearnet addresses drawn from the peer pool, with timestamps
+    /// from the last few hours.
+    Sample(u8),
+}
+
+/// Where to look for a full node of the user's own before broadcasting.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LocalNodeProbe {
+    /// Do not look.
+    #[default]
+    Off,
+    /// Try the network's P2P port on localhost.
+    Localhost,
+    /// Try localhost, then the default gateway of the LAN (Linux only), for nodes running on a
+    /// home router or a box next to it.
+    LocalhostAndGateway,
 }
 
 /// The network to connect to.
@@ -174,19 +798,349 @@ pub struct Opts {
     pub network: Network,
     /// Whether to broadcast through Tor if a local instance of it is found running.
     pub use_tor: TorMode,
+    /// SOCKS proxies to use instead of looking for a local Tor instance, in order of preference.
+    /// The first reachable one is used, and if it becomes unreachable mid-run the next reachable
+    /// one takes over. Ignored with `TorMode::No`. Empty by default.
+    pub socks_proxies: Vec<SocketAddr>,
     /// Which strategy to use to find the pool to draw peers from.
     pub find_peer_strategy: FindPeerStrategy,
+    /// How few peers DNS seeding has to yield for `FindPeerStrategy::DnsSeedWithFixedFallback` to
+    /// fall back on the fixed peer list. Tor-heavy or censored environments often resolve fewer
+    /// peers than usual without anything being wrong, and may want to lower it.
+    pub fixed_fallback_threshold: usize,
+    /// Where to cache DNS seed results between broadcasts. Off by default, which queries the
+    /// seeds every time.
+    pub dns_cache: Option<seeds::DnsCache>,
+    /// Whether to look for a local full node and, if one is found, connect to it on top of the
+    /// peer pool and always pick it as a broadcast peer. Acks are still only counted from random
+    /// peers. Skipped when connecting through Tor, which cannot reach local addresses. Off by
+    /// default.
+    pub local_node: LocalNodeProbe,
     /// The maximum allowed duration for broadcasting regardless of the result. Terminates afterward.
+    /// Peer resolution happens before and is not counted.
     pub max_time: std::time::Duration,
+    /// How long to stay connected once every transaction was seen, to keep counting acks and
+    /// collecting late rejects for the report. This period is bounded on its own and may run
+    /// past `max_time`. No replacement peers are dialed while lingering. Zero by default.
+    pub linger: std::time::Duration,
+    /// How long to keep watching block announcements once every transaction was seen. Each new
+    /// block is fetched in compact form from the peer that announced it and checked for the
+    /// transactions, which are reported through `Info::Confirmed`. Extends `Opts::linger` and
+    /// ends early once every transaction is confirmed. Zero (off) by default.
+    pub watch_blocks: std::time::Duration,
+    /// Separate time budgets for the phases of the broadcast, on top of `max_time`.
+    pub phase_timeouts: PhaseTimeouts,
     /// Whether to simulate the broadcast. This means that every part of the process will be
-    /// executed as normal, including connecting to actual peers, but the final part where the tx
-    /// is sent out is omitted (we pretend that the transaction really did go out and was seen.)
+    /// executed as normal, including connecting to actual peers and announcing the transactions
+    /// to them, but the transactions themselves are never sent out. A transaction counts as a
+    /// success once a peer asks for it, which is what it would have done had it been willing to
+    /// accept it.
     pub dry_run: bool,
-    /// How many peers to connect to.
-    pub target_peers: u8,
-    /// Custom user agent, POSIX time (secs) and block height to send during peer handshakes.
-    /// Exercise caution modifying this.
-    pub ua: Option<(String, u64, u64)>,
+    /// How many peers the transactions are sent to at the same time. Every broadcast peer is
+    /// rotated out after a while.
+    pub broadcast_peers: u8,
+    /// How many peers to connect to on top of the broadcast peers, only to watch for the
+    /// transactions coming back. Acks are only counted from these. Up to a few hundred are fine
+    /// for research use, provided that `max_connection_attempts` is raised to match and the
+    /// address book has enough peers to choose from.
+    pub monitor_peers: u16,
+    /// The maximum number of connection attempts over the whole broadcast, replacements
+    /// included. Each address is also dialed at most twice. Once no more attempts can be made and
+    /// no peer is connected, the broadcast fails with `Error::AllConnectionsFailed`.
+    pub max_connection_attempts: usize,
+    /// The minimum time between two rounds of dialing replacements for lost or unreachable
+    /// peers. Failures that happen in the meantime are replaced together in the next round,
+    /// which keeps a bad stretch of the address book from being burned through in seconds.
+    pub replacement_interval: std::time::Duration,
+    /// How long a peer may take to complete its handshake before a replacement is dialed next
+    /// to it. The slow peer is kept and used if it finishes after all, so that slow Tor circuits
+    /// do not shrink the usable peer set for most of the broadcast. Zero disables this.
+    pub handshake_stall: std::time::Duration,
+    /// The P2P protocol version advertised during peer handshakes. Peers only negotiate the
+    /// features that both sides' versions support, e.g. wtxid relay needs 70016 and `feefilter`
+    /// needs 70013. Mostly useful for compatibility testing against old nodes.
+    pub protocol_version: u32,
+    /// What to advertise about ourselves during peer handshakes. Exercise caution modifying this.
+    pub handshake: HandshakeConfig,
+    /// What peers have to advertise to be kept after the handshake.
+    pub peer_requirements: PeerRequirements,
+    /// The maximum number of bytes per second to send across all peers, handshakes included.
+    /// Messages over the limit are delayed rather than dropped. Useful on metered or slow (e.g.
+    /// Tor) links, at the cost of a slower broadcast. Unlimited if not set.
+    pub max_bandwidth: Option<u32>,
+    /// The largest message payload in bytes to accept from peers. A peer whose message header
+    /// declares a larger payload is disconnected before the payload is read, which bounds the
+    /// memory that a hostile peer can tie up. Clamped to between 4 KiB and the default of 2 MiB.
+    /// Caps below about 1.8 MB also drop honest peers that announce full `inv` messages. Has no
+    /// effect on broadcasts through a shared `Reactor`.
+    pub max_message_size: Option<u32>,
+    /// Seeds the random number generator behind peer shuffling, replacement choice, handshake
+    /// nonces and Tor circuit credentials, so that runs are reproducible in tests and
+    /// simulations. Never set this in production: it makes nonces and circuits predictable.
+    /// Randomly seeded if not set.
+    pub rng_seed: Option<u64>,
+    /// The source of time for timeouts, lingering, rotation and escalation. The system clock by
+    /// default. A virtual clock lets tests run time-dependent behavior without sleeping.
+    pub clock: std::sync::Arc<dyn Clock>,
+    /// Peers in these countries are left out of the pool, so they are neither broadcast to nor
+    /// counted as acks. Onion peers and peers missing from the map are kept.
+    pub exclude_countries: Option<geo::CountryExclusion>,
+    /// The outputs spent by the transactions. If not empty, the input scripts of every
+    /// transaction are verified before anything is broadcast and the broadcast fails if any of
+    /// them is invalid or spends an output that is neither listed here nor created in the batch.
+    #[cfg(feature = "consensus-validation")]
+    pub prevouts: Vec<Prevout>,
+    /// Peers whose advertised user agent does not pass this filter are disconnected right after
+    /// the handshake and replaced. Off by default.
+    #[cfg(feature = "ua-filter")]
+    pub ua_filter: Option<UaFilter>,
+    /// Messages received here are sent as they are to connected peers that completed their
+    /// handshake. Nothing stops them from interfering with the broadcast, so use with care.
+    #[cfg(feature = "raw-messages")]
+    pub raw_messages: Option<crossbeam_channel::Receiver<RawMessage>>,
+    /// Faults to inject, for testing applications against a misbehaving network.
+    #[cfg(feature = "chaos")]
+    pub chaos: Chaos,
+    /// Whether to record evidence of the broadcast in `Report::attestation`, and the key to sign
+    /// it with. Off by default.
+    pub attestation: Attestation,
+    /// Whether to fetch the transactions that peers announce while monitoring and warn through
+    /// `Warning::DoubleSpend` about those that spend the same outputs as the batch, such as an
+    /// RBF replacement in flight. Fetching is rate limited. Off by default.
+    pub conflict_alerts: bool,
+    /// Whether to ask a few peers for the transactions before broadcasting anything. The ones
+    /// that peers already have are reported in `Report::already_known` and not sent.
+    pub presence_probe: bool,
+    /// Whether to stop as soon as a peer rejects one of the transactions. Nothing more is sent
+    /// or announced, and the transactions that never went out are listed in `Report::unsent`.
+    /// Off by default.
+    pub halt_on_reject: bool,
+    /// How long a transaction may go without an ack after it first went out before it is given
+    /// up on. A transaction given up on is listed in `Report::timed_out` and announced through
+    /// `Warning::AckTimeout`, while the rest of the batch carries on. The broadcast ends early
+    /// once the success policy can no longer be met. Zero, the default, leaves every transaction
+    /// to `max_time`.
+    pub ack_timeout: std::time::Duration,
+    /// How long to only listen for announcements of the transactions once the first peer is
+    /// ready, before sending or announcing anything. Transactions that are already being relayed
+    /// end up in `Report::already_known` and are never sent, so the session is not linked to
+    /// them. Counts towards `max_time`. Off by default.
+    pub listen_first: Option<std::time::Duration>,
+    /// Sends to progressively more peers while acks are missing instead of rotating a single
+    /// broadcast peer, trading some privacy for reliability only when needed. The stage that
+    /// succeeded is reported in `Report::escalation_stage`. Off by default.
+    pub escalation: Option<Escalation>,
+    /// When the broadcast is done. It stops, or starts lingering, as soon as the policy is met,
+    /// and `Report::policy_met` tells whether it was by the end.
+    pub success_policy: SuccessPolicy,
+    /// Every transaction announced by a connected peer is reported here as it is seen, whether
+    /// it is ours or not. Useful for propagation research. Off by default.
+    pub inv_log: Option<crossbeam_channel::Sender<InvObservation>>,
+    /// Every address dialed is reported here once the attempt or connection is over, along with
+    /// the outcome. Meant for a durable record of the hosts contacted. Off by default.
+    pub connection_log: Option<crossbeam_channel::Sender<ConnectionRecord>>,
+    /// Kept up to date with the peers being dialed or connected and the progress of the
+    /// broadcast, for polling instead of or alongside the `Info` events. With
+    /// `broadcast_diverse`, both paths share the view and it shows whichever path changed last.
+    /// Off by default.
+    pub monitor: Option<Monitor>,
+    /// How to answer peers that ask for addresses through `getaddr`.
+    pub getaddr_response: AddrResponse,
+    /// Transactions that replace others in the batch. When two transactions spend the same
+    /// output only one of them can propagate: the one listed here is kept and the other one is
+    /// dropped. Conflicts where neither or both are listed fail the broadcast.
+    pub replacements: HashSet<Txid>,
+    /// An explorer to ask about the transactions that were seen on the network, as a second and
+    /// independent confirmation. The broadcast itself stays P2P-only. Off by default.
+    #[cfg(feature = "explorer")]
+    pub explorer: Option<ExplorerCheck>,
+    /// Relays to publish the transactions to if they fail to propagate through the P2P network.
+    /// Off by default.
+    #[cfg(feature = "nostr")]
+    pub nostr: Option<NostrFallback>,
+    /// Electrum servers to publish the transactions through if the P2P broadcast fails entirely,
+    /// that is if no connection could be made or no transaction propagated. Off by default.
+    #[cfg(feature = "electrum")]
+    pub electrum: Option<ElectrumFallback>,
+    /// A node to check the transactions with before broadcasting and to submit them to if they
+    /// do not propagate. The broadcast itself stays P2P. Off by default.
+    #[cfg(feature = "rpc")]
+    pub rpc: Option<RpcNode>,
+}
+
+/// Allow and deny patterns matched against the user agent a peer advertises, e.g.
+/// `/Satoshi:27.0.0/`.
+#[cfg(feature = "ua-filter")]
+#[derive(Debug, Clone, Default)]
+pub struct UaFilter {
+    /// If set, only peers whose user agent matches are kept, e.g. `^/Satoshi:`.
+    pub allow: Option<regex::Regex>,
+    /// If set, peers whose user agent matches are dropped, even if they are allowed.
+    pub deny: Option<regex::Regex>,
+}
+
+#[cfg(feature = "ua-filter")]
+impl UaFilter {
+    /// Whether a peer with this user agent passes the filter.
+    pub fn allows(&self, user_agent: &str) -> bool {
+        let allowed = match &self.allow {
+            Some(allow) => allow.is_match(user_agent),
+            None => true,
+        };
+        let denied = match &self.deny {
+            Some(deny) => deny.is_match(user_agent),
+            None => false,
+        };
+        allowed && !denied
+    }
+}
+
+/// A source of time, see `Opts::clock`.
+pub trait Clock: std::fmt::Debug + Send + Sync {
+    /// The current monotonic time.
+    fn now(&self) -> std::time::Instant;
+
+    /// The current wall clock time.
+    fn system_now(&self) -> std::time::SystemTime;
+
+    /// The time that passed since `earlier`, or zero if `earlier` is in the future.
+    fn elapsed(&self, earlier: std::time::Instant) -> std::time::Duration {
+        self.now().saturating_duration_since(earlier)
+    }
+}
+
+/// The system clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> std::time::Instant {
+        std::time::Instant::now()
+    }
+
+    fn system_now(&self) -> std::time::SystemTime {
+        std::time::SystemTime::now()
+    }
+}
+
+/// Whether and how to attest to a broadcast, see `Report::attestation`.
+#[derive(Debug, Clone, Default)]
+pub enum Attestation {
+    /// No evidence is recorded.
+    #[default]
+    Off,
+    /// Evidence is recorded but not signed.
+    Unsigned,
+    /// Evidence is recorded and signed with this key (BIP-340 Schnorr over the SHA-256 of the
+    /// `attestation` JSON value, exactly as serialized).
+    Signed(bitcoin::secp256k1::SecretKey),
+}
+
+impl Attestation {
+    /// Signed with a secret key given as 64 hex characters. Returns `None` if the key is invalid.
+    pub fn signed_from_hex(key: &str) -> Option<Self> {
+        key.trim().parse().ok().map(Self::Signed)
+    }
+}
+
+/// Nostr relays that accept raw transactions for broadcast.
+#[cfg(feature = "nostr")]
+#[derive(Debug, Clone)]
+pub struct NostrFallback {
+    /// Websocket URLs of the relays, e.g. `wss://relay.example.com`.
+    pub relays: Vec<String>,
+    /// The event kind that the relays' tx broadcast convention uses. Each transaction is sent as
+    /// an event of this kind with the hex encoded transaction as its content.
+    pub kind: u16,
+}
+
+/// Electrum servers that the transactions are published through as a last resort.
+#[cfg(feature = "electrum")]
+#[derive(Debug, Clone)]
+pub struct ElectrumFallback {
+    /// Server URLs, tried in order, e.g. `ssl://electrum.example.com:50002`. Certificates are
+    /// checked against the web PKI, so servers with self-signed certificates fail. Plaintext
+    /// `tcp://` servers, including onion ones, are only used through Tor.
+    pub servers: Vec<String>,
+}
+
+/// The RPC interface of a node and what to use it for.
+#[cfg(feature = "rpc")]
+#[derive(Debug, Clone)]
+pub struct RpcNode {
+    /// The RPC URL, e.g. `http://127.0.0.1:8332`.
+    pub url: String,
+    /// The credentials to authenticate with.
+    pub auth: RpcAuth,
+    /// Asks the node with `testmempoolaccept` before broadcasting. A transaction it refuses
+    /// fails the broadcast with `Error::NodeRejected` and nothing is sent.
+    pub preflight: bool,
+    /// Submits the transactions that did not propagate through P2P with `sendrawtransaction`
+    /// once the broadcast is over. See `Report::rpc_submitted`.
+    pub fallback: bool,
+}
+
+/// How to authenticate to the RPC interface of a node.
+#[cfg(feature = "rpc")]
+#[derive(Clone)]
+pub enum RpcAuth {
+    /// The `rpcuser` and `rpcpassword` of the node.
+    UserPass { user: String, password: String },
+    /// The path of the node's `.cookie` file.
+    Cookie(std::path::PathBuf),
+}
+
+#[cfg(feature = "rpc")]
+impl std::fmt::Debug for RpcAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RpcAuth::UserPass { user, .. } => f
+                .debug_struct("UserPass")
+                .field("user", user)
+                .finish_non_exhaustive(),
+            RpcAuth::Cookie(path) => f.debug_tuple("Cookie").field(path).finish(),
+        }
+    }
+}
+
+/// Where and when to cross-check a broadcast against a block explorer.
+#[cfg(feature = "explorer")]
+#[derive(Debug, Clone)]
+pub struct ExplorerCheck {
+    /// The base URL of an Esplora-compatible API, e.g. `https://blockstream.info/api`.
+    pub url: String,
+    /// How long to wait after the broadcast before asking, giving the explorer's own nodes time
+    /// to see the transactions.
+    pub delay: std::time::Duration,
+}
+
+/// An output spent by a transaction, needed to verify the transaction's input scripts.
+#[cfg(feature = "consensus-validation")]
+#[derive(Debug, Clone)]
+pub struct Prevout {
+    /// The txid of the transaction that created the output.
+    pub txid: Txid,
+    /// The index of the output.
+    pub vout: u32,
+    /// The value of the output in satoshis.
+    pub amount: u64,
+    /// The raw output script.
+    pub script_pubkey: Vec<u8>,
+}
+
+impl Opts {
+    /// How many peers to keep connected in total.
+    pub(crate) fn peer_count(&self) -> usize {
+        usize::from(self.broadcast_peers) + usize::from(self.monitor_peers)
+    }
+
+    /// A random number generator seeded from `rng_seed`, if set.
+    pub(crate) fn rng(&self) -> fastrand::Rng {
+        match self.rng_seed {
+            Some(seed) => fastrand::Rng::with_seed(seed),
+            None => fastrand::Rng::new(),
+        }
+    }
 }
 
 impl Default for Opts {
@@ -194,11 +1148,58 @@ impl Default for Opts {
         Self {
             network: Network::default(),
             use_tor: Default::default(),
+            socks_proxies: Vec::new(),
             find_peer_strategy: Default::default(),
+            fixed_fallback_threshold: seeds::DEFAULT_FALLBACK_THRESHOLD,
+            dns_cache: None,
+            local_node: LocalNodeProbe::Off,
             max_time: std::time::Duration::from_secs(40),
+            linger: std::time::Duration::ZERO,
+            watch_blocks: std::time::Duration::ZERO,
+            phase_timeouts: PhaseTimeouts::default(),
             dry_run: false,
-            target_peers: 10,
-            ua: None,
+            broadcast_peers: 1,
+            monitor_peers: 9,
+            max_connection_attempts: 100,
+            replacement_interval: std::time::Duration::from_secs(2),
+            handshake_stall: std::time::Duration::from_secs(10),
+            protocol_version: PROTOCOL_VERSION,
+            handshake: HandshakeConfig::default(),
+            peer_requirements: PeerRequirements::default(),
+            max_bandwidth: None,
+            max_message_size: None,
+            rng_seed: None,
+            clock: std::sync::Arc::new(SystemClock),
+            exclude_countries: None,
+            #[cfg(feature = "consensus-validation")]
+            prevouts: Vec::new(),
+            #[cfg(feature = "ua-filter")]
+            ua_filter: None,
+            #[cfg(feature = "chaos")]
+            chaos: Chaos::default(),
+            #[cfg(feature = "raw-messages")]
+            raw_messages: None,
+            presence_probe: false,
+            halt_on_reject: false,
+            ack_timeout: std::time::Duration::ZERO,
+            conflict_alerts: false,
+            attestation: Attestation::Off,
+            listen_first: None,
+            escalation: None,
+            success_policy: SuccessPolicy::default(),
+            inv_log: None,
+            connection_log: None,
+            monitor: None,
+            getaddr_response: AddrResponse::default(),
+            replacements: HashSet::new(),
+            #[cfg(feature = "explorer")]
+            explorer: None,
+            #[cfg(feature = "nostr")]
+            nostr: None,
+            #[cfg(feature = "electrum")]
+            electrum: None,
+            #[cfg(feature = "rpc")]
+            rpc: None,
         }
     }
 }
@@ -210,43 +1211,560 @@ pub enum Info {
     ResolvingPeers,
     /// How many peers were resolved.
     ResolvedPeers(usize),
+    /// Every address that may be contacted, in the order they are tried. The first
+    /// `Opts::broadcast_peers + Opts::monitor_peers` are dialed right away and replacements are
+    /// drawn from the rest.
+    PeerPool(Vec<Service>),
     /// Connecting to the p2p network.
     ConnectingToNetwork { tor_status: Option<SocketAddr> },
+    /// A connection attempt failed. The peer is replaced.
+    ConnectFailed {
+        peer: Service,
+        cause: ConnectFailure,
+    },
     /// A tx broadcast to a particular peer was completed.
-    Broadcast { peer: String },
+    Broadcast { peer: Service },
+    /// A transaction was found in a newly announced block while watching blocks. Compact block
+    /// short ids may collide, so this is a near certainty rather than a proof.
+    Confirmed { txid: Txid, block_hash: BlockHash },
+    /// Something noteworthy happened that does not stop the broadcast.
+    Warning(Warning),
+    /// An estimate of how far along the broadcast is, from 0 to 1, for progress bars. It is
+    /// based on the peers connected, the acks gathered towards `Opts::success_policy` and the
+    /// share of `Opts::max_time` used up, and never goes down. Sent when it moves by at least a
+    /// percent or the phase changes, and at least once a second regardless, which is also how a
+    /// broadcast notices that the receiver was dropped and winds down.
+    Progress { phase: Phase, fraction: f32 },
     /// The broadcast process is done.
     Done(Result<Report, Error>),
 }
 
+/// Why a connection attempt failed, as far as the OS or the Tor proxy tell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectFailure {
+    /// The peer actively refused the connection.
+    Refused,
+    /// The peer did not answer in time.
+    TimedOut,
+    /// An onion service peer could not be reached through Tor.
+    OnionUnreachable,
+    /// The Tor proxy failed to connect to a clearnet peer.
+    Proxy,
+    /// Any other I/O error.
+    Other(std::io::ErrorKind),
+}
+
+impl std::fmt::Display for ConnectFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectFailure::Refused => write!(f, "connection refused"),
+            ConnectFailure::TimedOut => write!(f, "timed out"),
+            ConnectFailure::OnionUnreachable => write!(f, "onion service unreachable"),
+            ConnectFailure::Proxy => write!(f, "Tor proxy failure"),
+            ConnectFailure::Other(kind) => write!(f, "{kind}"),
+        }
+    }
+}
+
+/// Non-fatal conditions encountered during the broadcast process.
+#[derive(Debug, Clone)]
+pub enum Warning {
+    /// The same transaction was submitted more than once. It is only broadcast once.
+    DuplicateTx(Txid),
+    /// A transaction conflicts with a replacement in the batch and is not broadcast.
+    ConflictDropped { dropped: Txid, replacement: Txid },
+    /// An output of a transaction violates common relay policy, so most nodes will not relay the
+    /// transaction. It is broadcast anyway.
+    NonStandardOutput {
+        txid: Txid,
+        vout: u32,
+        violation: PolicyViolation,
+    },
+    /// DNS seeding yielded too few peers, so the hardcoded fixed seeds were mixed into the pool.
+    /// The fixed list ships with the library and may be stale.
+    FixedSeedFallback { from_dns: usize, from_fixed: usize },
+    /// A peer sent messages meant for a different network, such as testnet messages from a
+    /// mainnet address. It is disconnected.
+    WrongNetwork { peer: Service },
+    /// A peer announced a foreign transaction that spends an output that a transaction of the
+    /// batch spends as well. Only one of them can confirm.
+    DoubleSpend { ours: Txid, theirs: Txid },
+    /// No peer acked a transaction within `Opts::ack_timeout` of it going out, so it is given up
+    /// on. The diagnosis is the likely reason as far as can be told at that point.
+    AckTimeout { txid: Txid, diagnosis: Diagnosis },
+}
+
+/// Ways in which a transaction output can violate common relay policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyViolation {
+    /// An `OP_RETURN` output larger than the default data carrier limit of 83 bytes.
+    OversizedOpReturn,
+    /// A bare multisig output.
+    BareMultisig,
+    /// A witness program with a version not yet in use.
+    UnknownWitnessVersion,
+    /// An output worth less than it costs to spend it.
+    Dust,
+}
+
+impl std::fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PolicyViolation::OversizedOpReturn => {
+                write!(f, "OP_RETURN over the data carrier limit")
+            }
+            PolicyViolation::BareMultisig => write!(f, "bare multisig"),
+            PolicyViolation::UnknownWitnessVersion => write!(f, "unknown witness version"),
+            PolicyViolation::Dust => write!(f, "dust"),
+        }
+    }
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::DuplicateTx(txid) => write!(f, "Duplicate transaction ignored: {txid}"),
+            Warning::ConflictDropped {
+                dropped,
+                replacement,
+            } => write!(f, "Transaction {dropped} dropped in favor of {replacement}"),
+            Warning::NonStandardOutput {
+                txid,
+                vout,
+                violation,
+            } => write!(f, "Non-standard output {txid}:{vout}: {violation}"),
+            Warning::WrongNetwork { peer } => {
+                write!(f, "Peer {peer} is on a different network, disconnected")
+            }
+            Warning::DoubleSpend { ours, theirs } => {
+                write!(f, "Transaction {theirs} double spends {ours}")
+            }
+            Warning::AckTimeout { txid, diagnosis } => {
+                write!(f, "Transaction {txid} was not acked in time, {diagnosis}")
+            }
+            Warning::FixedSeedFallback {
+                from_dns,
+                from_fixed,
+            } => write!(
+                f,
+                "DNS seeding yielded only {from_dns} peers, added {from_fixed} fixed seed peers"
+            ),
+        }
+    }
+}
+
+/// A transaction that was rejected, see `Report::rejects`. Displays as the reason given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reject {
+    /// The category of the reject, for branching on.
+    pub reason: RejectReason,
+    /// The reason as given, e.g. `min relay fee not met`.
+    pub message: String,
+    /// The peer that sent the reject. Missing for rejects from elsewhere, such as the node of
+    /// `Opts::rpc`.
+    pub peer: Option<Service>,
+}
+
+impl std::fmt::Display for Reject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The reject codes of BIP-61.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RejectReason {
+    /// The transaction could not be decoded.
+    Malformed,
+    /// The transaction breaks consensus rules.
+    Invalid,
+    /// The transaction uses a version that is no longer supported.
+    Obsolete,
+    /// The transaction is already known, e.g. in the mempool or in a block.
+    Duplicate,
+    /// The transaction breaks relay policy.
+    NonStandard,
+    /// An output of the transaction is below the dust threshold.
+    Dust,
+    /// The transaction does not pay enough fees.
+    InsufficientFee,
+    /// The transaction conflicts with a checkpoint.
+    Checkpoint,
+    /// The reject did not come with a BIP-61 code, see `Reject::message`.
+    Other,
+}
+
+impl From<bitcoin::p2p::message_network::RejectReason> for RejectReason {
+    fn from(value: bitcoin::p2p::message_network::RejectReason) -> Self {
+        use bitcoin::p2p::message_network::RejectReason as Code;
+        match value {
+            Code::Malformed => Self::Malformed,
+            Code::Invalid => Self::Invalid,
+            Code::Obsolete => Self::Obsolete,
+            Code::Duplicate => Self::Duplicate,
+            Code::NonStandard => Self::NonStandard,
+            Code::Dust => Self::Dust,
+            Code::Fee => Self::InsufficientFee,
+            Code::Checkpoint => Self::Checkpoint,
+        }
+    }
+}
+
+impl std::fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RejectReason::Malformed => write!(f, "malformed"),
+            RejectReason::Invalid => write!(f, "invalid"),
+            RejectReason::Obsolete => write!(f, "obsolete"),
+            RejectReason::Duplicate => write!(f, "duplicate"),
+            RejectReason::NonStandard => write!(f, "non-standard"),
+            RejectReason::Dust => write!(f, "dust"),
+            RejectReason::InsufficientFee => write!(f, "insufficient fee"),
+            RejectReason::Checkpoint => write!(f, "checkpoint"),
+            RejectReason::Other => write!(f, "other"),
+        }
+    }
+}
+
 /// An informational report on a broadcast outcome.
 #[derive(Debug, Clone)]
 pub struct Report {
     /// The list of transactions that were sent out and then seen on the network.
     pub success: HashSet<Txid>,
-    /// The list of transactions that were rejected, along with the reason.
-    pub rejects: HashMap<Txid, String>,
+    /// The wtxid of every transaction in the batch, for tooling that keys off wtxids, such as
+    /// package relay.
+    pub wtxids: HashMap<Txid, bitcoin::Wtxid>,
+    /// The list of transactions that were rejected, along with the reason. With `Opts::linger`,
+    /// this may also hold late rejects of transactions that are in `success`.
+    pub rejects: HashMap<Txid, Reject>,
+    /// The transactions that the network already had. These are the ones that peers had or
+    /// announced before anything was sent with `Opts::presence_probe` or `Opts::listen_first`,
+    /// and the announced ones that came back without any peer ever asking for them.
+    pub already_known: HashSet<Txid>,
+    /// Whether the outcome meets `Opts::success_policy`. A report is returned either way once the
+    /// broadcast ends.
+    pub policy_met: bool,
+    /// The transactions that were never sent or announced to any peer, such as the ones left
+    /// over when `Opts::halt_on_reject` stopped the broadcast.
+    pub unsent: HashSet<Txid>,
+    /// The transactions that were given up on after `Opts::ack_timeout`. Each one also has an
+    /// entry in `diagnoses`.
+    pub timed_out: HashSet<Txid>,
+    /// Best-effort explanations for the transactions that were neither seen nor rejected.
+    pub diagnoses: HashMap<Txid, Diagnosis>,
+    /// The successful transactions that an explorer also knows about, if a cross-check was
+    /// requested through `Opts::explorer`.
+    pub explorer_seen: Option<HashSet<Txid>>,
+    /// The transactions that failed to propagate and were published to Nostr relays instead, if
+    /// a fallback was configured through `Opts::nostr`.
+    pub nostr_published: Option<HashSet<Txid>>,
+    /// The transactions that were published through Electrum servers after the P2P broadcast
+    /// failed entirely, if a fallback was configured through `Opts::electrum`. None of them went
+    /// out through P2P or the RPC fallback, and if no connection could be made at all, `unsent`
+    /// holds the whole batch.
+    pub electrum_published: Option<HashSet<Txid>>,
+    /// The transactions that did not propagate and that the node then accepted, if a fallback
+    /// was configured through `Opts::rpc`. The ones it refused are in `rejects`.
+    pub rpc_submitted: Option<HashSet<Txid>>,
+    /// How fast the acked transactions propagated.
+    pub latencies: HashMap<Txid, Latency>,
+    /// Which peers asked for each transaction through `getdata` and how quickly. This is an
+    /// earlier sign of acceptance than an ack and shows where a partial propagation stopped.
+    pub interest: HashMap<Txid, Vec<Interest>>,
+    /// How many connection attempts failed, by cause.
+    pub connect_failures: HashMap<ConnectFailure, usize>,
+    /// The minimum fee rates that peers announced. A transaction paying less than a peer's rate
+    /// is not relayed by that peer.
+    pub feefilters: FeeFilters,
+    /// The escalation stage during which the last transaction was acked, if `Opts::escalation`
+    /// is set and every sent transaction was acked.
+    pub escalation_stage: Option<u32>,
+    /// Evidence of the broadcast as JSON, if requested through `Opts::attestation`: timestamps,
+    /// the peer pool, the handshakes, every transaction sent or announced to a peer and every
+    /// ack, in milliseconds since the Unix epoch. Signed attestations carry the signature and
+    /// the x-only public key alongside. With `broadcast_diverse`, a JSON array of one attestation
+    /// per path.
+    pub attestation: Option<String>,
+}
+
+/// The propagation latency of a transaction, measured from the moment it was first sent or
+/// announced to a broadcast peer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Latency {
+    /// The time until each ack, in order of arrival. Each peer counts once.
+    pub acks: Vec<std::time::Duration>,
+}
+
+impl Latency {
+    /// The time until the first ack.
+    pub fn first(&self) -> Option<std::time::Duration> {
+        self.acks.first().copied()
+    }
+
+    /// The time until the `n`th ack, counting from 1.
+    pub fn nth(&self, n: usize) -> Option<std::time::Duration> {
+        self.acks.get(n.checked_sub(1)?).copied()
+    }
+}
+
+/// A `getdata` request for a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interest {
+    /// The peer that asked.
+    pub peer: Service,
+    /// The time from when the transaction first went out until the request.
+    pub after: std::time::Duration,
+}
+
+/// The minimum fee rates that peers announced through `feefilter` messages (BIP-133), in
+/// sat/kvB.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeeFilters {
+    /// The latest rate each peer announced. Peers that never sent a `feefilter` are missing.
+    pub peers: HashMap<Service, u64>,
+}
+
+impl FeeFilters {
+    /// The lowest rate any peer announced.
+    pub fn min(&self) -> Option<u64> {
+        self.peers.values().min().copied()
+    }
+
+    /// The median of the announced rates, the higher one of the two middle rates if there is an
+    /// even number of them.
+    pub fn median(&self) -> Option<u64> {
+        let mut rates: Vec<_> = self.peers.values().copied().collect();
+        rates.sort_unstable();
+        rates.get(rates.len() / 2).copied()
+    }
+}
+
+/// Why a transaction most likely failed to propagate. Modern nodes no longer explain themselves
+/// through `reject` messages, so this is inferred from indirect evidence and may be wrong.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnosis {
+    /// The transaction spends an output of another transaction in the batch that did not
+    /// propagate either.
+    LikelyMissingParent(Txid),
+    /// Peers announced a minimum fee rate (sat/kvB) above the default relay fee, which the
+    /// transaction probably does not pay.
+    LikelyBelowRelayFee { feefilter: u64 },
+    /// Peers asked for the transaction did not have it even though fees are at the floor. It is
+    /// probably non-standard or spends outputs they do not know about.
+    LikelyNonStandard,
+    /// There is not enough evidence to tell.
+    Unknown,
+}
+
+impl std::fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Diagnosis::LikelyMissingParent(parent) => {
+                write!(f, "likely missing its parent {parent}")
+            }
+            Diagnosis::LikelyBelowRelayFee { feefilter } => write!(
+                f,
+                "likely below the relay fee (peers ask for {} sat/vB)",
+                *feefilter as f64 / 1000.0
+            ),
+            Diagnosis::LikelyNonStandard => {
+                write!(f, "likely non-standard or spending unknown outputs")
+            }
+            Diagnosis::Unknown => write!(f, "unknown cause"),
+        }
+    }
 }
 
 /// Possible error variants while broadcasting.
 #[derive(Debug, Clone)]
 pub enum Error {
+    /// Tor was required but a Tor proxy was not found.
     TorNotFound,
+    /// An I/O error occurred in the p2p subsystem.
+    Io(std::io::ErrorKind),
+    /// An unexpected internal condition prevented the broadcast from completing.
+    Internal(String),
+    /// Two transactions in the batch spend the same output and neither (or both) were marked as
+    /// a replacement. Nothing was broadcast.
+    ConflictingTxs(Txid, Txid),
+    /// No peer completed its handshake within `PhaseTimeouts::connect`.
+    ConnectTimeout,
+    /// The options or the transaction set make a broadcast pointless, e.g. no transactions or a
+    /// zero `max_time`. Nothing was broadcast.
+    InvalidOptions(String),
+    /// No peer could be connected to, or every connected peer was lost, and no more connection
+    /// attempts were possible.
+    AllConnectionsFailed { attempts: usize },
+    /// A transaction failed script validation and nothing was broadcast.
+    #[cfg(feature = "consensus-validation")]
+    InvalidTransaction { txid: Txid, reason: String },
+    /// The node refused a transaction in the pre-flight check and nothing was broadcast.
+    #[cfg(feature = "rpc")]
+    NodeRejected { txid: Txid, reason: String },
+    /// The node could not be asked for the pre-flight check. Nothing was broadcast.
+    #[cfg(feature = "rpc")]
+    Rpc(String),
 }
 
+impl std::error::Error for Error {}
+
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::TorNotFound => write!(f, "Tor was required but a Tor proxy was not found"),
+            Error::Io(kind) => write!(f, "P2P I/O error: {kind}"),
+            Error::Internal(reason) => write!(f, "Internal error: {reason}"),
+            Error::ConflictingTxs(a, b) => {
+                write!(f, "Transactions {a} and {b} spend the same output")
+            }
+            Error::ConnectTimeout => write!(f, "No peer completed its handshake in time"),
+            Error::InvalidOptions(reason) => write!(f, "Invalid options: {reason}"),
+            Error::AllConnectionsFailed { attempts } => {
+                write!(f, "All {attempts} connection attempts failed")
+            }
+            #[cfg(feature = "consensus-validation")]
+            Error::InvalidTransaction { txid, reason } => {
+                write!(f, "Invalid transaction {txid}: {reason}")
+            }
+            #[cfg(feature = "rpc")]
+            Error::NodeRejected { txid, reason } => {
+                write!(f, "The node rejected transaction {txid}: {reason}")
+            }
+            #[cfg(feature = "rpc")]
+            Error::Rpc(err) => write!(f, "The node could not be asked: {err}"),
         }
     }
 }
 
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value.kind())
+    }
+}
+
 /// Connects to the p2p network and broadcasts a series of transactions. This runs fully in the
 /// background. Network and other parameters can be set through the `opts` argument.
 ///
 /// Returns a channel where status updates may be read.
 pub fn broadcast(tx: Vec<Transaction>, opts: Opts) -> crossbeam_channel::Receiver<Info> {
-    let (broadcaster, event_rx) = broadcast::Runner::new(tx, opts);
+    let (broadcaster, event_rx) = broadcast::Runner::new(tx, opts, None);
     broadcaster.run();
     event_rx
 }
+
+/// Like `broadcast`, but broadcasts twice in parallel: once exclusively through Tor and once
+/// exclusively through clearnet, with separate connections and peer sets. `opts.use_tor` is
+/// ignored. Succeeds if either path succeeds, in which case the report merges both of them.
+///
+/// Useful when one of the paths may be degraded or watched. Note that both paths send the same
+/// transactions, so an observer of the clearnet path learns them as well.
+pub fn broadcast_diverse(tx: Vec<Transaction>, opts: Opts) -> crossbeam_channel::Receiver<Info> {
+    diverse::run(tx, opts)
+}
+
+/// Replaces txids, addresses and peer IPs in the log output of this library with short hashes so
+/// that logs can be shared without linking the operator to particular transactions and peers.
+/// The hashes are salted per process: the same value maps to the same hash within one process
+/// only. Applies to every broadcast in the process. Off by default.
+pub fn set_log_redaction(enabled: bool) {
+    redact::set(enabled);
+}
+
+/// A p2p reactor that can be shared by several concurrent broadcasts, instead of each of them
+/// spinning up a reactor thread of its own. Cloning it is cheap and yields a handle to the same
+/// reactor, which keeps running for as long as any handle or broadcast is alive.
+///
+/// Broadcasts that share a reactor also share its Tor circuit, which allows the circuit's exit
+/// (or an onion service peer) to link them. Use `broadcast` for unrelated transactions that must
+/// not be linked.
+#[derive(Clone)]
+pub struct Reactor {
+    shared: std::sync::Arc<p2p::SharedReactor>,
+    proxies: Option<p2p::ProxyPool>,
+}
+
+impl Reactor {
+    /// Starts a reactor. Tor is detected once according to `use_tor`, and every broadcast started
+    /// through the reactor connects the same way regardless of its `Opts::use_tor`.
+    pub fn new(use_tor: TorMode) -> Result<Self, Error> {
+        Self::with_proxies(use_tor, &[])
+    }
+
+    /// Starts a reactor that connects through the given SOCKS proxies instead of a local Tor
+    /// instance, failing over between them like `Opts::socks_proxies`.
+    pub fn with_proxies(use_tor: TorMode, socks_proxies: &[SocketAddr]) -> Result<Self, Error> {
+        let proxies = broadcast::tor_proxy(&use_tor, socks_proxies)?;
+        let shared = p2p::SharedReactor::new(proxies.clone())?;
+
+        Ok(Self {
+            shared: std::sync::Arc::new(shared),
+            proxies,
+        })
+    }
+
+    /// Broadcasts a series of transactions through this reactor. Otherwise the same as the free
+    /// standing `broadcast` function.
+    pub fn broadcast(&self, tx: Vec<Transaction>, opts: Opts) -> crossbeam_channel::Receiver<Info> {
+        let (broadcaster, event_rx) = broadcast::Runner::new(tx, opts, Some(self.clone()));
+        broadcaster.run();
+        event_rx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TXID: &str = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+
+    #[test]
+    fn txid_round_trips_through_a_string() {
+        let txid: Txid = TXID.parse().unwrap();
+        assert_eq!(txid.to_string(), TXID);
+        assert_eq!(bitcoin::Txid::from(txid).to_string(), TXID);
+        assert_eq!(format!("  {TXID}\n").parse::<Txid>().unwrap(), txid);
+    }
+
+    #[test]
+    fn txid_refuses_bad_input() {
+        let too_long = format!("{TXID}0");
+        let not_hex = TXID.replace('a', "g");
+        let inner_space = format!("{} {}", &TXID[..32], &TXID[32..]);
+        for bad in ["", &TXID[1..], &too_long, &not_hex, &inner_space] {
+            assert!(bad.parse::<Txid>().is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn success_policy_is_met() {
+        // ack counts per transaction; already known ones count as usize::MAX
+        let cases: &[(SuccessPolicy, &[usize], bool)] = &[
+            (SuccessPolicy::AllAcked, &[], true),
+            (SuccessPolicy::AllAcked, &[1, 3], true),
+            (SuccessPolicy::AllAcked, &[1, 0], false),
+            (SuccessPolicy::AllAcked, &[usize::MAX, 1], true),
+            (SuccessPolicy::AllAcked, &[usize::MAX, 0], false),
+            (SuccessPolicy::AtLeast(0), &[0, 0], true),
+            (SuccessPolicy::AtLeast(1), &[0, 2], true),
+            (SuccessPolicy::AtLeast(2), &[0, 2], false),
+            (SuccessPolicy::AtLeast(2), &[usize::MAX, 1, 0], true),
+            (SuccessPolicy::AtLeast(3), &[usize::MAX, 1], false),
+            (SuccessPolicy::PeersPerTx(2), &[2, 3], true),
+            (SuccessPolicy::PeersPerTx(2), &[2, 1], false),
+            (SuccessPolicy::PeersPerTx(2), &[usize::MAX, 2], true),
+            (SuccessPolicy::PeersPerTx(usize::MAX), &[usize::MAX], true),
+            (
+                SuccessPolicy::PeersPerTx(usize::MAX),
+                &[usize::MAX, 100],
+                false,
+            ),
+        ];
+        for (policy, acks, met) in cases {
+            assert_eq!(
+                policy.is_met(acks.iter().copied()),
+                *met,
+                "{policy:?} with acks {acks:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! Detection of a full node running on this machine or on the LAN gateway.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+use crate::{net, redact, LocalNodeProbe, Network};
+
+/// How long to wait for each candidate to accept a connection. Anything local answers at once.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// Looks for an open P2P port of `network` on the hosts selected by `probe` and returns the first
+/// one found.
+pub fn detect(probe: LocalNodeProbe, network: Network) -> Option<net::Service> {
+    let hosts: Vec<IpAddr> = match probe {
+        LocalNodeProbe::Off => return None,
+        LocalNodeProbe::Localhost => vec![Ipv4Addr::LOCALHOST.into()],
+        LocalNodeProbe::LocalhostAndGateway => std::iter::once(Ipv4Addr::LOCALHOST.into())
+            .chain(gateway().map(IpAddr::from))
+            .collect(),
+    };
+    let port = match network {
+        Network::Mainnet => 8333,
+        Network::Testnet => 18333,
+        Network::Signet => 38333,
+        Network::Regtest => 18444,
+    };
+
+    hosts
+        .into_iter()
+        .map(|host| SocketAddr::new(host, port))
+        .find_map(
+            |addr| match TcpStream::connect_timeout(&addr, PROBE_TIMEOUT) {
+                Ok(_) => {
+                    log::info!("found a local node @ {}", redact::r(addr));
+                    Some(addr.into())
+                }
+                Err(err) => {
+                    log::debug!("no local node @ {}: {err}", redact::r(addr));
+                    None
+                }
+            },
+        )
+}
+
+/// The default IPv4 gateway, read from the kernel routing table.
+#[cfg(target_os = "linux")]
+fn gateway() -> Option<Ipv4Addr> {
+    let routes = std::fs::read_to_string("/proc/net/route").ok()?;
+    routes.lines().skip(1).find_map(|line| {
+        let mut fields = line.split_whitespace().skip(1);
+        let (destination, gateway) = (fields.next()?, fields.next()?);
+        if destination != "00000000" {
+            return None;
+        }
+        // the address is printed as a host order integer of the network order bytes
+        let gateway = u32::from_str_radix(gateway, 16).ok()?;
+        Some(Ipv4Addr::from(gateway.to_ne_bytes()))
+    })
+}
+
+/// The default IPv4 gateway. Only known on Linux.
+#[cfg(not(target_os = "linux"))]
+fn gateway() -> Option<Ipv4Addr> {
+    None
+}
@@ -0,0 +1,130 @@
+//! In-run peer misbehavior scoring.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::net::Service;
+use crate::redact;
+
+/// The score at which a peer is disconnected and banned for the rest of the run.
+const BAN_THRESHOLD: u32 = 100;
+
+/// How many messages a peer may send within a second before it is considered to be flooding.
+pub(crate) const MAX_MESSAGES_PER_SEC: u32 = 200;
+
+/// Kinds of peer misbehavior.
+#[derive(Debug, Clone, Copy)]
+pub enum Misbehavior {
+    /// The peer sent data that could not be decoded.
+    Malformed,
+    /// The peer violated the handshake protocol.
+    HandshakeViolation,
+    /// The peer pushed a message that we never asked for.
+    Unsolicited,
+    /// The peer sent messages faster than we are willing to process them.
+    Flood,
+    /// The peer is on a different network.
+    WrongNetwork,
+}
+
+impl Misbehavior {
+    /// How much a single occurrence contributes to the peer's score.
+    fn penalty(self) -> u32 {
+        match self {
+            Misbehavior::Malformed => BAN_THRESHOLD,
+            Misbehavior::HandshakeViolation => BAN_THRESHOLD,
+            Misbehavior::Unsolicited => 10,
+            Misbehavior::Flood => 50,
+            Misbehavior::WrongNetwork => BAN_THRESHOLD,
+        }
+    }
+}
+
+/// Keeps the misbehavior scores of peers, keyed by address, for the duration of a run.
+#[derive(Debug, Default)]
+pub struct Scoreboard {
+    /// Accumulated scores of peers that have not been banned (yet).
+    scores: HashMap<Service, u32>,
+    /// Peers that crossed the ban threshold.
+    banned: HashSet<Service>,
+}
+
+impl Scoreboard {
+    /// Records misbehavior by a peer. Returns `true` if the peer is now banned and should be
+    /// disconnected.
+    pub fn record(&mut self, peer: Service, misbehavior: Misbehavior) -> bool {
+        if self.banned.contains(&peer) {
+            return true;
+        }
+
+        let score = self.scores.entry(peer).or_default();
+        *score = score.saturating_add(misbehavior.penalty());
+
+        if *score >= BAN_THRESHOLD {
+            log::warn!("banned: peer @ {}: {misbehavior:?}", redact::r(peer));
+            self.scores.remove(&peer);
+            self.banned.insert(peer);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether the peer has been banned.
+    pub fn is_banned(&self, peer: &Service) -> bool {
+        self.banned.contains(peer)
+    }
+}
+
+/// Counts the messages received from a single peer in one second windows.
+#[derive(Debug)]
+pub struct RateLimit {
+    /// When the current window started.
+    window_start: Instant,
+    /// How many messages were received in the current window.
+    count: u32,
+}
+
+impl RateLimit {
+    /// Starts counting with a window that begins at `now`.
+    pub fn new(now: Instant) -> Self {
+        Self {
+            window_start: now,
+            count: 0,
+        }
+    }
+
+    /// Counts a message received at `now`. Returns `true` the first time the peer exceeds its
+    /// allowance within the current window.
+    pub fn hit(&mut self, now: Instant) -> bool {
+        if now.saturating_duration_since(self.window_start) >= Duration::from_secs(1) {
+            self.window_start = now;
+            self.count = 0;
+        }
+
+        self.count += 1;
+        self.count == MAX_MESSAGES_PER_SEC + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limit_trips_once_per_window() {
+        let start = Instant::now();
+        let mut rate_limit = RateLimit::new(start);
+        for _ in 0..MAX_MESSAGES_PER_SEC {
+            assert!(!rate_limit.hit(start));
+        }
+        assert!(rate_limit.hit(start + Duration::from_millis(999)));
+        assert!(!rate_limit.hit(start + Duration::from_millis(999)));
+
+        let next = start + Duration::from_secs(1);
+        for _ in 0..MAX_MESSAGES_PER_SEC {
+            assert!(!rate_limit.hit(next));
+        }
+        assert!(rate_limit.hit(next));
+    }
+}
@@ -7,8 +7,7 @@ use std::{
 
 /// Supported network.
 #[derive(Debug, Clone, Copy)]
-#[allow(unused)]
-pub enum Network {
+pub(crate) enum Network {
     /// IPv4.
     Ipv4,
     /// IPv6.
@@ -17,14 +16,16 @@ pub enum Network {
     TorV3,
 }
 
-/// Address variant.
+/// Address variant. Non-exhaustive because the onion variant depends on the `onion` feature.
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[non_exhaustive]
 pub enum Address {
     /// IPv4.
     Ipv4(Ipv4Addr),
     /// IPv6.
     Ipv6(Ipv6Addr),
     /// Onion V3.
+    #[cfg(feature = "onion")]
     TorV3([u8; 32]),
 }
 
@@ -33,24 +34,81 @@ impl std::fmt::Display for Address {
         match self {
             Address::Ipv4(ip) => write!(f, "{}", ip),
             Address::Ipv6(ip) => write!(f, "{}", ip),
+            #[cfg(feature = "onion")]
             Address::TorV3(pk) => write!(f, "{}", tor::v3_pubkey_to_domain(pk)),
         }
     }
 }
 
+/// A group of addresses that are likely to be under the control of a single operator: the /16
+/// for IPv4, the /32 for IPv6 and the address itself for onion services.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub(crate) enum NetGroup {
+    Ipv4([u8; 2]),
+    Ipv6([u8; 4]),
+    #[cfg(feature = "onion")]
+    TorV3([u8; 32]),
+}
+
 /// The combination of `Address` and port describing a peer/node/service on the network.
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct Service(Address, u16);
 
 impl Service {
+    /// Creates a new service from an address and a port.
+    pub fn new(address: Address, port: u16) -> Self {
+        Self(address, port)
+    }
+
+    /// Returns the address of the service.
+    pub fn address(&self) -> Address {
+        self.0
+    }
+
+    /// Returns the port of the service.
+    pub fn port(&self) -> u16 {
+        self.1
+    }
+
+    /// The network group the service belongs to.
+    pub(crate) fn netgroup(&self) -> NetGroup {
+        let ipv4_group = |ip: Ipv4Addr| {
+            let [a, b, _, _] = ip.octets();
+            NetGroup::Ipv4([a, b])
+        };
+
+        match self.0 {
+            Address::Ipv4(ip) => ipv4_group(ip),
+            Address::Ipv6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) => ipv4_group(ip),
+                None => {
+                    let [a, b, c, d, ..] = ip.octets();
+                    NetGroup::Ipv6([a, b, c, d])
+                }
+            },
+            #[cfg(feature = "onion")]
+            Address::TorV3(pk) => NetGroup::TorV3(pk),
+        }
+    }
+
+    /// The socket address of the service, unless it is an onion service.
+    pub(crate) fn socket_addr(&self) -> Option<SocketAddr> {
+        match self.0 {
+            Address::Ipv4(ip) => Some((ip, self.1).into()),
+            Address::Ipv6(ip) => Some((ip, self.1).into()),
+            #[cfg(feature = "onion")]
+            Address::TorV3(_) => None,
+        }
+    }
+
     /// Whether the service is on a particular network.
-    pub fn on_network(&self, network: Network) -> bool {
-        matches!(
-            (self.0, network),
-            (Address::Ipv4(_), Network::Ipv4)
-                | (Address::Ipv6(_), Network::Ipv6)
-                | (Address::TorV3(_), Network::TorV3)
-        )
+    pub(crate) fn on_network(&self, network: Network) -> bool {
+        match (self.0, network) {
+            (Address::Ipv4(_), Network::Ipv4) | (Address::Ipv6(_), Network::Ipv6) => true,
+            #[cfg(feature = "onion")]
+            (Address::TorV3(_), Network::TorV3) => true,
+            _ => false,
+        }
     }
 }
 
@@ -68,15 +126,18 @@ impl FromStr for Service {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if let Ok(socket) = s.parse::<SocketAddr>() {
-            Ok(socket.into())
-        } else {
+            return Ok(socket.into());
+        }
+
+        #[cfg(feature = "onion")]
+        {
             let (domain, port) = s.trim().rsplit_once(':').ok_or(InvalidConnectTarget)?;
             if let Some((pk, port)) = tor::v3_domain_to_pk(domain).zip(port.parse().ok()) {
-                Ok(Service(Address::TorV3(pk), port))
-            } else {
-                Err(InvalidConnectTarget)
+                return Ok(Service(Address::TorV3(pk), port));
             }
         }
+
+        Err(InvalidConnectTarget)
     }
 }
 
@@ -87,6 +148,7 @@ impl peerlink::connector::IntoTarget for Service {
         match addr {
             Address::Ipv4(ip) => Some(Target::Socket((ip, port).into())),
             Address::Ipv6(ip) => Some(Target::Socket((ip, port).into())),
+            #[cfg(feature = "onion")]
             Address::TorV3(pk) => Some(Target::Domain(tor::v3_pubkey_to_domain(&pk), port)),
         }
     }
@@ -102,6 +164,14 @@ impl std::fmt::Display for Service {
 #[derive(Debug)]
 pub struct InvalidConnectTarget;
 
+impl std::error::Error for InvalidConnectTarget {}
+
+impl std::fmt::Display for InvalidConnectTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Not a valid socket address or onion service")
+    }
+}
+
 /// The network type is not supported by the application.
 #[derive(Debug)]
 pub struct UnsupportedNetworkError;
@@ -124,12 +194,14 @@ impl TryFrom<&bitcoin::p2p::address::AddrV2Message> for Service {
         match value.addr {
             bitcoin::p2p::address::AddrV2::Ipv4(ip) => Ok(Self(Address::Ipv4(ip), value.port)),
             bitcoin::p2p::address::AddrV2::Ipv6(ip) => Ok(Self(Address::Ipv6(ip), value.port)),
+            #[cfg(feature = "onion")]
             bitcoin::p2p::address::AddrV2::TorV3(pk) => Ok(Self(Address::TorV3(pk), value.port)),
             _ => Err(UnsupportedNetworkError),
         }
     }
 }
 
+#[cfg(feature = "onion")]
 mod tor {
     const V3_VERSION: u8 = 0x03;
     const TOR_V3_ADDR_LEN: usize = 62;
@@ -208,3 +280,56 @@ mod tor {
         assert_eq!(v3_domain_to_pk(domain), Some(pk.to_owned()));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn netgroup(service: &str) -> NetGroup {
+        service.parse::<Service>().unwrap().netgroup()
+    }
+
+    #[test]
+    fn ipv4_is_grouped_by_its_16() {
+        assert_eq!(netgroup("10.1.0.1:8333"), NetGroup::Ipv4([10, 1]));
+        assert_eq!(netgroup("10.1.0.1:8333"), netgroup("10.1.255.254:18333"));
+        assert_ne!(netgroup("10.1.0.1:8333"), netgroup("10.2.0.1:8333"));
+        assert_ne!(netgroup("10.1.0.1:8333"), netgroup("11.1.0.1:8333"));
+    }
+
+    #[test]
+    fn ipv6_is_grouped_by_its_32() {
+        assert_eq!(
+            netgroup("[2001:db8:1::1]:8333"),
+            NetGroup::Ipv6([0x20, 0x01, 0x0d, 0xb8])
+        );
+        assert_eq!(
+            netgroup("[2001:db8:1::1]:8333"),
+            netgroup("[2001:db8:ffff::2]:8333")
+        );
+        assert_ne!(
+            netgroup("[2001:db8::1]:8333"),
+            netgroup("[2001:db9::1]:8333")
+        );
+    }
+
+    #[test]
+    fn ipv4_mapped_ipv6_is_grouped_like_ipv4() {
+        assert_eq!(
+            netgroup("[::ffff:10.1.2.3]:8333"),
+            netgroup("10.1.0.1:8333")
+        );
+    }
+
+    #[cfg(feature = "onion")]
+    #[test]
+    fn onion_services_are_groups_of_their_own() {
+        let onion = |n: u8| format!("{}:8333", tor::v3_pubkey_to_domain(&[n; 32]));
+        assert_eq!(netgroup(&onion(1)), NetGroup::TorV3([1; 32]));
+        assert_eq!(
+            netgroup(&onion(1)),
+            netgroup(&onion(1).replace(":8333", ":9333"))
+        );
+        assert_ne!(netgroup(&onion(1)), netgroup(&onion(2)));
+    }
+}
@@ -0,0 +1,112 @@
+//! Fallback publication of raw transactions to Nostr relays, for networks where neither direct
+//! P2P connections nor Tor get through but websockets do.
+
+use std::collections::HashSet;
+use std::net::TcpStream;
+use std::time::{Duration, SystemTime};
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{Keypair, Message, Secp256k1, SecretKey};
+use tungstenite::http::Uri;
+
+use crate::{redact, NostrFallback, Txid};
+
+/// How long to wait for a relay to acknowledge an event.
+const RELAY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Publishes each transaction as an event to every relay. Returns the transactions that at least
+/// one relay accepted. Events are signed with a throwaway key, so they cannot be tied to one
+/// another or to anyone across runs.
+pub fn publish(fallback: &NostrFallback, txs: &[(Txid, &[u8])]) -> HashSet<Txid> {
+    let secp = Secp256k1::new();
+    let keypair = loop {
+        let mut secret = [0_u8; 32];
+        fastrand::fill(&mut secret);
+        if let Ok(key) = SecretKey::from_slice(&secret) {
+            break Keypair::from_secret_key(&secp, &key);
+        }
+    };
+    let pubkey = hex::encode(keypair.x_only_public_key().0.serialize());
+    let created_at = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    let events: Vec<_> = txs
+        .iter()
+        .map(|(txid, tx)| {
+            let content = hex::encode(tx);
+            let commitment = format!(
+                "[0,\"{pubkey}\",{created_at},{},[],\"{content}\"]",
+                fallback.kind
+            );
+            let id = sha256::Hash::hash(commitment.as_bytes()).to_byte_array();
+            let sig = secp.sign_schnorr_no_aux_rand(&Message::from_digest(id), &keypair);
+            let id = hex::encode(id);
+            let event = format!(
+                "[\"EVENT\",{{\"id\":\"{id}\",\"pubkey\":\"{pubkey}\",\"created_at\":{created_at},\
+                 \"kind\":{},\"tags\":[],\"content\":\"{content}\",\"sig\":\"{sig}\"}}]",
+                fallback.kind
+            );
+            (*txid, id, event)
+        })
+        .collect();
+
+    let mut accepted = HashSet::new();
+    for relay in &fallback.relays {
+        match publish_to(relay, &events) {
+            Ok(ok) => accepted.extend(ok),
+            Err(err) => log::warn!("nostr: relay {relay} failed: {err}"),
+        }
+    }
+
+    accepted
+}
+
+/// Sends the events to a single relay and collects the ones it accepted.
+fn publish_to(
+    relay: &str,
+    events: &[(Txid, String, String)],
+) -> Result<HashSet<Txid>, Box<dyn std::error::Error>> {
+    let uri: Uri = relay.parse()?;
+    let host = uri.host().ok_or("relay URL without a host")?;
+    let port = match (uri.port_u16(), uri.scheme_str()) {
+        (Some(port), _) => port,
+        (None, Some("ws")) => 80,
+        _ => 443,
+    };
+
+    let stream = TcpStream::connect((host, port))?;
+    stream.set_read_timeout(Some(RELAY_TIMEOUT))?;
+    let (mut socket, _) = tungstenite::client_tls(relay, stream)?;
+
+    for (_, _, event) in events {
+        socket.send(tungstenite::Message::text(event))?;
+    }
+
+    let mut accepted = HashSet::new();
+    let mut pending = events.len();
+    while pending > 0 {
+        let text = match socket.read()? {
+            tungstenite::Message::Text(text) => text,
+            tungstenite::Message::Close(_) => break,
+            _ => continue,
+        };
+
+        let compact: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+        for (txid, id, _) in events {
+            if compact.starts_with(&format!("[\"OK\",\"{id}\",")) {
+                pending -= 1;
+                if compact.starts_with(&format!("[\"OK\",\"{id}\",true")) {
+                    log::info!("nostr: relay {relay} accepted {}", redact::r(txid));
+                    accepted.insert(*txid);
+                } else {
+                    log::warn!("nostr: relay {relay} refused {}: {text}", redact::r(txid));
+                }
+            }
+        }
+    }
+
+    let _ = socket.close(None);
+    Ok(accepted)
+}
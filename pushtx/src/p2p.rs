@@ -1,14 +1,21 @@
 mod client;
 mod protocol;
+mod proxy;
+mod shared;
+mod throttle;
 
 use std::io;
 use std::net::SocketAddr;
-use std::thread::JoinHandle;
 
 use bitcoin::p2p::message::RawNetworkMessage;
 
 use crate::net;
 
+pub use client::Client;
+pub use protocol::{Inbound, SharedTx};
+pub use proxy::ProxyPool;
+pub use shared::SharedReactor;
+
 /// Provides common functionality that uniquely identifies a peer.
 pub trait Peerlike:
     Clone + Copy + Eq + PartialEq + std::fmt::Debug + std::fmt::Display + std::hash::Hash
@@ -24,14 +31,36 @@ pub trait Outbox<P: Peerlike> {
     #[allow(unused)]
     fn disconnect(&self, peer: P);
 
-    /// Queues a `Version` message for sending.
-    fn version(&self, peer: P);
+    /// Queues a `Version` message for sending to the peer, which was reached at `target`.
+    fn version(&self, peer: P, target: net::Service);
 
     /// Queues a `VerAck` message for sending.
     fn verack(&self, peer: P);
 
+    /// Queues a `SendAddrV2` message for sending.
+    fn sendaddrv2(&self, peer: P);
+
+    /// Queues a `WtxidRelay` message for sending.
+    fn wtxidrelay(&self, peer: P);
+
     /// Queues a `Tx` message for sending.
-    fn tx(&self, peer: P, tx: bitcoin::Transaction);
+    fn tx(&self, peer: P, tx: SharedTx);
+
+    /// Queues an `Inv` message for sending.
+    fn inv(&self, peer: P, inv: Vec<bitcoin::p2p::message_blockdata::Inventory>);
+
+    /// Queues a `GetData` message for sending.
+    fn getdata(&self, peer: P, inv: Vec<bitcoin::p2p::message_blockdata::Inventory>);
+
+    /// Queues an `Addr` message for sending.
+    fn addr(&self, peer: P, addrs: Vec<(u32, bitcoin::p2p::Address)>);
+
+    /// Queues an `AddrV2` message for sending.
+    fn addrv2(&self, peer: P, addrs: Vec<bitcoin::p2p::address::AddrV2Message>);
+
+    /// Queues an arbitrary message for sending.
+    #[cfg(feature = "raw-messages")]
+    fn raw(&self, peer: P, message: bitcoin::p2p::message::NetworkMessage);
 }
 
 /// Describes a type capable of receiving p2p events.
@@ -44,8 +73,13 @@ pub trait Sender {
     /// Sends all the queued commands to the delivery subsystem.
     fn send(&self) -> io::Result<()>;
 
-    /// Shuts down the client.
-    fn shutdown(self) -> JoinHandle<std::io::Result<()>>;
+    /// Whether some queued commands were held back by the last `send` and are still waiting.
+    fn has_pending(&self) -> bool {
+        false
+    }
+
+    /// Shuts down the client and waits for the delivery subsystem to let go of it.
+    fn shutdown(self) -> io::Result<()>;
 }
 
 /// Possible p2p network events.
@@ -79,7 +113,7 @@ pub enum Event<P: Peerlike> {
         /// The peer associated with the event.
         peer: P,
         /// The message received from the peer.
-        message: RawNetworkMessage,
+        message: Inbound,
     },
     /// No peer exists with the specified id. Sent when an operation was specified using a peer id
     /// that is not present.
@@ -110,11 +144,49 @@ pub enum DisconnectReason {
 }
 
 pub fn client(
-    socks_proxy: Option<SocketAddr>,
+    socks_proxy: Option<ProxyPool>,
+    network: crate::Network,
+    protocol_version: u32,
+    handshake: &crate::HandshakeConfig,
+    max_bandwidth: Option<u32>,
+    max_message_size: Option<u32>,
+    rng: fastrand::Rng,
+) -> io::Result<Client> {
+    client::client(
+        socks_proxy,
+        network,
+        protocol_version,
+        handshake,
+        max_bandwidth,
+        max_message_size,
+        rng,
+    )
+}
+
+/// Decodes a single message from the start of `buffer` the way the reactor does.
+#[cfg(feature = "bench")]
+pub fn decode(buffer: &[u8]) -> Option<(Inbound, usize)> {
+    match <protocol::Message as peerlink::Message>::decode(buffer) {
+        Ok((message, consumed)) => Some((message.into(), consumed)),
+        Err(_) => None,
+    }
+}
+
+/// Creates a client on top of a reactor that is shared with other clients.
+pub fn shared_client(
+    reactor: &SharedReactor,
     network: crate::Network,
-    ua: Option<(String, u64, u64)>,
-) -> impl Sender
-       + Receiver<peerlink::PeerId, peerlink::Event<protocol::Message, net::Service>>
-       + Outbox<peerlink::PeerId> {
-    client::client(socks_proxy, network, ua)
+    protocol_version: u32,
+    handshake: &crate::HandshakeConfig,
+    max_bandwidth: Option<u32>,
+    rng: fastrand::Rng,
+) -> Client {
+    Client::new(
+        client::Link::Shared(reactor.route()),
+        network,
+        protocol_version,
+        handshake,
+        max_bandwidth,
+        rng,
+    )
 }
@@ -1,5 +1,4 @@
 use std::cell::RefCell;
-use std::net::SocketAddr;
 use std::thread::JoinHandle;
 
 use bitcoin::p2p::message::{NetworkMessage, RawNetworkMessage};
@@ -9,13 +8,43 @@ use peerlink::PeerId;
 
 use crate::net;
 
-use super::protocol;
+use super::{protocol, shared, throttle::Throttle, ProxyPool};
 
 pub fn client(
-    socks_proxy: Option<SocketAddr>,
+    socks_proxy: Option<ProxyPool>,
     network: crate::Network,
-    ua: Option<(String, u64, u64)>,
-) -> Client {
+    protocol_version: u32,
+    handshake: &crate::HandshakeConfig,
+    max_bandwidth: Option<u32>,
+    max_message_size: Option<u32>,
+    mut rng: fastrand::Rng,
+) -> std::io::Result<Client> {
+    let (handle, join_handle) = reactor(socks_proxy, &mut rng)?;
+    // nothing was dialed yet, so the reactor has not decoded a message before the cap is set
+    let payload_cap = max_message_size
+        .map(|bytes| protocol::PayloadCap::register(join_handle.thread().id(), bytes as usize));
+    Ok(Client::new(
+        Link::Own {
+            handle,
+            join_handle,
+            payload_cap,
+        },
+        network,
+        protocol_version,
+        handshake,
+        max_bandwidth,
+        rng,
+    ))
+}
+
+/// Starts a p2p reactor, connecting through SOCKS proxies if any are provided.
+pub fn reactor(
+    socks_proxy: Option<ProxyPool>,
+    #[allow(unused_variables)] rng: &mut fastrand::Rng,
+) -> std::io::Result<(
+    peerlink::Handle<protocol::Message, net::Service>,
+    JoinHandle<std::io::Result<()>>,
+)> {
     let config = peerlink::Config {
         stream_config: peerlink::StreamConfig {
             tx_buf_min_size: 4096,
@@ -26,62 +55,116 @@ pub fn client(
     };
 
     let (handle, join_handle) = match socks_proxy {
-        Some(proxy) => {
+        #[cfg(feature = "tor")]
+        Some(pool) => {
             let (reactor, handle) = peerlink::Reactor::with_connector(
                 config,
-                peerlink::connector::Socks5Connector {
-                    proxy,
+                super::proxy::FailoverConnector {
+                    pool,
                     // random proxy credentials to get an isolated Tor circuit
-                    credentials: Some((
-                        fastrand::u32(..).to_string(),
-                        fastrand::u32(..).to_string(),
-                    )),
+                    credentials: (rng.u32(..).to_string(), rng.u32(..).to_string()),
                 },
-            )
-            .unwrap();
+            )?;
             (handle, reactor.run())
         }
+        #[cfg(not(feature = "tor"))]
+        Some(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "SOCKS proxy support is compiled out",
+            ))
+        }
         None => {
-            let (reactor, handle) = peerlink::Reactor::new(config).unwrap();
+            let (reactor, handle) = peerlink::Reactor::new(config)?;
             (handle, reactor.run())
         }
     };
 
-    let (user_agent, timestamp, start_height) = ua.unwrap_or(("/pynode:0.0.1/".to_string(), 0, 0));
+    Ok((handle, join_handle))
+}
 
-    Client {
-        peerlink: handle,
-        commands: Default::default(),
-        network: network.into(),
-        join_handle,
-        our_version: VersionMessage {
-            version: 70016,
-            services: bitcoin::p2p::ServiceFlags::NONE,
-            timestamp: timestamp as i64,
-            receiver: bitcoin::p2p::Address {
-                services: bitcoin::p2p::ServiceFlags::NONE,
-                address: [0; 8],
-                port: 0,
-            },
-            sender: bitcoin::p2p::Address {
-                services: bitcoin::p2p::ServiceFlags::NONE,
-                address: [0; 8],
-                port: 0,
-            },
-            nonce: fastrand::u64(..),
-            user_agent,
-            start_height: start_height as i32,
-            relay: true,
-        },
-    }
+/// How a client reaches its p2p reactor.
+pub enum Link {
+    /// The client owns its reactor.
+    Own {
+        handle: peerlink::Handle<protocol::Message, net::Service>,
+        join_handle: JoinHandle<std::io::Result<()>>,
+        /// Released once the reactor thread has been joined.
+        payload_cap: Option<protocol::PayloadCap>,
+    },
+    /// The client shares a reactor with other clients.
+    Shared(shared::Route),
 }
 
 pub struct Client {
-    peerlink: peerlink::Handle<protocol::Message, net::Service>,
+    link: Link,
     commands: RefCell<Vec<peerlink::Command<protocol::Message, net::Service>>>,
+    throttle: RefCell<Option<Throttle>>,
     network: Network,
-    join_handle: JoinHandle<std::io::Result<()>>,
-    our_version: VersionMessage,
+    protocol_version: u32,
+    handshake: crate::HandshakeConfig,
+    rng: RefCell<fastrand::Rng>,
+}
+
+impl Client {
+    /// Creates a client on top of a link to a p2p reactor that advertises `protocol_version` and
+    /// `handshake` in its handshakes. If `max_bandwidth` is set, outbound messages are held back
+    /// so that no more than that many bytes per second go out. Nonces and jitter are drawn from
+    /// `rng`.
+    pub fn new(
+        link: Link,
+        network: crate::Network,
+        protocol_version: u32,
+        handshake: &crate::HandshakeConfig,
+        max_bandwidth: Option<u32>,
+        rng: fastrand::Rng,
+    ) -> Self {
+        Self {
+            link,
+            commands: Default::default(),
+            throttle: RefCell::new(max_bandwidth.map(Throttle::new)),
+            network: network.into(),
+            protocol_version,
+            handshake: handshake.clone(),
+            rng: RefCell::new(rng),
+        }
+    }
+
+    /// Builds the version message for a new connection to `target`. The nonce and the timestamp
+    /// jitter are drawn anew every time.
+    fn our_version(&self, target: net::Service) -> VersionMessage {
+        let address = bitcoin::p2p::Address {
+            services: bitcoin::p2p::ServiceFlags::NONE,
+            address: [0; 8],
+            port: 0,
+        };
+        // like Core, the peer's own address is echoed unless it has no pre-BIP-155 encoding
+        let receiver = match target.socket_addr() {
+            Some(socket) => bitcoin::p2p::Address::new(&socket, bitcoin::p2p::ServiceFlags::NONE),
+            None => address.clone(),
+        };
+
+        let now = self.handshake.timestamp.unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default()
+        }) as i64;
+        let jitter = self.handshake.timestamp_jitter.as_secs() as i64;
+        let mut rng = self.rng.borrow_mut();
+
+        VersionMessage {
+            version: self.protocol_version,
+            services: bitcoin::p2p::ServiceFlags::from(self.handshake.services),
+            timestamp: now + rng.i64(-jitter..=jitter),
+            receiver,
+            sender: address,
+            nonce: rng.u64(..),
+            user_agent: self.handshake.user_agent.clone(),
+            start_height: self.handshake.start_height as i32,
+            relay: self.handshake.relay,
+        }
+    }
 }
 
 impl super::Peerlike for PeerId {}
@@ -95,30 +178,99 @@ impl super::Outbox<PeerId> for Client {
         self.queue(peerlink::Command::Disconnect(peer));
     }
 
-    fn version(&self, peer: PeerId) {
-        self.queue(self.message(peer, NetworkMessage::Version(self.our_version.clone())));
+    fn version(&self, peer: PeerId, target: net::Service) {
+        self.queue(self.message(peer, NetworkMessage::Version(self.our_version(target))));
     }
 
     fn verack(&self, peer: PeerId) {
         self.queue(self.message(peer, NetworkMessage::Verack));
     }
 
-    fn tx(&self, peer: PeerId, tx: bitcoin::Transaction) {
-        self.queue(self.message(peer, NetworkMessage::Tx(tx)))
+    fn sendaddrv2(&self, peer: PeerId) {
+        self.queue(self.message(peer, NetworkMessage::SendAddrV2));
+    }
+
+    fn wtxidrelay(&self, peer: PeerId) {
+        self.queue(self.message(peer, NetworkMessage::WtxidRelay));
+    }
+
+    fn tx(&self, peer: PeerId, tx: protocol::SharedTx) {
+        let magic = self.network.magic();
+        self.queue(peerlink::Command::Message(
+            peer,
+            protocol::Message::Tx(magic, tx),
+        ))
+    }
+
+    fn inv(&self, peer: PeerId, inv: Vec<bitcoin::p2p::message_blockdata::Inventory>) {
+        self.queue(self.message(peer, NetworkMessage::Inv(inv)))
+    }
+
+    fn getdata(&self, peer: PeerId, inv: Vec<bitcoin::p2p::message_blockdata::Inventory>) {
+        self.queue(self.message(peer, NetworkMessage::GetData(inv)))
+    }
+
+    fn addr(&self, peer: PeerId, addrs: Vec<(u32, bitcoin::p2p::Address)>) {
+        self.queue(self.message(peer, NetworkMessage::Addr(addrs)))
+    }
+
+    fn addrv2(&self, peer: PeerId, addrs: Vec<bitcoin::p2p::address::AddrV2Message>) {
+        self.queue(self.message(peer, NetworkMessage::AddrV2(addrs)))
+    }
+
+    #[cfg(feature = "raw-messages")]
+    fn raw(&self, peer: PeerId, message: NetworkMessage) {
+        self.queue(self.message(peer, message))
     }
 }
 
 impl super::Sender for Client {
     fn send(&self) -> std::io::Result<()> {
-        self.commands.borrow_mut().drain(..).try_for_each(|cmd| {
-            log::debug!(">> P2P: {:?}", cmd);
-            self.peerlink.send(cmd)
+        let mut commands = self.commands.borrow_mut();
+        // whatever the throttle holds back stays queued, in order, until the next call
+        let sendable = match self.throttle.borrow_mut().as_mut() {
+            Some(throttle) => commands
+                .iter()
+                .take_while(|cmd| throttle.admit(wire_size(cmd)))
+                .count(),
+            None => commands.len(),
+        };
+
+        commands.drain(..sendable).try_for_each(|cmd| {
+            // commands carry raw transactions and addresses
+            if !crate::redact::enabled() {
+                log::debug!(">> P2P: {:?}", cmd);
+            }
+            match &self.link {
+                Link::Own { handle, .. } => handle.send(cmd),
+                Link::Shared(route) => route.send(cmd),
+            }
         })
     }
 
-    fn shutdown(self) -> JoinHandle<std::io::Result<()>> {
-        let _ = self.peerlink.shutdown();
-        self.join_handle
+    fn has_pending(&self) -> bool {
+        !self.commands.borrow().is_empty()
+    }
+
+    fn shutdown(self) -> std::io::Result<()> {
+        match self.link {
+            Link::Own {
+                handle,
+                join_handle,
+                payload_cap,
+            } => {
+                let _ = handle.shutdown();
+                let joined = join_handle.join();
+                drop(payload_cap);
+                joined.map_err(|_| {
+                    std::io::Error::new(std::io::ErrorKind::Other, "p2p reactor thread panicked")
+                })?
+            }
+            Link::Shared(route) => {
+                route.close();
+                Ok(())
+            }
+        }
     }
 }
 
@@ -126,7 +278,10 @@ impl super::Receiver<PeerId, peerlink::Event<protocol::Message, net::Service>> f
     fn receiver(
         &self,
     ) -> &crossbeam_channel::Receiver<peerlink::Event<protocol::Message, net::Service>> {
-        self.peerlink.receiver()
+        match &self.link {
+            Link::Own { handle, .. } => handle.receiver(),
+            Link::Shared(route) => route.receiver(),
+        }
     }
 }
 
@@ -144,11 +299,21 @@ impl Client {
     ) -> peerlink::Command<protocol::Message, net::Service> {
         peerlink::Command::Message(
             peer_id,
-            protocol::Message(RawNetworkMessage::new(self.network.magic(), message)),
+            protocol::Message::Network(RawNetworkMessage::new(self.network.magic(), message)),
         )
     }
 }
 
+/// How many bytes a command puts on the wire.
+fn wire_size(cmd: &peerlink::Command<protocol::Message, net::Service>) -> usize {
+    match cmd {
+        peerlink::Command::Message(_, message) => {
+            peerlink::Message::encode(message, &mut std::io::sink())
+        }
+        _ => 0,
+    }
+}
+
 impl From<peerlink::Event<protocol::Message, net::Service>> for super::Event<PeerId> {
     fn from(value: peerlink::Event<protocol::Message, net::Service>) -> Self {
         match value {
@@ -174,14 +339,14 @@ impl From<peerlink::Event<protocol::Message, net::Service>> for super::Event<Pee
 
             peerlink::Event::Message { peer, message } => Self::Message {
                 peer,
-                message: message.0,
+                message: message.into(),
             },
 
             peerlink::Event::NoPeer(peer) => Self::NoPeer(peer),
 
             peerlink::Event::SendBufferFull { peer, message } => Self::SendBufferFull {
                 peer,
-                message: message.0,
+                message: message.into(),
             },
         }
     }
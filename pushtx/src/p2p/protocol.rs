@@ -1,36 +1,447 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread::ThreadId;
+
 use bitcoin::consensus::{encode, Encodable};
-use bitcoin::p2p::message::{NetworkMessage, RawNetworkMessage};
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::p2p::message::{CommandString, NetworkMessage, RawNetworkMessage};
+use bitcoin::p2p::Magic;
 use peerlink::DecodeError;
 
+/// The largest payload accepted for messages that we have a use for. The largest of those is a
+/// full `inv` (50,000 entries of 36 bytes each), followed by a `cmpctblock` of a large block.
+const MAX_PAYLOAD_SIZE: usize = 2 * 1024 * 1024;
+
+/// The smallest cap that can be configured. Leaves room for `version` and the other handshake
+/// messages.
+const MIN_PAYLOAD_SIZE: usize = 4 * 1024;
+
+/// Caps on payloads for the reactors that were started with one, keyed by the reactor thread
+/// that decodes their messages. An entry lives exactly as long as the [`PayloadCap`] that holds it.
+static PAYLOAD_CAPS: Mutex<Option<HashMap<ThreadId, usize>>> = Mutex::new(None);
+
+/// Commands of messages that the broadcaster acts on. Other messages are skipped without being
+/// decoded.
+const WANTED_COMMANDS: &[&str] = &[
+    "version",
+    "verack",
+    "sendaddrv2",
+    "wtxidrelay",
+    "inv",
+    "getdata",
+    "notfound",
+    "tx",
+    "reject",
+    "ping",
+    "pong",
+    "feefilter",
+    "addr",
+    "addrv2",
+    "getaddr",
+    "cmpctblock",
+];
+
+/// Commands of messages that we never ask for and that are too large to be sent unsolicited by
+/// a well behaved peer. Peers that send them are disconnected.
+const UNWANTED_COMMANDS: &[&str] = &[
+    "block",
+    "headers",
+    "blocktxn",
+    "merkleblock",
+    "cfilter",
+    "cfheaders",
+    "cfcheckpt",
+];
+
+/// A cap on the payloads that one reactor accepts. The cap is in force from its registration
+/// until it is dropped, which has to happen only after the reactor thread has exited.
+#[must_use]
+pub struct PayloadCap(ThreadId);
+
+impl PayloadCap {
+    /// Lowers the largest payload that the reactor running on `reactor` accepts to `bytes`,
+    /// clamped to the range between [`MIN_PAYLOAD_SIZE`] and [`MAX_PAYLOAD_SIZE`]. Has to be
+    /// registered before the reactor is asked to connect anywhere, since a reactor without any
+    /// connections never decodes.
+    pub fn register(reactor: ThreadId, bytes: usize) -> Self {
+        let bytes = bytes.clamp(MIN_PAYLOAD_SIZE, MAX_PAYLOAD_SIZE);
+        let mut caps = PAYLOAD_CAPS.lock().unwrap_or_else(|e| e.into_inner());
+        let previous = caps.get_or_insert_with(HashMap::new).insert(reactor, bytes);
+        debug_assert!(previous.is_none(), "reactor thread registered twice");
+        Self(reactor)
+    }
+}
+
+impl Drop for PayloadCap {
+    fn drop(&mut self) {
+        let mut caps = PAYLOAD_CAPS.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(caps) = caps.as_mut() {
+            caps.remove(&self.0);
+        }
+    }
+}
+
+/// The largest payload that the reactor on the current thread accepts.
+fn payload_cap() -> usize {
+    thread_local! {
+        static CAP: usize = PAYLOAD_CAPS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .and_then(|caps| caps.get(&std::thread::current().id()).copied())
+            .unwrap_or(MAX_PAYLOAD_SIZE);
+    }
+    CAP.with(|cap| *cap)
+}
+
 #[derive(Debug)]
-pub struct Message(pub RawNetworkMessage);
+pub enum Message {
+    /// A regular network message.
+    Network(RawNetworkMessage),
+    /// A `tx` message carrying a transaction that was serialized ahead of time.
+    Tx(Magic, SharedTx),
+    /// A message received from a peer.
+    Inbound(Inbound),
+}
+
+/// A message received from a peer. Unlike `RawNetworkMessage`, it does not carry the header
+/// fields that were already checked while decoding, so building one costs nothing beyond
+/// decoding the payload.
+#[derive(Debug, Clone)]
+pub struct Inbound {
+    magic: Magic,
+    payload: NetworkMessage,
+}
+
+impl Inbound {
+    /// Wraps a decoded message.
+    pub fn new(magic: Magic, payload: NetworkMessage) -> Self {
+        Self { magic, payload }
+    }
+
+    /// Stands in for a message that was skipped without decoding its payload. Carries the command
+    /// only, as an unknown message with an empty payload.
+    fn skipped(magic: Magic, command: &[u8]) -> Result<Self, DecodeError> {
+        let command = std::str::from_utf8(command)
+            .map_err(|_| DecodeError::MalformedMessage)?
+            .trim_end_matches('\0');
+        let command = CommandString::try_from(command.to_string())
+            .map_err(|_| DecodeError::MalformedMessage)?;
+        let payload = NetworkMessage::Unknown {
+            command,
+            payload: Vec::new(),
+        };
+        Ok(Self::new(magic, payload))
+    }
+
+    /// The network magic of the message.
+    pub fn magic(&self) -> &Magic {
+        &self.magic
+    }
+
+    /// The decoded message.
+    pub fn payload(&self) -> &NetworkMessage {
+        &self.payload
+    }
+
+    /// Takes the decoded message.
+    #[cfg(feature = "bench")]
+    pub fn into_payload(self) -> NetworkMessage {
+        self.payload
+    }
+}
 
 impl peerlink::Message for Message {
     fn encode(&self, dest: &mut impl std::io::Write) -> usize {
-        self.0.consensus_encode(dest).unwrap()
+        match self {
+            Message::Network(message) => message.consensus_encode(dest).unwrap(),
+            Message::Tx(magic, tx) => {
+                let mut header = [0_u8; 24];
+                header[0..4].copy_from_slice(&magic.to_bytes());
+                header[4..6].copy_from_slice(b"tx");
+                header[16..20].copy_from_slice(&(tx.payload.len() as u32).to_le_bytes());
+                header[20..24].copy_from_slice(&tx.checksum);
+                dest.write_all(&header).unwrap();
+                dest.write_all(&tx.payload).unwrap();
+                header.len() + tx.payload.len()
+            }
+            Message::Inbound(inbound) => {
+                RawNetworkMessage::new(inbound.magic, inbound.payload.clone())
+                    .consensus_encode(dest)
+                    .unwrap()
+            }
+        }
     }
 
     fn decode(buffer: &[u8]) -> Result<(Self, usize), peerlink::DecodeError> {
+        // a magic of no network at all means the stream is garbage, not a node of another network
+        let magic = buffer.get(0..4).ok_or(DecodeError::NotEnoughData)?;
+        let magic = Magic::from_bytes(magic.try_into().expect("4 bytes"));
+        if bitcoin::Network::from_magic(magic).is_none() {
+            return Err(DecodeError::MalformedMessage);
+        }
+
+        let command = buffer.get(4..16).ok_or(DecodeError::NotEnoughData)?;
         let payload_size = buffer.get(16..20).ok_or(DecodeError::NotEnoughData)?;
 
         let payload_size =
             encode::deserialize::<u32>(payload_size).expect("4 bytes -> u32 cannot fail") as usize;
 
-        if 24 + payload_size > bitcoin::p2p::message::MAX_MSG_SIZE {
+        let max_payload_size = payload_cap();
+
+        // refused on the header alone so that the payload is never buffered
+        if UNWANTED_COMMANDS.iter().any(|c| is_command(command, c)) {
+            log::debug!(
+                "peer sent an unsolicited {}",
+                String::from_utf8_lossy(command).trim_end_matches('\0')
+            );
+            Err(DecodeError::MalformedMessage)
+        } else if payload_size > max_payload_size {
+            log::debug!(
+                "peer declared a payload of {payload_size} bytes, above {max_payload_size}"
+            );
+            Err(DecodeError::MalformedMessage)
+        } else if 24 + payload_size > bitcoin::p2p::message::MAX_MSG_SIZE {
             Err(DecodeError::MalformedMessage)
         } else if buffer.len() < 24 + payload_size {
             Err(DecodeError::NotEnoughData)
+        } else if !WANTED_COMMANDS.iter().any(|c| is_command(command, c)) {
+            // neither the checksum nor the payload matter for a message that is dropped anyway
+            Ok((
+                Self::Inbound(Inbound::skipped(magic, command)?),
+                24 + payload_size,
+            ))
+        } else if !checksum_matches(&buffer[20..24], &buffer[24..24 + payload_size]) {
+            Err(DecodeError::MalformedMessage)
         } else {
-            match encode::deserialize_partial(buffer) {
-                Ok((msg, consumed)) => Ok((Self(msg), consumed)),
+            let payload = &buffer[24..24 + payload_size];
+            let decoded = match decode_payload(command, payload) {
+                Some(decoded) => decoded,
+                // the general decoder parses the header again and copies the payload, which is
+                // fine for the messages that only arrive now and then
+                None => encode::deserialize_partial::<RawNetworkMessage>(buffer)
+                    .map(|(message, _)| message.into_payload()),
+            };
+            match decoded {
+                Ok(decoded) => Ok((
+                    Self::Inbound(Inbound::new(magic, decoded)),
+                    24 + payload_size,
+                )),
                 Err(_) => Err(DecodeError::MalformedMessage),
             }
         }
     }
 }
 
+/// Decodes the payloads of the messages that busy peers send all the time straight from the
+/// receive buffer. Returns `None` for any other command.
+fn decode_payload(command: &[u8], payload: &[u8]) -> Option<Result<NetworkMessage, encode::Error>> {
+    fn partial<T: bitcoin::consensus::Decodable>(payload: &[u8]) -> Result<T, encode::Error> {
+        // trailing bytes are ignored like the general decoder does
+        encode::deserialize_partial(payload).map(|(decoded, _)| decoded)
+    }
+
+    let decoded = if is_command(command, "inv") {
+        partial(payload).map(NetworkMessage::Inv)
+    } else if is_command(command, "getdata") {
+        partial(payload).map(NetworkMessage::GetData)
+    } else if is_command(command, "notfound") {
+        partial(payload).map(NetworkMessage::NotFound)
+    } else if is_command(command, "tx") {
+        partial(payload).map(NetworkMessage::Tx)
+    } else if is_command(command, "addr") {
+        partial(payload).map(NetworkMessage::Addr)
+    } else if is_command(command, "ping") {
+        partial(payload).map(NetworkMessage::Ping)
+    } else if is_command(command, "pong") {
+        partial(payload).map(NetworkMessage::Pong)
+    } else {
+        return None;
+    };
+
+    Some(decoded)
+}
+
+/// Whether the checksum from a message header matches the payload. Checked before decoding so
+/// that a corrupted payload is never decoded in the first place.
+fn checksum_matches(checksum: &[u8], payload: &[u8]) -> bool {
+    sha256d::Hash::hash(payload).as_byte_array()[0..4] == *checksum
+}
+
+/// Whether a raw, null padded command from a message header matches a command name.
+fn is_command(raw: &[u8], name: &str) -> bool {
+    raw.strip_prefix(name.as_bytes())
+        .is_some_and(|padding| padding.iter().all(|b| *b == 0))
+}
+
 impl From<(bitcoin::Network, NetworkMessage)> for Message {
     fn from((network, message): (bitcoin::Network, NetworkMessage)) -> Self {
-        Self(RawNetworkMessage::new(network.magic(), message))
+        Self::Network(RawNetworkMessage::new(network.magic(), message))
+    }
+}
+
+impl From<Message> for Inbound {
+    fn from(value: Message) -> Self {
+        match value {
+            Message::Inbound(inbound) => inbound,
+            message => {
+                let message = RawNetworkMessage::from(message);
+                Inbound::new(*message.magic(), message.into_payload())
+            }
+        }
+    }
+}
+
+impl From<Message> for RawNetworkMessage {
+    fn from(value: Message) -> Self {
+        match value {
+            Message::Network(message) => message,
+            Message::Inbound(inbound) => RawNetworkMessage::new(inbound.magic, inbound.payload),
+            Message::Tx(magic, tx) => RawNetworkMessage::new(
+                magic,
+                NetworkMessage::Tx(
+                    encode::deserialize(&tx.payload).expect("serialized from a valid transaction"),
+                ),
+            ),
+        }
+    }
+}
+
+/// A transaction serialized once and shared by every `tx` message that carries it, so that
+/// sending it to several peers does not copy it each time.
+#[derive(Debug, Clone)]
+pub struct SharedTx {
+    /// The consensus serialization of the transaction.
+    payload: Arc<[u8]>,
+    /// The message checksum of the payload.
+    checksum: [u8; 4],
+}
+
+impl SharedTx {
+    /// Serializes a transaction for sharing.
+    pub fn new(tx: &bitcoin::Transaction) -> Self {
+        let bytes = encode::serialize(tx);
+        let payload: Arc<[u8]> = bytes.as_slice().into();
+        #[cfg(feature = "zeroize")]
+        crate::wipe::bytes(bytes);
+
+        let mut checksum = [0_u8; 4];
+        checksum.copy_from_slice(&sha256d::Hash::hash(&payload).to_byte_array()[0..4]);
+
+        Self { payload, checksum }
+    }
+
+    /// The serialized transaction.
+    #[cfg(any(
+        feature = "nostr",
+        feature = "electrum",
+        feature = "rpc",
+        feature = "testing"
+    ))]
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Mutable access to the serialized transaction, provided that no message still holds it.
+    #[cfg(feature = "zeroize")]
+    pub fn payload_mut(&mut self) -> Option<&mut [u8]> {
+        Arc::get_mut(&mut self.payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use peerlink::Message as _;
+
+    use super::*;
+
+    /// A message header for mainnet with the given fields.
+    fn header(command: &str, payload_size: usize, checksum: [u8; 4]) -> Vec<u8> {
+        let mut header = bitcoin::Network::Bitcoin.magic().to_bytes().to_vec();
+        let mut raw_command = [0_u8; 12];
+        raw_command[..command.len()].copy_from_slice(command.as_bytes());
+        header.extend(raw_command);
+        header.extend((payload_size as u32).to_le_bytes());
+        header.extend(checksum);
+        header
+    }
+
+    #[test]
+    fn oversized_payload_is_refused_on_the_header() {
+        let buffer = header("inv", MAX_PAYLOAD_SIZE + 1, [0; 4]);
+        assert!(matches!(
+            Message::decode(&buffer),
+            Err(DecodeError::MalformedMessage)
+        ));
+
+        // a thread of its own stands in for a reactor that was started with a cap
+        let (start, started) = crossbeam_channel::bounded(0);
+        let capped = std::thread::spawn(move || {
+            started.recv().unwrap();
+            let buffer = header("inv", MIN_PAYLOAD_SIZE + 1, [0; 4]);
+            Message::decode(&buffer).map(|(_, consumed)| consumed)
+        });
+        let capped_id = capped.thread().id();
+        let cap = PayloadCap::register(capped_id, MIN_PAYLOAD_SIZE);
+        start.send(()).unwrap();
+        assert!(matches!(
+            capped.join().unwrap(),
+            Err(DecodeError::MalformedMessage)
+        ));
+
+        drop(cap);
+        assert!(!PAYLOAD_CAPS
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|caps| caps.contains_key(&capped_id)));
+    }
+
+    #[test]
+    fn unwanted_commands_are_refused_on_the_header() {
+        for command in ["block", "headers"] {
+            let buffer = header(command, 80, [0; 4]);
+            assert!(matches!(
+                Message::decode(&buffer),
+                Err(DecodeError::MalformedMessage)
+            ));
+        }
+    }
+
+    #[test]
+    fn other_commands_are_skipped_whole() {
+        let mut buffer = header("sendcmpct", 9, [0; 4]);
+        buffer.extend([0; 9]);
+        // the start of the next message
+        buffer.extend(header("verack", 0, [0; 4]));
+
+        let (message, consumed) = Message::decode(&buffer).unwrap();
+        assert_eq!(consumed, 24 + 9);
+        match Inbound::from(message).payload() {
+            NetworkMessage::Unknown { command, payload } => {
+                assert_eq!(command.as_ref(), "sendcmpct");
+                assert!(payload.is_empty());
+            }
+            payload => panic!("unexpected {payload:?}"),
+        }
+    }
+
+    #[test]
+    fn wanted_commands_need_a_valid_checksum() {
+        let mut buffer = header("ping", 8, [0; 4]);
+        buffer.extend(42_u64.to_le_bytes());
+        assert!(matches!(
+            Message::decode(&buffer),
+            Err(DecodeError::MalformedMessage)
+        ));
+
+        let checksum = sha256d::Hash::hash(&buffer[24..]).to_byte_array();
+        buffer[20..24].copy_from_slice(&checksum[0..4]);
+        let (message, consumed) = Message::decode(&buffer).unwrap();
+        assert_eq!(consumed, buffer.len());
+        assert!(matches!(
+            Inbound::from(message).payload(),
+            NetworkMessage::Ping(42)
+        ));
     }
 }
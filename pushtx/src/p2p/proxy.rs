@@ -0,0 +1,106 @@
+//! SOCKS proxies with failover.
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// SOCKS proxies in order of preference. Connections go through the current proxy until it stops
+/// accepting connections, at which point the next reachable one takes over.
+#[derive(Debug, Clone)]
+pub struct ProxyPool {
+    proxies: Arc<[SocketAddr]>,
+    current: Arc<AtomicUsize>,
+}
+
+impl ProxyPool {
+    /// Creates a pool that starts at the first reachable proxy. Returns `None` if none is.
+    pub fn new(proxies: Vec<SocketAddr>) -> Option<Self> {
+        let first = proxies.iter().position(|proxy| is_reachable(*proxy))?;
+        Some(Self {
+            proxies: proxies.into(),
+            current: Arc::new(AtomicUsize::new(first)),
+        })
+    }
+
+    /// The proxy in use.
+    pub fn current(&self) -> SocketAddr {
+        self.proxies[self.current.load(Ordering::Relaxed)]
+    }
+
+    /// Called after a connection through the proxy at `index` failed. If that proxy is no longer
+    /// reachable, the next reachable one becomes current. Returns whether a different proxy is
+    /// current now, i.e. whether the connection is worth retrying.
+    #[cfg_attr(not(feature = "tor"), allow(dead_code))]
+    fn fail_over(&self, index: usize) -> bool {
+        if self.current.load(Ordering::Relaxed) != index {
+            // another connection failed over already
+            return true;
+        }
+        if self.proxies.len() < 2 || is_reachable(self.proxies[index]) {
+            return false;
+        }
+
+        let n = self.proxies.len();
+        let next = (1..n)
+            .map(|i| (index + i) % n)
+            .find(|i| is_reachable(self.proxies[*i]));
+        match next {
+            Some(next) => {
+                log::warn!(
+                    "SOCKS proxy {} is unreachable, failing over to {}",
+                    self.proxies[index],
+                    self.proxies[next]
+                );
+                let _ = self.current.compare_exchange(
+                    index,
+                    next,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                );
+                true
+            }
+            None => {
+                log::error!("no SOCKS proxy is reachable");
+                false
+            }
+        }
+    }
+}
+
+/// Dials through the current proxy of a pool, failing over to the next one if the current proxy
+/// becomes unreachable.
+#[cfg(feature = "tor")]
+pub struct FailoverConnector {
+    pub pool: ProxyPool,
+    /// Proxy credentials, which Tor uses to isolate circuits.
+    pub credentials: (String, String),
+}
+
+#[cfg(feature = "tor")]
+impl peerlink::connector::Connector for FailoverConnector {
+    fn connect(
+        &self,
+        target: &peerlink::connector::Target,
+    ) -> std::io::Result<std::net::TcpStream> {
+        use peerlink::connector::{Connector, Socks5Connector};
+
+        // every proxy gets at most one try per connection
+        let mut tries = self.pool.proxies.len();
+        loop {
+            let index = self.pool.current.load(Ordering::Relaxed);
+            let connector = Socks5Connector {
+                proxy: self.pool.proxies[index],
+                credentials: Some(self.credentials.clone()),
+            };
+            match connector.connect(target) {
+                Err(_) if tries > 1 && self.pool.fail_over(index) => tries -= 1,
+                result => return result,
+            }
+        }
+    }
+}
+
+fn is_reachable(proxy: SocketAddr) -> bool {
+    std::net::TcpStream::connect_timeout(&proxy, Duration::from_secs(2)).is_ok()
+}
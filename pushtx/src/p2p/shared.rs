@@ -0,0 +1,207 @@
+//! A p2p reactor shared by several concurrent clients.
+//!
+//! A dispatcher thread owns the reactor handle. Clients send their commands through it, and the
+//! dispatcher routes reactor events back to the client that caused them: connection results by
+//! the target that was dialed, everything else by peer id.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use peerlink::PeerId;
+
+use crate::net;
+
+use super::{client, protocol, ProxyPool};
+
+type Command = peerlink::Command<protocol::Message, net::Service>;
+type Event = peerlink::Event<protocol::Message, net::Service>;
+
+/// Requests that clients send to the dispatcher.
+pub enum Request {
+    /// A new client wants to receive its events on the provided channel.
+    Register(u64, crossbeam_channel::Sender<Event>),
+    /// A client wants a command delivered to the reactor.
+    Command(u64, Command),
+    /// A client is done. Its peers are disconnected.
+    Close(u64),
+}
+
+/// A p2p reactor that can be shared by several clients.
+pub struct SharedReactor {
+    requests: crossbeam_channel::Sender<Request>,
+    next_client: AtomicU64,
+}
+
+impl SharedReactor {
+    /// Starts a reactor along with its dispatcher thread. Both stop once the reactor and every
+    /// route obtained from it are dropped.
+    pub fn new(socks_proxy: Option<ProxyPool>) -> std::io::Result<Self> {
+        let (handle, join_handle) = client::reactor(socks_proxy, &mut fastrand::Rng::new())?;
+        let (requests_tx, requests_rx) = crossbeam_channel::unbounded();
+
+        std::thread::spawn(move || {
+            Dispatcher::default().run(&handle, requests_rx);
+            let _ = handle.shutdown();
+            match join_handle.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => log::warn!("shared p2p reactor shut down with an error: {err}"),
+                Err(_) => log::warn!("shared p2p reactor thread panicked"),
+            }
+        });
+
+        Ok(Self {
+            requests: requests_tx,
+            next_client: AtomicU64::new(0),
+        })
+    }
+
+    /// Registers a new client with the dispatcher and returns its route.
+    pub fn route(&self) -> Route {
+        let client = self.next_client.fetch_add(1, Ordering::Relaxed);
+        let (events_tx, events_rx) = crossbeam_channel::unbounded();
+        let _ = self.requests.send(Request::Register(client, events_tx));
+
+        Route {
+            client,
+            requests: self.requests.clone(),
+            events: events_rx,
+        }
+    }
+}
+
+/// A single client's path to and from a shared reactor.
+pub struct Route {
+    client: u64,
+    requests: crossbeam_channel::Sender<Request>,
+    events: crossbeam_channel::Receiver<Event>,
+}
+
+impl Route {
+    /// Sends a command to the reactor.
+    pub fn send(&self, cmd: Command) -> std::io::Result<()> {
+        self.requests
+            .send(Request::Command(self.client, cmd))
+            .map_err(|_| {
+                std::io::Error::new(std::io::ErrorKind::BrokenPipe, "shared reactor stopped")
+            })
+    }
+
+    /// The events destined for this client.
+    pub fn receiver(&self) -> &crossbeam_channel::Receiver<Event> {
+        &self.events
+    }
+
+    /// Deregisters the client and disconnects its peers.
+    pub fn close(self) {
+        let _ = self.requests.send(Request::Close(self.client));
+    }
+}
+
+/// Routing state of the dispatcher.
+#[derive(Default)]
+struct Dispatcher {
+    /// Where the events of each registered client go.
+    clients: HashMap<u64, crossbeam_channel::Sender<Event>>,
+    /// Which clients are dialing a target, in the order they asked for it.
+    dials: HashMap<net::Service, VecDeque<u64>>,
+    /// Which client a connected peer belongs to.
+    peers: HashMap<PeerId, u64>,
+}
+
+impl Dispatcher {
+    /// Runs until every client and the reactor owner are gone or the reactor stops.
+    fn run(
+        mut self,
+        handle: &peerlink::Handle<protocol::Message, net::Service>,
+        requests: crossbeam_channel::Receiver<Request>,
+    ) {
+        loop {
+            let result = crossbeam_channel::select! {
+                recv(requests) -> request => match request {
+                    Ok(request) => self.request(handle, request),
+                    Err(_) => break,
+                },
+                recv(handle.receiver()) -> event => match event {
+                    Ok(event) => self.route(handle, event),
+                    Err(_) => break,
+                },
+            };
+
+            if let Err(err) = result {
+                log::warn!("shared p2p reactor failed: {err}");
+                break;
+            }
+        }
+    }
+
+    /// Handles a request from a client.
+    fn request(
+        &mut self,
+        handle: &peerlink::Handle<protocol::Message, net::Service>,
+        request: Request,
+    ) -> std::io::Result<()> {
+        match request {
+            Request::Register(client, events) => {
+                self.clients.insert(client, events);
+                Ok(())
+            }
+            Request::Command(client, cmd) => {
+                if let peerlink::Command::Connect(target) = &cmd {
+                    self.dials.entry(*target).or_default().push_back(client);
+                }
+                handle.send(cmd)
+            }
+            Request::Close(client) => {
+                self.clients.remove(&client);
+                self.peers
+                    .iter()
+                    .filter(|(_, owner)| **owner == client)
+                    .try_for_each(|(peer, _)| handle.send(peerlink::Command::Disconnect(*peer)))
+            }
+        }
+    }
+
+    /// Delivers a reactor event to the client it belongs to.
+    fn route(
+        &mut self,
+        handle: &peerlink::Handle<protocol::Message, net::Service>,
+        event: Event,
+    ) -> std::io::Result<()> {
+        let client = match &event {
+            peerlink::Event::ConnectedTo { target, result } => {
+                let client = self.dials.get_mut(target).and_then(VecDeque::pop_front);
+                if self.dials.get(target).is_some_and(VecDeque::is_empty) {
+                    self.dials.remove(target);
+                }
+
+                match (client, result) {
+                    (Some(client), Ok(peer)) if self.clients.contains_key(&client) => {
+                        self.peers.insert(*peer, client);
+                        Some(client)
+                    }
+                    (_, Ok(peer)) => {
+                        // nobody is waiting for this connection anymore
+                        handle.send(peerlink::Command::Disconnect(*peer))?;
+                        None
+                    }
+                    (client, Err(_)) => client,
+                }
+            }
+            peerlink::Event::ConnectedFrom { peer, .. } => {
+                // we never listen, but if this happens the connection is not ours to keep
+                handle.send(peerlink::Command::Disconnect(*peer))?;
+                None
+            }
+            peerlink::Event::Disconnected { peer, .. } => self.peers.remove(peer),
+            peerlink::Event::Message { peer, .. }
+            | peerlink::Event::NoPeer(peer)
+            | peerlink::Event::SendBufferFull { peer, .. } => self.peers.get(peer).copied(),
+        };
+
+        if let Some(events) = client.and_then(|client| self.clients.get(&client)) {
+            let _ = events.send(event);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use std::time::Instant;
+
+/// A token bucket that limits how many bytes per second go out. The bucket holds at most one
+/// second worth of bytes, and a message larger than what is left may still go out as long as the
+/// bucket is not empty, in which case the excess is paid off before anything else is admitted.
+pub struct Throttle {
+    rate: u32,
+    allowance: f64,
+    last_refill: Instant,
+}
+
+impl Throttle {
+    /// Creates a throttle that allows `rate` bytes per second. A rate of zero admits nothing.
+    pub fn new(rate: u32) -> Self {
+        Self {
+            rate,
+            allowance: rate as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Returns whether a message of the given size may go out now and charges for it if so.
+    pub fn admit(&mut self, bytes: usize) -> bool {
+        self.admit_at(bytes, Instant::now())
+    }
+
+    /// Same as `admit`, at a given point in time.
+    fn admit_at(&mut self, bytes: usize, now: Instant) -> bool {
+        let refill = now.duration_since(self.last_refill).as_secs_f64() * self.rate as f64;
+        self.allowance = (self.allowance + refill).min(self.rate as f64);
+        self.last_refill = now;
+
+        if self.allowance > 0.0 {
+            self.allowance -= bytes as f64;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn overdraft_is_paid_off_before_the_next_message() {
+        let mut throttle = Throttle::new(1000);
+        let start = throttle.last_refill;
+        assert!(throttle.admit_at(1500, start));
+        assert!(!throttle.admit_at(1, start));
+        // half a second only brings the bucket back to empty
+        assert!(!throttle.admit_at(1, start + Duration::from_millis(500)));
+        assert!(throttle.admit_at(1, start + Duration::from_millis(600)));
+    }
+
+    #[test]
+    fn refill_stops_at_one_second_worth() {
+        let mut throttle = Throttle::new(1000);
+        let start = throttle.last_refill;
+        assert!(throttle.admit_at(1000, start));
+        assert!(!throttle.admit_at(1, start));
+        let later = start + Duration::from_secs(10);
+        assert!(throttle.admit_at(1000, later));
+        assert!(!throttle.admit_at(1, later));
+    }
+}
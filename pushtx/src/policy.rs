@@ -0,0 +1,175 @@
+//! Checks of transactions against common relay policy. Nodes drop non-standard transactions
+//! silently, so catching the usual suspects up front explains broadcasts that never show up.
+
+use bitcoin::consensus::encode;
+use bitcoin::{Script, TxOut};
+
+use crate::PolicyViolation;
+
+/// The largest standard `OP_RETURN` output script (`-datacarriersize`), as enforced by Bitcoin
+/// Core before v30 and by other implementations to this day.
+const MAX_OP_RETURN_RELAY: usize = 83;
+
+/// The fee rate (sat/kvB) that outputs must be worth spending at in order not to be dust.
+const DUST_RELAY_FEE: u64 = 3_000;
+
+/// Returns the outputs of a transaction that violate relay policy, along with their index.
+pub fn check_outputs(tx: &bitcoin::Transaction) -> Vec<(u32, PolicyViolation)> {
+    tx.output
+        .iter()
+        .enumerate()
+        .filter_map(|(vout, output)| check_output(output).map(|v| (vout as u32, v)))
+        .collect()
+}
+
+fn check_output(output: &TxOut) -> Option<PolicyViolation> {
+    let script = &output.script_pubkey;
+
+    if script.is_op_return() {
+        return (script.len() > MAX_OP_RETURN_RELAY).then_some(PolicyViolation::OversizedOpReturn);
+    }
+
+    if is_bare_multisig(script) {
+        return Some(PolicyViolation::BareMultisig);
+    }
+
+    if script
+        .witness_version()
+        .is_some_and(|version| version.to_num() > 1)
+    {
+        return Some(PolicyViolation::UnknownWitnessVersion);
+    }
+
+    (output.value.to_sat() < dust_threshold(output)).then_some(PolicyViolation::Dust)
+}
+
+/// Whether the script is a bare `m-of-n` multisig, which nodes no longer relay by default.
+fn is_bare_multisig(script: &Script) -> bool {
+    use bitcoin::opcodes::all::{OP_CHECKMULTISIG, OP_PUSHNUM_1, OP_PUSHNUM_16};
+
+    let bytes = script.as_bytes();
+    match (bytes.first(), bytes.last()) {
+        (Some(first), Some(last)) => {
+            (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(first)
+                && *last == OP_CHECKMULTISIG.to_u8()
+        }
+        _ => false,
+    }
+}
+
+/// The smallest value an output can have without being dust, mirroring Bitcoin Core's
+/// `GetDustThreshold`: the cost of creating and later spending the output at the dust relay fee.
+fn dust_threshold(output: &TxOut) -> u64 {
+    let spend_size = if output.script_pubkey.is_witness_program() {
+        // outpoint, empty script sig, sequence and a discounted witness stack
+        32 + 4 + 1 + 107 / 4 + 4
+    } else {
+        32 + 4 + 1 + 107 + 4
+    };
+
+    (encode::serialize(output).len() as u64 + spend_size) * DUST_RELAY_FEE / 1000
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Amount, ScriptBuf};
+
+    use super::*;
+
+    fn output(sats: u64, script_pubkey: ScriptBuf) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(sats),
+            script_pubkey,
+        }
+    }
+
+    fn p2pkh() -> ScriptBuf {
+        ScriptBuf::new_p2pkh(&bitcoin::PubkeyHash::all_zeros())
+    }
+
+    fn p2wpkh() -> ScriptBuf {
+        ScriptBuf::new_v0_p2wpkh(&bitcoin::WPubkeyHash::all_zeros())
+    }
+
+    /// An `OP_RETURN` script of `len` bytes in all: the opcode, an `OP_PUSHDATA1` push and data.
+    fn op_return(len: usize) -> ScriptBuf {
+        let data = len - 3;
+        let mut bytes = vec![0x6a, 0x4c, data as u8];
+        bytes.resize(len, 0);
+        ScriptBuf::from_bytes(bytes)
+    }
+
+    /// A witness program of the given version with 32 bytes of zeros.
+    fn witness_program(version: u8) -> ScriptBuf {
+        let mut bytes = vec![0x50 + version, 0x20];
+        bytes.resize(34, 0);
+        ScriptBuf::from_bytes(bytes)
+    }
+
+    /// `OP_1 <pubkey> OP_1 OP_CHECKMULTISIG`
+    fn bare_multisig_1_of_1() -> ScriptBuf {
+        let mut bytes = vec![0x51, 0x21, 0x02];
+        bytes.resize(35, 0x11);
+        bytes.extend([0x51, 0xae]);
+        ScriptBuf::from_bytes(bytes)
+    }
+
+    #[test]
+    fn dust_threshold_matches_bitcoin_core() {
+        assert_eq!(dust_threshold(&output(0, p2pkh())), 546);
+        assert_eq!(dust_threshold(&output(0, p2wpkh())), 294);
+    }
+
+    #[test]
+    fn is_bare_multisig_spots_only_multisig() {
+        let cases = [
+            ("1-of-1 multisig", bare_multisig_1_of_1(), true),
+            ("p2pkh", p2pkh(), false),
+            ("p2wpkh", p2wpkh(), false),
+            ("taproot", witness_program(1), false),
+            ("empty", ScriptBuf::new(), false),
+        ];
+        for (name, script, expected) in cases {
+            assert_eq!(is_bare_multisig(&script), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn check_output_flags_non_standard_outputs() {
+        let cases = [
+            ("p2pkh at the dust threshold", output(546, p2pkh()), None),
+            (
+                "p2pkh below the dust threshold",
+                output(545, p2pkh()),
+                Some(PolicyViolation::Dust),
+            ),
+            ("p2wpkh at the dust threshold", output(294, p2wpkh()), None),
+            (
+                "p2wpkh below the dust threshold",
+                output(293, p2wpkh()),
+                Some(PolicyViolation::Dust),
+            ),
+            ("83-byte OP_RETURN", output(0, op_return(83)), None),
+            (
+                "84-byte OP_RETURN",
+                output(0, op_return(84)),
+                Some(PolicyViolation::OversizedOpReturn),
+            ),
+            (
+                "1-of-1 bare multisig",
+                output(10_000, bare_multisig_1_of_1()),
+                Some(PolicyViolation::BareMultisig),
+            ),
+            ("taproot", output(10_000, witness_program(1)), None),
+            (
+                "v2 witness program",
+                output(10_000, witness_program(2)),
+                Some(PolicyViolation::UnknownWitnessVersion),
+            ),
+        ];
+        for (name, output, expected) in cases {
+            assert_eq!(check_output(&output), expected, "{name}");
+        }
+    }
+}
@@ -0,0 +1,47 @@
+//! Redaction of the values that identify transactions and peers in log output.
+
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Random per process, so that the hashes cannot be looked up across logs of different runs.
+static SALT: OnceLock<[u8; 16]> = OnceLock::new();
+
+pub fn set(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Wraps a txid, address or peer for logging. Displays as is unless redaction is enabled.
+pub fn r<T: Display>(value: T) -> Redacted<T> {
+    Redacted(value)
+}
+
+pub struct Redacted<T>(T);
+
+impl<T: Display> Display for Redacted<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if ENABLED.load(Ordering::Relaxed) {
+            let salt = SALT.get_or_init(|| {
+                let mut salt = [0; 16];
+                fastrand::fill(&mut salt);
+                salt
+            });
+            let mut engine = sha256::Hash::engine();
+            engine.input(salt);
+            engine.input(self.0.to_string().as_bytes());
+            let hash = sha256::Hash::from_engine(engine).to_byte_array();
+            write!(f, "#{}", hex::encode(&hash[..4]))
+        } else {
+            self.0.fmt(f)
+        }
+    }
+}
+
+/// Whether redaction is enabled, for log output that cannot be redacted value by value.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
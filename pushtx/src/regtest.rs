@@ -0,0 +1,167 @@
+//! An end-to-end harness around a local regtest `bitcoind`: broadcast through pushtx, then check
+//! through RPC that the node really accepted the transactions into its mempool.
+//!
+//! `Regtest::spawn` starts a throwaway node from the binary in `BITCOIND_EXE` (or on the `PATH`),
+//! while `Regtest::attach` uses one that is already running.
+
+use std::time::{Duration, Instant};
+
+use bitcoind::bitcoincore_rpc::{self, Auth, RpcApi};
+
+use crate::{FindPeerStrategy, Info, Opts, Report, Service, TorMode, Transaction, Txid};
+
+/// A regtest node to broadcast to.
+pub struct Regtest {
+    /// The node, if it was spawned by the harness. It is shut down when dropped.
+    node: Option<bitcoind::BitcoinD>,
+    rpc: bitcoincore_rpc::Client,
+    peer: Service,
+}
+
+impl Regtest {
+    /// Spawns a fresh regtest node with a wallet and P2P enabled.
+    pub fn spawn() -> Result<Self, HarnessError> {
+        let exe = bitcoind::exe_path().map_err(|err| HarnessError::Bitcoind(err.to_string()))?;
+
+        let mut conf = bitcoind::Conf::default();
+        conf.p2p = bitcoind::P2P::Yes;
+        let node = bitcoind::BitcoinD::with_conf(exe, &conf)
+            .map_err(|err| HarnessError::Bitcoind(err.to_string()))?;
+
+        let peer = node
+            .params
+            .p2p_socket
+            .ok_or_else(|| HarnessError::Bitcoind("node has no P2P socket".to_string()))?;
+        let rpc = bitcoincore_rpc::Client::new(
+            &node.rpc_url_with_wallet("default"),
+            Auth::CookieFile(node.params.cookie_file.clone()),
+        )?;
+
+        Ok(Self {
+            node: Some(node),
+            rpc,
+            peer: std::net::SocketAddr::V4(peer).into(),
+        })
+    }
+
+    /// Attaches to a running regtest node. The RPC URL should point at a loaded wallet if
+    /// `funded_tx` is going to be used.
+    pub fn attach(rpc_url: &str, auth: Auth, peer: Service) -> Result<Self, HarnessError> {
+        Ok(Self {
+            node: None,
+            rpc: bitcoincore_rpc::Client::new(rpc_url, auth)?,
+            peer,
+        })
+    }
+
+    /// The node's RPC client.
+    pub fn rpc(&self) -> &bitcoincore_rpc::Client {
+        &self.rpc
+    }
+
+    /// The node's P2P address.
+    pub fn peer(&self) -> Service {
+        self.peer
+    }
+
+    /// Whether the harness spawned the node itself.
+    pub fn is_spawned(&self) -> bool {
+        self.node.is_some()
+    }
+
+    /// Creates a signed transaction that the node has not seen yet, mining coins to spend first
+    /// if the wallet has none.
+    pub fn funded_tx(&self) -> Result<Transaction, HarnessError> {
+        let address = self.rpc.get_new_address(None, None)?.assume_checked();
+        if self.rpc.get_balance(None, None)? < bitcoin::Amount::ONE_BTC {
+            self.rpc.generate_to_address(101, &address)?;
+        }
+
+        let unfunded = bitcoin::Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![bitcoin::TxOut {
+                value: bitcoin::Amount::from_sat(100_000),
+                script_pubkey: address.script_pubkey(),
+            }],
+        };
+
+        let funded = self.rpc.fund_raw_transaction(&unfunded, None, None)?;
+        let signed =
+            self.rpc
+                .sign_raw_transaction_with_wallet(funded.hex.as_slice(), None, None)?;
+
+        Transaction::from_bytes(&signed.hex)
+            .map_err(|err| HarnessError::Bitcoind(format!("node signed a bad tx: {err}")))
+    }
+
+    /// Broadcasts transactions to the node and waits for the outcome. The network, Tor mode and
+    /// peer strategy in `opts` are overridden to point at the node.
+    pub fn broadcast(&self, txs: Vec<Transaction>, opts: Opts) -> Result<Report, HarnessError> {
+        let opts = Opts {
+            network: crate::Network::Regtest,
+            use_tor: TorMode::No,
+            find_peer_strategy: FindPeerStrategy::Custom(vec![self.peer]),
+            ..opts
+        };
+
+        let receiver = crate::broadcast(txs, opts);
+        loop {
+            match receiver.recv() {
+                Ok(Info::Done(result)) => break result.map_err(HarnessError::Broadcast),
+                Ok(_) => {}
+                Err(_) => {
+                    break Err(HarnessError::Broadcast(crate::Error::Internal(
+                        "broadcast thread disconnected".to_string(),
+                    )))
+                }
+            }
+        }
+    }
+
+    /// Waits until a transaction shows up in the node's mempool.
+    pub fn wait_for_mempool(&self, txid: Txid, timeout: Duration) -> Result<(), HarnessError> {
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            if self.rpc.get_mempool_entry(&txid.into()).is_ok() {
+                return Ok(());
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        Err(HarnessError::NotInMempool(txid))
+    }
+}
+
+/// Failures of the regtest harness.
+#[derive(Debug)]
+pub enum HarnessError {
+    /// The node could not be found, started or used.
+    Bitcoind(String),
+    /// An RPC call failed.
+    Rpc(bitcoincore_rpc::Error),
+    /// The broadcast itself failed.
+    Broadcast(crate::Error),
+    /// The transaction never made it into the node's mempool.
+    NotInMempool(Txid),
+}
+
+impl std::error::Error for HarnessError {}
+
+impl std::fmt::Display for HarnessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HarnessError::Bitcoind(reason) => write!(f, "bitcoind: {reason}"),
+            HarnessError::Rpc(err) => write!(f, "RPC error: {err}"),
+            HarnessError::Broadcast(err) => write!(f, "broadcast failed: {err}"),
+            HarnessError::NotInMempool(txid) => write!(f, "{txid} not in the node's mempool"),
+        }
+    }
+}
+
+impl From<bitcoincore_rpc::Error> for HarnessError {
+    fn from(value: bitcoincore_rpc::Error) -> Self {
+        Self::Rpc(value)
+    }
+}
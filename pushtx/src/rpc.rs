@@ -0,0 +1,71 @@
+//! Checks and fallback submission through the RPC interface of a node the user runs.
+
+use std::collections::{HashMap, HashSet};
+
+use bitcoincore_rpc::{Auth, Client, RpcApi};
+
+use crate::{redact, Error, Reject, RejectReason, RpcAuth, RpcNode, Transaction, Txid};
+
+fn client(node: &RpcNode) -> Result<Client, bitcoincore_rpc::Error> {
+    let auth = match &node.auth {
+        RpcAuth::UserPass { user, password } => Auth::UserPass(user.clone(), password.clone()),
+        RpcAuth::Cookie(path) => Auth::CookieFile(path.clone()),
+    };
+    Client::new(&node.url, auth)
+}
+
+/// Asks the node whether it would accept the batch into its mempool with `testmempoolaccept`.
+/// The first transaction it refuses fails the check.
+pub fn preflight(node: &RpcNode, txs: &[Transaction]) -> Result<(), Error> {
+    let raw: Vec<_> = txs.iter().map(|tx| &tx.0).collect();
+    let results = client(node)
+        .and_then(|client| client.test_mempool_accept(&raw))
+        .map_err(|err| Error::Rpc(err.to_string()))?;
+
+    match results.into_iter().find(|result| !result.allowed) {
+        Some(refused) => Err(Error::NodeRejected {
+            txid: Txid(refused.txid),
+            reason: refused
+                .reject_reason
+                .unwrap_or_else(|| "no reason given".to_string()),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Submits each transaction with `sendrawtransaction`. Returns the transactions that the node
+/// accepted and the reasons it gave for the rest. A node that cannot be reached refuses nothing
+/// and accepts nothing.
+pub fn submit(node: &RpcNode, txs: &[(Txid, &[u8])]) -> (HashSet<Txid>, HashMap<Txid, Reject>) {
+    let mut accepted = HashSet::new();
+    let mut refused = HashMap::new();
+
+    let client = match client(node) {
+        Ok(client) => client,
+        Err(err) => {
+            log::warn!("rpc: cannot reach the node: {err}");
+            return (accepted, refused);
+        }
+    };
+
+    for (txid, tx) in txs {
+        match client.send_raw_transaction(*tx) {
+            Ok(_) => {
+                log::info!("rpc: node accepted {}", redact::r(txid));
+                accepted.insert(*txid);
+            }
+            Err(bitcoincore_rpc::Error::JsonRpc(bitcoincore_rpc::jsonrpc::Error::Rpc(err))) => {
+                log::warn!("rpc: node refused {}: {}", redact::r(txid), err.message);
+                let reject = Reject {
+                    reason: RejectReason::Other,
+                    message: err.message,
+                    peer: None,
+                };
+                refused.insert(*txid, reject);
+            }
+            Err(err) => log::warn!("rpc: submitting {} failed: {err}", redact::r(txid)),
+        }
+    }
+
+    (accepted, refused)
+}
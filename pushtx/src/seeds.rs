@@ -1,11 +1,16 @@
-use std::net::SocketAddr;
+//! Resolution of the candidate peer pool from DNS seeds and fixed seed lists.
 
-use crate::{net::Service, Network};
+use std::collections::HashSet;
+
+use crate::geo::CountryExclusion;
+use crate::net::{self, Service};
+use crate::Network;
 
 const FIXED_MAINNET: &str = include_str!("../seeds/mainnet.txt");
 const FIXED_TESTNET: &str = include_str!("../seeds/testnet.txt");
 const FIXED_SIGNET: &str = include_str!("../seeds/signet.txt");
 
+#[cfg(feature = "dns")]
 const DNS_MAINNET: &[&str] = &[
     "dnsseed.bluematt.me.",
     "dnsseed.bitcoin.dashjr-list-of-p2p-nodes.us.",
@@ -17,6 +22,7 @@ const DNS_MAINNET: &[&str] = &[
     "seed.bitcoin.wiz.biz.",
 ];
 
+#[cfg(feature = "dns")]
 const DNS_TESTNET: &[&str] = &[
     "testnet-seed.bluematt.me",
     "testnet-seed.bitcoin.jonasschnelli.ch",
@@ -24,10 +30,246 @@ const DNS_TESTNET: &[&str] = &[
     "seed.testnet.bitcoin.sprovoost.nl",
 ];
 
+#[cfg(feature = "dns")]
 const DNS_SIGNET: &[&str] = &["seed.signet.bitcoin.sprovoost.nl"];
 
-/// Returns nodes returned by DNS seeds.
-pub fn dns(network: Network) -> Vec<Service> {
+/// The number of DNS seeded peers below which the fixed seeds are mixed in by default.
+pub(crate) const DEFAULT_FALLBACK_THRESHOLD: usize = 20;
+
+/// How long a background refresh of the DNS cache waits for the seeds.
+const REFRESH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Options that control how the candidate peer pool is resolved.
+#[derive(Debug, Clone)]
+pub struct ResolveOpts {
+    /// Whether to mix in the fixed seed list if DNS seeding yields too few peers.
+    pub fixed_fallback: bool,
+    /// DNS seeding yielding fewer peers than this counts as a failure and triggers the fixed
+    /// seed fallback. `usize::MAX` always mixes in the fixed seeds.
+    pub fallback_threshold: usize,
+    /// How long to wait for DNS seeds to answer. Seeds that have not answered by then are left
+    /// out. Unbounded if not set.
+    pub timeout: Option<std::time::Duration>,
+    /// Whether the peers will be reached through Tor. This admits IPv6 and onion peers into the
+    /// pool in addition to IPv4 ones.
+    pub via_tor: bool,
+    /// Where to keep DNS seed results between runs. Not cached if not set.
+    pub cache: Option<DnsCache>,
+    /// Peers located in any of these countries are left out of the pool.
+    pub exclude_countries: Option<CountryExclusion>,
+}
+
+impl Default for ResolveOpts {
+    fn default() -> Self {
+        Self {
+            fixed_fallback: true,
+            fallback_threshold: DEFAULT_FALLBACK_THRESHOLD,
+            timeout: None,
+            via_tor: false,
+            cache: None,
+            exclude_countries: None,
+        }
+    }
+}
+
+/// An on-disk cache of DNS seed results. Reusing them saves querying every seed on each run,
+/// which speeds up startup and keeps the queries from hinting that a broadcast is about to
+/// happen.
+#[derive(Debug, Clone)]
+pub struct DnsCache {
+    /// The cache file. Created on the first successful lookup.
+    pub path: std::path::PathBuf,
+    /// How long cached results are used for. Once half of it has passed, the seeds are queried
+    /// again in the background so that the next run finds fresh results. The refresh is best
+    /// effort: nothing waits for it, so a process that exits soon after, such as the CLI after a
+    /// quick broadcast, may leave the cache as it was.
+    pub ttl: std::time::Duration,
+}
+
+impl DnsCache {
+    /// Loads the cached nodes of a network along with their age.
+    fn load(&self, network: Network) -> Option<(std::time::Duration, Vec<Service>)> {
+        let contents = std::fs::read_to_string(&self.path).ok()?;
+        let mut lines = contents.lines();
+        let (cached_network, stored) = lines.next()?.split_once(' ')?;
+        if cached_network != format!("{network:?}") {
+            return None;
+        }
+        let stored = std::time::UNIX_EPOCH + std::time::Duration::from_secs(stored.parse().ok()?);
+        let age = stored.elapsed().unwrap_or_default();
+        let nodes = lines.filter_map(|line| line.parse().ok()).collect();
+
+        Some((age, nodes))
+    }
+
+    /// Replaces the cache with freshly resolved nodes. Empty results are not stored.
+    fn store(&self, network: Network, nodes: &[Service]) {
+        if nodes.is_empty() {
+            return;
+        }
+        let now = match std::time::UNIX_EPOCH.elapsed() {
+            Ok(now) => now.as_secs(),
+            Err(_) => {
+                log::warn!("system time is before the epoch, not writing the DNS cache");
+                return;
+            }
+        };
+        let mut contents = format!("{network:?} {now}\n");
+        for addr in nodes.iter().filter_map(Service::socket_addr) {
+            contents.push_str(&format!("{addr}\n"));
+        }
+
+        // written aside and moved into place so that a concurrent run never reads half a file,
+        // under a name of its own so that concurrent writers do not clobber each other
+        let suffix = format!("{}.{}.tmp", std::process::id(), fastrand::u32(..));
+        let tmp = self.path.with_extension(suffix);
+        let result = std::fs::write(&tmp, contents).and_then(|_| std::fs::rename(&tmp, &self.path));
+        if let Err(err) = result {
+            let _ = std::fs::remove_file(&tmp);
+            log::warn!(
+                "failed to write the DNS cache {}: {err}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// Resolves a shuffled pool of distinct candidate peers from DNS seeds, optionally falling back
+/// on the fixed seed list, and filtered down to the networks that are reachable under `opts` and
+/// the countries that are not excluded.
+///
+/// This is the same pool that a broadcast draws its peers from. It can be cached, filtered and
+/// fed back through `FindPeerStrategy::Custom`. Peers are only held against
+/// `Opts::peer_requirements` once they announce their version, which is after they were dialed.
+pub fn resolve(network: Network, opts: &ResolveOpts) -> Vec<Service> {
+    resolve_with_sources(network, opts).nodes
+}
+
+/// Same as `resolve`, but also tells how many peers came from each source.
+pub fn resolve_with_sources(network: Network, opts: &ResolveOpts) -> Resolution {
+    resolve_with_rng(network, opts, &mut fastrand::Rng::new())
+}
+
+/// Same as `resolve_with_sources`, shuffling with the given random number generator.
+pub(crate) fn resolve_with_rng(
+    network: Network,
+    opts: &ResolveOpts,
+    rng: &mut fastrand::Rng,
+) -> Resolution {
+    let reachable = |node: &Service| is_reachable(node, opts.via_tor);
+
+    let mut nodes: Vec<_> = dns_cached(network, opts.timeout, opts.cache.as_ref())
+        .into_iter()
+        .filter(reachable)
+        .collect();
+    let from_dns = nodes.len();
+    let from_fixed = if opts.fixed_fallback && from_dns < opts.fallback_threshold {
+        nodes.extend(fixed(network).filter(reachable));
+        Some(nodes.len() - from_dns)
+    } else {
+        None
+    };
+    prune(&mut nodes, opts.exclude_countries.as_ref());
+    rng.shuffle(&mut nodes);
+
+    Resolution {
+        nodes,
+        from_dns,
+        from_fixed,
+    }
+}
+
+/// Returns a shuffled pool of fixed seed peers without resolving anything through DNS. The peers
+/// come from the list that ships with the library, or from `file` if given, which has the same
+/// format: one address per line, anything after the address being ignored.
+pub fn resolve_fixed(
+    network: Network,
+    via_tor: bool,
+    file: Option<&std::path::Path>,
+) -> std::io::Result<Vec<Service>> {
+    resolve_fixed_with_rng(network, via_tor, file, &mut fastrand::Rng::new())
+}
+
+/// Same as `resolve_fixed`, shuffling with the given random number generator.
+pub(crate) fn resolve_fixed_with_rng(
+    network: Network,
+    via_tor: bool,
+    file: Option<&std::path::Path>,
+    rng: &mut fastrand::Rng,
+) -> std::io::Result<Vec<Service>> {
+    let mut nodes: Vec<_> = match file {
+        Some(file) => parse_fixed(&std::fs::read_to_string(file)?).collect(),
+        None => fixed(network).collect(),
+    };
+    nodes.retain(|node| is_reachable(node, via_tor));
+    rng.shuffle(&mut nodes);
+
+    Ok(nodes)
+}
+
+/// Drops repeated peers and the ones located in excluded countries, keeping the order of the rest.
+pub(crate) fn prune(nodes: &mut Vec<Service>, exclusion: Option<&CountryExclusion>) {
+    let mut seen = HashSet::with_capacity(nodes.len());
+    nodes.retain(|node| {
+        seen.insert(*node) && !exclusion.is_some_and(|exclusion| exclusion.excludes(node))
+    });
+}
+
+/// Whether a node can be connected to. Only IPv4 is assumed to work without Tor.
+fn is_reachable(node: &Service, via_tor: bool) -> bool {
+    let allowed_networks: &[net::Network] = if via_tor {
+        &[net::Network::Ipv4, net::Network::Ipv6, net::Network::TorV3]
+    } else {
+        &[net::Network::Ipv4]
+    };
+    allowed_networks.iter().any(|net| node.on_network(*net))
+}
+
+/// A resolved peer pool along with where its peers came from.
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    /// The shuffled pool of candidate peers.
+    pub nodes: Vec<Service>,
+    /// How many of the peers came from DNS seeds.
+    pub from_dns: usize,
+    /// How many of the peers came from the fixed seed list, if it was mixed in.
+    pub from_fixed: Option<usize>,
+}
+
+/// Returns nodes from the DNS cache while it is fresh, or from the DNS seeds otherwise.
+fn dns_cached(
+    network: Network,
+    timeout: Option<std::time::Duration>,
+    cache: Option<&DnsCache>,
+) -> Vec<Service> {
+    let Some(cache) = cache else {
+        return dns_within(network, timeout);
+    };
+
+    match cache.load(network) {
+        Some((age, nodes)) if age < cache.ttl && !nodes.is_empty() => {
+            log::info!("using {} cached DNS seed results", nodes.len());
+            if age >= cache.ttl / 2 {
+                let cache = cache.clone();
+                std::thread::spawn(move || {
+                    cache.store(network, &dns_within(network, Some(REFRESH_TIMEOUT)))
+                });
+            }
+            nodes
+        }
+        _ => {
+            let nodes = dns_within(network, timeout);
+            cache.store(network, &nodes);
+            nodes
+        }
+    }
+}
+
+/// Returns nodes returned by the DNS seeds that answered within `timeout`.
+#[cfg(feature = "dns")]
+pub(crate) fn dns_within(network: Network, timeout: Option<std::time::Duration>) -> Vec<Service> {
+    use std::net::SocketAddr;
+
     let (seeds, port): (&[_], _) = match network {
         Network::Mainnet => (DNS_MAINNET, 8333),
         Network::Testnet => (DNS_TESTNET, 18333),
@@ -35,29 +277,49 @@ pub fn dns(network: Network) -> Vec<Service> {
         Network::Signet => (DNS_SIGNET, 38333),
     };
 
-    seeds
-        .iter()
-        .map(|seed| {
-            std::thread::spawn(move || {
-                let mut addrs: Vec<Service> = Vec::with_capacity(128);
-                if let Ok(iter) = dns_lookup::getaddrinfo(Some(seed), None, None) {
-                    for addr in iter.filter_map(Result::ok) {
-                        let socket_addr: SocketAddr = (addr.sockaddr.ip(), port).into();
-                        addrs.push(socket_addr.into());
-                    }
+    let deadline = timeout.map(|timeout| std::time::Instant::now() + timeout);
+    let (addrs_tx, addrs_rx) = crossbeam_channel::bounded(seeds.len());
+    for seed in seeds {
+        let addrs_tx = addrs_tx.clone();
+        std::thread::spawn(move || {
+            let mut addrs: Vec<Service> = Vec::with_capacity(128);
+            if let Ok(iter) = dns_lookup::getaddrinfo(Some(seed), None, None) {
+                for addr in iter.filter_map(Result::ok) {
+                    let socket_addr: SocketAddr = (addr.sockaddr.ip(), port).into();
+                    addrs.push(socket_addr.into());
                 }
-                addrs
-            })
-        })
-        .filter_map(|h| h.join().ok())
-        .fold(Vec::with_capacity(1024), |mut acc, val| {
-            acc.extend(val);
-            acc
-        })
+            }
+            let _ = addrs_tx.send(addrs);
+        });
+    }
+    drop(addrs_tx);
+
+    let mut nodes = Vec::with_capacity(1024);
+    for _ in seeds {
+        let addrs = match deadline {
+            Some(deadline) => addrs_rx.recv_deadline(deadline).ok(),
+            None => addrs_rx.recv().ok(),
+        };
+        match addrs {
+            Some(addrs) => nodes.extend(addrs),
+            None => {
+                log::warn!("DNS seeding timed out, some seeds did not answer");
+                break;
+            }
+        }
+    }
+    nodes
+}
+
+/// Returns nodes returned by DNS seeds. DNS seeding is compiled out, so this is always empty.
+#[cfg(not(feature = "dns"))]
+pub(crate) fn dns_within(network: Network, _timeout: Option<std::time::Duration>) -> Vec<Service> {
+    log::warn!("DNS seeding disabled at compile time, no {network:?} peers resolved");
+    Vec::new()
 }
 
 /// Returns an iterator over hardcoded seed nodes.
-pub fn fixed(network: Network) -> impl Iterator<Item = Service> {
+pub(crate) fn fixed(network: Network) -> impl Iterator<Item = Service> {
     match network {
         Network::Mainnet => parse_fixed(FIXED_MAINNET),
         Network::Testnet => parse_fixed(FIXED_TESTNET),
@@ -67,10 +329,120 @@ pub fn fixed(network: Network) -> impl Iterator<Item = Service> {
 }
 
 /// Parses a string containing seed nodes, one per line, and returns an iterator over it.
-fn parse_fixed(s: &'static str) -> impl Iterator<Item = Service> {
+fn parse_fixed(s: &str) -> impl Iterator<Item = Service> + '_ {
     s.lines().filter_map(|line| {
         line.split_whitespace()
             .next()
             .and_then(|addr| addr.parse().ok())
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    /// A directory of its own under the system temp dir, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let name = format!("pushtx-seeds-{}-{}", std::process::id(), fastrand::u64(..));
+            let dir = std::env::temp_dir().join(name);
+            std::fs::create_dir(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn entries(&self) -> Vec<PathBuf> {
+            std::fs::read_dir(&self.0)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .collect()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn nodes() -> Vec<Service> {
+        vec![
+            "10.1.0.1:18444".parse().unwrap(),
+            "10.2.0.1:18444".parse().unwrap(),
+        ]
+    }
+
+    /// Writes a cache file for regtest as if it had been stored `age` ago.
+    fn write_aged(cache: &DnsCache, age: Duration) {
+        let stored = (UNIX_EPOCH.elapsed().unwrap() - age).as_secs();
+        let mut contents = format!("{:?} {stored}\n", Network::Regtest);
+        for node in nodes() {
+            contents.push_str(&format!("{node}\n"));
+        }
+        std::fs::write(&cache.path, contents).unwrap();
+    }
+
+    fn cache(dir: &TempDir) -> DnsCache {
+        DnsCache {
+            path: dir.0.join("dns-cache"),
+            ttl: Duration::from_secs(3600),
+        }
+    }
+
+    #[test]
+    fn stored_nodes_are_loaded_back() {
+        let dir = TempDir::new();
+        let cache = cache(&dir);
+        cache.store(Network::Regtest, &nodes());
+
+        let (age, loaded) = cache.load(Network::Regtest).unwrap();
+        assert!(age < Duration::from_secs(60));
+        assert_eq!(loaded, nodes());
+        // nothing is left behind next to the cache file
+        assert_eq!(dir.entries(), vec![cache.path.clone()]);
+        // a cache of another network is not used
+        assert!(cache.load(Network::Mainnet).is_none());
+    }
+
+    #[test]
+    fn empty_results_are_not_stored() {
+        let dir = TempDir::new();
+        let cache = cache(&dir);
+        cache.store(Network::Regtest, &[]);
+        assert!(dir.entries().is_empty());
+    }
+
+    #[test]
+    fn failed_write_leaves_no_temp_file() {
+        let dir = TempDir::new();
+        let cache = cache(&dir);
+        // a directory in place of the cache file makes the final rename fail
+        std::fs::create_dir(&cache.path).unwrap();
+        cache.store(Network::Regtest, &nodes());
+        assert_eq!(dir.entries(), vec![cache.path.clone()]);
+    }
+
+    #[test]
+    fn fresh_cache_is_used() {
+        let dir = TempDir::new();
+        let cache = cache(&dir);
+        write_aged(&cache, Duration::from_secs(60));
+        assert_eq!(dns_cached(Network::Regtest, None, Some(&cache)), nodes());
+    }
+
+    #[test]
+    fn expired_cache_is_not_used() {
+        let dir = TempDir::new();
+        let cache = cache(&dir);
+        write_aged(&cache, cache.ttl + Duration::from_secs(60));
+        // regtest has no DNS seeds, so a lookup finds nothing
+        assert!(dns_cached(Network::Regtest, None, Some(&cache)).is_empty());
+        // and the empty lookup does not replace what was there
+        let (age, _) = cache.load(Network::Regtest).unwrap();
+        assert!(age > cache.ttl);
+    }
+}
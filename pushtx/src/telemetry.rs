@@ -0,0 +1,55 @@
+//! Optional instrumentation through the `metrics` facade.
+//!
+//! Every function here is a no-op unless the `metrics` feature is enabled. When it is, the
+//! embedding application is expected to install a recorder, such as a Prometheus exporter.
+
+#![cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+
+use std::time::Duration;
+
+#[cfg(feature = "metrics")]
+fn transport(via_tor: bool) -> &'static str {
+    if via_tor {
+        "tor"
+    } else {
+        "clearnet"
+    }
+}
+
+/// A transaction was sent out to a broadcast peer.
+pub fn broadcast_attempt(via_tor: bool) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("pushtx_broadcast_attempts_total", "transport" => transport(via_tor))
+        .increment(1);
+}
+
+/// A transaction was seen on the network for the first time.
+pub fn ack(via_tor: bool) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("pushtx_acks_total", "transport" => transport(via_tor)).increment(1);
+}
+
+/// A peer rejected one of our transactions.
+pub fn reject(via_tor: bool, reason: &str) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(
+        "pushtx_rejects_total",
+        "transport" => transport(via_tor),
+        "reason" => reason.to_string()
+    )
+    .increment(1);
+}
+
+/// A connection to a peer was established after `latency`.
+pub fn peer_connected(via_tor: bool, latency: Duration) {
+    #[cfg(feature = "metrics")]
+    ::metrics::histogram!("pushtx_peer_connect_seconds", "transport" => transport(via_tor))
+        .record(latency.as_secs_f64());
+}
+
+/// A connection attempt to a peer failed.
+pub fn peer_connect_failed(via_tor: bool) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("pushtx_peer_connect_failures_total", "transport" => transport(via_tor))
+        .increment(1);
+}
@@ -0,0 +1,478 @@
+//! An in-memory stand-in for the p2p network, for testing code that drives broadcasts without
+//! network access.
+//!
+//! ```no_run
+//! use pushtx::testing::{MockNetwork, MockPeer};
+//!
+//! let tx = "6afcc7949dd500000....".parse().unwrap();
+//! let network = MockNetwork::new([
+//!     ("10.1.0.1:8333".parse().unwrap(), MockPeer::Honest),
+//!     ("10.2.0.1:8333".parse().unwrap(), MockPeer::Honest),
+//!     ("10.3.0.1:8333".parse().unwrap(), MockPeer::Unreachable),
+//! ]);
+//!
+//! let receiver = pushtx::testing::broadcast(vec![tx], pushtx::Opts::default(), network);
+//! ```
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use bitcoin::p2p::message::{CommandString, NetworkMessage};
+use bitcoin::p2p::message_blockdata::Inventory;
+use bitcoin::p2p::message_network::{Reject, RejectReason, VersionMessage};
+use bitcoin::p2p::{Address, Magic, ServiceFlags};
+
+use crate::p2p::{self, DisconnectReason, Event, SharedTx};
+use crate::{broadcast, Clock, FindPeerStrategy, Info, Opts, Service, TorMode, Transaction};
+
+/// How a mock peer behaves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockPeer {
+    /// Refuses connections.
+    Unreachable,
+    /// Accepts the connection but never completes the handshake.
+    Mute,
+    /// Completes the handshake and accepts transactions, but never relays anything.
+    Silent,
+    /// Accepts transactions, requests announced ones and announces the transactions that other
+    /// peers accepted.
+    Honest,
+    /// Rejects every transaction it receives with the given reason.
+    Rejecting(String),
+    /// Disconnects as soon as it receives a transaction.
+    Dropping,
+}
+
+/// A scripted set of peers.
+///
+/// Acks only count if they come from a different netgroup than the peer the transaction was sent
+/// to, so honest peers should be spread over different /16 ranges.
+#[derive(Debug, Clone)]
+pub struct MockNetwork {
+    peers: HashMap<Service, MockPeer>,
+}
+
+impl MockNetwork {
+    /// Creates a network out of peers and their behavior.
+    pub fn new(peers: impl IntoIterator<Item = (Service, MockPeer)>) -> Self {
+        Self {
+            peers: peers.into_iter().collect(),
+        }
+    }
+}
+
+/// Broadcasts transactions into a mock network. Otherwise the same as `pushtx::broadcast`, except
+/// that Tor is never used and peers are always drawn from the mock network.
+pub fn broadcast(
+    tx: Vec<Transaction>,
+    opts: Opts,
+    network: MockNetwork,
+) -> crossbeam_channel::Receiver<Info> {
+    let opts = Opts {
+        use_tor: TorMode::No,
+        find_peer_strategy: FindPeerStrategy::Custom(network.peers.keys().copied().collect()),
+        ..opts
+    };
+
+    let (runner, event_rx) = broadcast::Runner::new(tx, opts, None);
+    runner.run_with(move |_, opts| Ok(MockClient::new(network, opts.network)));
+    event_rx
+}
+
+/// A clock that only moves when told to, for use as `Opts::clock`.
+#[derive(Debug)]
+pub struct ManualClock {
+    instant: Instant,
+    system: SystemTime,
+    offset: Mutex<Duration>,
+}
+
+impl ManualClock {
+    /// Creates a clock frozen at the current time.
+    pub fn new() -> Self {
+        Self {
+            instant: Instant::now(),
+            system: SystemTime::now(),
+            offset: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    /// Moves the clock forward.
+    pub fn advance(&self, by: Duration) {
+        *self.offset.lock().unwrap() += by;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.instant + *self.offset.lock().unwrap()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        self.system + *self.offset.lock().unwrap()
+    }
+}
+
+/// Identifies a connection to a mock peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MockPeerId(u64);
+
+impl std::fmt::Display for MockPeerId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "mock#{}", self.0)
+    }
+}
+
+impl p2p::Peerlike for MockPeerId {}
+
+/// Commands queued by the broadcast runner.
+enum Command {
+    Connect(Service),
+    Disconnect(MockPeerId),
+    Version(MockPeerId),
+    Tx(MockPeerId, SharedTx),
+    Inv(MockPeerId, Vec<Inventory>),
+    GetData(MockPeerId, Vec<Inventory>),
+}
+
+/// Plays the part of the p2p client, answering commands on behalf of the mock peers.
+struct MockClient {
+    peers: HashMap<Service, MockPeer>,
+    magic: Magic,
+    connected: RefCell<HashMap<MockPeerId, Service>>,
+    next_id: Cell<u64>,
+    mempool: RefCell<HashMap<bitcoin::Txid, bitcoin::Transaction>>,
+    commands: RefCell<Vec<Command>>,
+    events_tx: crossbeam_channel::Sender<Event<MockPeerId>>,
+    events_rx: crossbeam_channel::Receiver<Event<MockPeerId>>,
+}
+
+impl MockClient {
+    fn new(network: MockNetwork, p2p_network: crate::Network) -> Self {
+        let (events_tx, events_rx) = crossbeam_channel::unbounded();
+        Self {
+            peers: network.peers,
+            magic: bitcoin::Network::from(p2p_network).magic(),
+            connected: Default::default(),
+            next_id: Default::default(),
+            mempool: Default::default(),
+            commands: Default::default(),
+            events_tx,
+            events_rx,
+        }
+    }
+
+    /// The behavior of a connected peer.
+    fn behavior(&self, peer: MockPeerId) -> Option<&MockPeer> {
+        let service = *self.connected.borrow().get(&peer)?;
+        self.peers.get(&service)
+    }
+
+    /// Makes a peer send us a message.
+    fn emit(&self, peer: MockPeerId, message: NetworkMessage) {
+        let message = p2p::Inbound::new(self.magic, message);
+        let _ = self.events_tx.send(Event::Message { peer, message });
+    }
+
+    /// Answers a single command.
+    fn process(&self, cmd: Command) {
+        match cmd {
+            Command::Connect(target) => {
+                let result = match self.peers.get(&target) {
+                    None | Some(MockPeer::Unreachable) => Err(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionRefused,
+                        "mock peer unreachable",
+                    )),
+                    Some(_) => {
+                        let id = MockPeerId(self.next_id.get());
+                        self.next_id.set(id.0 + 1);
+                        self.connected.borrow_mut().insert(id, target);
+                        Ok(id)
+                    }
+                };
+                let _ = self.events_tx.send(Event::ConnectedTo { target, result });
+            }
+
+            Command::Disconnect(peer) => {
+                let event = match self.connected.borrow_mut().remove(&peer) {
+                    Some(_) => Event::Disconnected {
+                        peer,
+                        reason: DisconnectReason::Requested,
+                    },
+                    None => Event::NoPeer(peer),
+                };
+                let _ = self.events_tx.send(event);
+            }
+
+            Command::Version(peer) => {
+                if self.behavior(peer) != Some(&MockPeer::Mute) {
+                    self.emit(peer, NetworkMessage::Version(version()));
+                    self.emit(peer, NetworkMessage::Verack);
+                }
+            }
+
+            Command::Tx(peer, tx) => {
+                let tx: bitcoin::Transaction = match bitcoin::consensus::deserialize(tx.payload()) {
+                    Ok(tx) => tx,
+                    Err(_) => return,
+                };
+                let txid = tx.txid();
+
+                match self.behavior(peer).cloned() {
+                    Some(MockPeer::Honest) => {
+                        self.mempool.borrow_mut().insert(txid, tx);
+                        let relays: Vec<_> = self
+                            .connected
+                            .borrow()
+                            .iter()
+                            .filter(|(id, service)| {
+                                **id != peer && self.peers.get(service) == Some(&MockPeer::Honest)
+                            })
+                            .map(|(id, _)| *id)
+                            .collect();
+                        for id in relays {
+                            self.emit(id, NetworkMessage::Inv(vec![Inventory::Transaction(txid)]));
+                        }
+                    }
+                    Some(MockPeer::Rejecting(reason)) => self.emit(
+                        peer,
+                        NetworkMessage::Reject(Reject {
+                            message: CommandString::try_from_static("tx").expect("valid command"),
+                            ccode: RejectReason::Invalid,
+                            reason: reason.into(),
+                            hash: txid.to_raw_hash(),
+                        }),
+                    ),
+                    Some(MockPeer::Dropping) => {
+                        self.connected.borrow_mut().remove(&peer);
+                        let _ = self.events_tx.send(Event::Disconnected {
+                            peer,
+                            reason: DisconnectReason::Left,
+                        });
+                    }
+                    _ => {}
+                }
+            }
+
+            Command::Inv(peer, inv) => {
+                if self.behavior(peer) == Some(&MockPeer::Honest) {
+                    let wanted: Vec<_> = inv
+                        .into_iter()
+                        .filter(|inv| matches!(inv, Inventory::Transaction(_)))
+                        .collect();
+                    if !wanted.is_empty() {
+                        self.emit(peer, NetworkMessage::GetData(wanted));
+                    }
+                }
+            }
+
+            Command::GetData(peer, inv) => {
+                if self.behavior(peer).is_none() {
+                    return;
+                }
+                let mempool = self.mempool.borrow();
+                let mut not_found = Vec::new();
+                for inv in inv {
+                    let tx = match inv {
+                        Inventory::Transaction(txid) => mempool.get(&txid),
+                        _ => None,
+                    };
+                    match tx {
+                        Some(tx) => self.emit(peer, NetworkMessage::Tx(tx.clone())),
+                        None => not_found.push(inv),
+                    }
+                }
+                if !not_found.is_empty() {
+                    self.emit(peer, NetworkMessage::NotFound(not_found));
+                }
+            }
+        }
+    }
+}
+
+/// The version message that every mock peer sends.
+fn version() -> VersionMessage {
+    let address = Address {
+        services: ServiceFlags::NONE,
+        address: [0; 8],
+        port: 0,
+    };
+
+    VersionMessage {
+        version: 70016,
+        services: ServiceFlags::NETWORK | ServiceFlags::WITNESS,
+        timestamp: 0,
+        receiver: address.clone(),
+        sender: address,
+        nonce: fastrand::u64(..),
+        user_agent: "/mock:0.1.0/".to_string(),
+        start_height: 0,
+        relay: true,
+    }
+}
+
+impl p2p::Outbox<MockPeerId> for MockClient {
+    fn connect(&self, target: Service) {
+        self.commands.borrow_mut().push(Command::Connect(target));
+    }
+
+    fn disconnect(&self, peer: MockPeerId) {
+        self.commands.borrow_mut().push(Command::Disconnect(peer));
+    }
+
+    fn version(&self, peer: MockPeerId, _target: Service) {
+        self.commands.borrow_mut().push(Command::Version(peer));
+    }
+
+    fn verack(&self, _peer: MockPeerId) {}
+
+    fn sendaddrv2(&self, _peer: MockPeerId) {}
+
+    fn wtxidrelay(&self, _peer: MockPeerId) {}
+
+    fn tx(&self, peer: MockPeerId, tx: SharedTx) {
+        self.commands.borrow_mut().push(Command::Tx(peer, tx));
+    }
+
+    fn inv(&self, peer: MockPeerId, inv: Vec<Inventory>) {
+        self.commands.borrow_mut().push(Command::Inv(peer, inv));
+    }
+
+    fn getdata(&self, peer: MockPeerId, inv: Vec<Inventory>) {
+        self.commands.borrow_mut().push(Command::GetData(peer, inv));
+    }
+
+    fn addr(&self, _peer: MockPeerId, _addrs: Vec<(u32, bitcoin::p2p::Address)>) {}
+
+    fn addrv2(&self, _peer: MockPeerId, _addrs: Vec<bitcoin::p2p::address::AddrV2Message>) {}
+
+    #[cfg(feature = "raw-messages")]
+    fn raw(&self, _peer: MockPeerId, _message: NetworkMessage) {}
+}
+
+impl p2p::Receiver<MockPeerId, Event<MockPeerId>> for MockClient {
+    fn receiver(&self) -> &crossbeam_channel::Receiver<Event<MockPeerId>> {
+        &self.events_rx
+    }
+}
+
+impl p2p::Sender for MockClient {
+    fn send(&self) -> std::io::Result<()> {
+        let commands: Vec<_> = self.commands.borrow_mut().drain(..).collect();
+        commands.into_iter().for_each(|cmd| self.process(cmd));
+        Ok(())
+    }
+
+    fn shutdown(self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+    use crate::{Error, Report, Txid};
+
+    /// A transaction that spends a made-up output into a standard P2WPKH output.
+    fn tx() -> Transaction {
+        let wpkh = bitcoin::WPubkeyHash::all_zeros();
+        Transaction(bitcoin::Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn {
+                previous_output: bitcoin::OutPoint::new(bitcoin::Txid::all_zeros(), 0),
+                ..Default::default()
+            }],
+            output: vec![bitcoin::TxOut {
+                value: bitcoin::Amount::from_sat(10_000),
+                script_pubkey: bitcoin::ScriptBuf::new_v0_p2wpkh(&wpkh),
+            }],
+        })
+    }
+
+    fn network(peers: impl IntoIterator<Item = MockPeer>) -> MockNetwork {
+        MockNetwork::new(peers.into_iter().enumerate().map(|(n, peer)| {
+            let service = format!("10.{}.0.1:8333", n + 1).parse().unwrap();
+            (service, peer)
+        }))
+    }
+
+    fn outcome(info_rx: crossbeam_channel::Receiver<Info>) -> Result<Report, Error> {
+        info_rx
+            .iter()
+            .find_map(|info| match info {
+                Info::Done(result) => Some(result),
+                _ => None,
+            })
+            .expect("the broadcast always ends with Done")
+    }
+
+    #[test]
+    fn honest_network_acks_the_broadcast() {
+        let tx = tx();
+        let txid = Txid(tx.0.txid());
+        let opts = Opts {
+            broadcast_peers: 1,
+            monitor_peers: 2,
+            ..Default::default()
+        };
+        let network = network([MockPeer::Honest, MockPeer::Honest, MockPeer::Honest]);
+
+        let report = outcome(broadcast(vec![tx], opts, network)).unwrap();
+        assert_eq!(report.success, HashSet::from([txid]));
+        assert!(report.policy_met);
+        assert!(report.rejects.is_empty());
+        assert!(report.unsent.is_empty());
+    }
+
+    #[test]
+    fn rejecting_network_fails_the_policy() {
+        let tx = tx();
+        let txid = Txid(tx.0.txid());
+        let opts = Opts {
+            broadcast_peers: 1,
+            monitor_peers: 1,
+            halt_on_reject: true,
+            ..Default::default()
+        };
+        let rejecting = MockPeer::Rejecting("bad-txns-inputs-missingorspent".to_string());
+        let network = network([rejecting.clone(), rejecting]);
+
+        let report = outcome(broadcast(vec![tx], opts, network)).unwrap();
+        assert!(report.success.is_empty());
+        assert!(!report.policy_met);
+        assert_eq!(
+            report.rejects[&txid].message,
+            "bad-txns-inputs-missingorspent"
+        );
+    }
+
+    #[test]
+    fn unreachable_network_fails_to_connect() {
+        let opts = Opts {
+            broadcast_peers: 1,
+            monitor_peers: 1,
+            replacement_interval: Duration::ZERO,
+            ..Default::default()
+        };
+        let network = network([MockPeer::Unreachable, MockPeer::Unreachable]);
+
+        // each address is given a second chance before it is given up on
+        match outcome(broadcast(vec![tx()], opts, network)) {
+            Err(Error::AllConnectionsFailed { attempts }) => assert_eq!(attempts, 4),
+            other => panic!("expected every connection to fail, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! Script validation of a batch against the outputs it spends, before anything goes out.
+
+use std::collections::HashMap;
+
+use bitcoin::{Amount, OutPoint, ScriptBuf, TxOut};
+
+use crate::{Error, Prevout, Transaction, Txid};
+
+/// Verifies the input scripts of every transaction in the batch. Outputs spent within the batch
+/// are taken from the batch itself, everything else must be among `prevouts`.
+pub fn validate(txs: &[Transaction], prevouts: &[Prevout]) -> Result<(), Error> {
+    let mut spent: HashMap<OutPoint, TxOut> = prevouts
+        .iter()
+        .map(|p| {
+            let outpoint = OutPoint::new(p.txid.into(), p.vout);
+            let output = TxOut {
+                value: Amount::from_sat(p.amount),
+                script_pubkey: ScriptBuf::from_bytes(p.script_pubkey.clone()),
+            };
+            (outpoint, output)
+        })
+        .collect();
+
+    for tx in txs {
+        let txid = tx.0.txid();
+        for (vout, output) in tx.0.output.iter().enumerate() {
+            spent.insert(OutPoint::new(txid, vout as u32), output.clone());
+        }
+    }
+
+    txs.iter().try_for_each(|tx| {
+        tx.0.verify(|outpoint| spent.get(outpoint).cloned())
+            .map_err(|err| Error::InvalidTransaction {
+                txid: Txid(tx.0.txid()),
+                reason: err.to_string(),
+            })
+    })
+}
@@ -0,0 +1,35 @@
+//! Best-effort wiping of transaction data from memory once a broadcast is over.
+
+use zeroize::Zeroize;
+
+/// Overwrites the scripts and amounts of a transaction with zeroes and clears its witnesses.
+///
+/// Witness data lives in a buffer owned by `rust-bitcoin` that cannot be reached from here, so it
+/// is only cleared rather than overwritten.
+pub fn transaction(mut tx: bitcoin::Transaction) {
+    for input in &mut tx.input {
+        std::mem::take(&mut input.script_sig).into_bytes().zeroize();
+        input.witness.clear();
+    }
+
+    for output in &mut tx.output {
+        std::mem::take(&mut output.script_pubkey)
+            .into_bytes()
+            .zeroize();
+        output.value = bitcoin::Amount::ZERO;
+    }
+}
+
+/// Overwrites a serialization buffer with zeroes.
+pub fn bytes(mut bytes: Vec<u8>) {
+    bytes.zeroize();
+}
+
+/// Overwrites a shared transaction serialization with zeroes, provided that no message still
+/// holds it. Logs a warning otherwise.
+pub fn shared_tx(mut tx: crate::p2p::SharedTx) {
+    match tx.payload_mut() {
+        Some(payload) => payload.zeroize(),
+        None => log::warn!("could not wipe a transaction that a message still holds"),
+    }
+}
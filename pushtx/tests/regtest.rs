@@ -0,0 +1,35 @@
+//! End-to-end tests against a regtest `bitcoind`. They are ignored by default since they need
+//! the binary, either in `BITCOIND_EXE` or on the `PATH`. Run them with:
+//!
+//! ```text
+//! BITCOIND_EXE=/path/to/bitcoind cargo test -p pushtx --features regtest -- --ignored
+//! ```
+
+#![cfg(feature = "regtest")]
+
+use std::time::Duration;
+
+use pushtx::regtest::Regtest;
+
+#[test]
+#[ignore = "needs a bitcoind binary"]
+fn broadcast_reaches_mempool() {
+    let node = Regtest::spawn().expect("no regtest node, set BITCOIND_EXE");
+
+    let tx = node.funded_tx().unwrap();
+    let txid = tx.txid();
+
+    // the node never announces a tx back to the peer it got it from, so the report cannot show
+    // it as seen and only the mempool check matters here
+    node.broadcast(
+        vec![tx],
+        pushtx::Opts {
+            max_time: Duration::from_secs(10),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    node.wait_for_mempool(txid, Duration::from_secs(10))
+        .unwrap();
+}